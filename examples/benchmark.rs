@@ -0,0 +1,71 @@
+//! Renders a fill heavy scene without a window and reports the frame rate.
+//!
+//! ```text
+//! cargo run --release --example benchmark [frames] [threads]
+//! ```
+
+use std::time::Instant;
+
+use ground_up_rasterizer::color::Color;
+use ground_up_rasterizer::geometry::{direction, quad, triangle};
+use ground_up_rasterizer::renderer::Renderer;
+use ground_up_rasterizer::world::{Camera, World};
+use nalgebra as na;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let frames: usize = args
+        .next()
+        .and_then(|frames| frames.parse().ok())
+        .unwrap_or(20);
+    let threads: Option<usize> = args.next().and_then(|threads| threads.parse().ok());
+    let (width, height) = (1500, 1000);
+    let world = scene(width as f32, height as f32);
+    let camera = Camera::new(0.0, 0.0, width as f32, height as f32, 0.0);
+    let mut renderer = Renderer::new(width, height);
+    if let Some(threads) = threads {
+        renderer.set_threads(threads);
+    }
+    // the first frame grows the buffers
+    renderer.render(&world, &camera, 0.0);
+    let start = Instant::now();
+    for frame in 0..frames {
+        renderer.render(&world, &camera, frame as f32 / 60.0);
+    }
+    let secs = start.elapsed().as_secs_f64();
+    println!(
+        "{frames} frames at {width}x{height} on {} threads: {:.2} ms per frame, {:.1} fps",
+        renderer.threads(),
+        secs * 1000.0 / frames as f64,
+        frames as f64 / secs
+    );
+}
+
+/// Layers of large squares and triangles covering most of the screen several
+/// times over, every third one translucent.
+fn scene(width: f32, height: f32) -> World {
+    let mut world = World::default();
+    let colors = [Color::Red, Color::Green, Color::Blue, Color::White];
+    for layer in 0..6 {
+        let offset = layer as f32 * 40.0;
+        let color = match layer % 3 {
+            2 => Color::Custom(0.2, 0.4, 0.8, 0.5),
+            _ => colors[layer % colors.len()],
+        };
+        let mut background = quad();
+        background.scale(na::Vector3::new(width * 0.4, height * 0.4, 1.0));
+        background.translate(direction(width / 2.0 + offset, height / 2.0, layer as f32));
+        background.set_color(color);
+        world.insert(background);
+        let mut front = triangle();
+        front.scale(na::Vector3::new(width * 0.3, height * 0.3, 1.0));
+        front.translate(direction(
+            width / 2.0 - offset,
+            height / 2.0,
+            10.0 - layer as f32,
+        ));
+        front.set_color(color);
+        world.insert(front);
+    }
+    world
+}
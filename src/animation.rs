@@ -0,0 +1,228 @@
+use crate::geometry::{direction, Geometry};
+use crate::math;
+
+/// Moves an object according to the time in seconds.
+///
+/// Every frame the renderer animates a fresh copy of each object in its
+/// resting state. Animators are called mutably and may keep state from one
+/// call to the next, such as a velocity they integrate, but they can be
+/// called more than once for the same time (once for an object and again
+/// for each of its children), so stepping animators should go by the time
+/// since their last call. Any values an animator captures are its own,
+/// which lets the same kind of animation run on several objects with
+/// different parameters. Closures taking `(&mut Geometry, f32)` are
+/// animators.
+pub trait Animator: Send {
+    fn animate(&mut self, geometry: &mut Geometry, secs: f32);
+
+    /// Runs `self` and then `other` at the same time.
+    fn and<A: Animator>(self, other: A) -> And<Self, A>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    /// Runs `self` for `duration` seconds, then holds its last pose while
+    /// `next` runs with its clock starting from zero.
+    fn then<A: Animator>(self, duration: f32, next: A) -> Then<Self, A>
+    where
+        Self: Sized,
+    {
+        Then {
+            first: self,
+            duration,
+            next,
+        }
+    }
+
+    /// Starts `self` after `delay` seconds, holding its pose at zero until
+    /// then.
+    fn delayed(self, delay: f32) -> Delayed<Self>
+    where
+        Self: Sized,
+    {
+        Delayed {
+            animator: self,
+            delay,
+        }
+    }
+
+    /// Runs `self` `factor` times as fast.
+    fn speed(self, factor: f32) -> Speed<Self>
+    where
+        Self: Sized,
+    {
+        Speed {
+            animator: self,
+            factor,
+        }
+    }
+}
+
+impl<F: FnMut(&mut Geometry, f32) + Send> Animator for F {
+    fn animate(&mut self, geometry: &mut Geometry, secs: f32) {
+        self(geometry, secs)
+    }
+}
+
+/// See `Animator::and`.
+pub struct And<A, B>(A, B);
+
+impl<A: Animator, B: Animator> Animator for And<A, B> {
+    fn animate(&mut self, geometry: &mut Geometry, secs: f32) {
+        self.0.animate(geometry, secs);
+        self.1.animate(geometry, secs);
+    }
+}
+
+/// See `Animator::then`.
+pub struct Then<A, B> {
+    first: A,
+    duration: f32,
+    next: B,
+}
+
+impl<A: Animator, B: Animator> Animator for Then<A, B> {
+    fn animate(&mut self, geometry: &mut Geometry, secs: f32) {
+        if secs < self.duration {
+            self.first.animate(geometry, secs);
+        } else {
+            self.first.animate(geometry, self.duration);
+            self.next.animate(geometry, secs - self.duration);
+        }
+    }
+}
+
+/// See `Animator::delayed`.
+pub struct Delayed<A> {
+    animator: A,
+    delay: f32,
+}
+
+impl<A: Animator> Animator for Delayed<A> {
+    fn animate(&mut self, geometry: &mut Geometry, secs: f32) {
+        self.animator
+            .animate(geometry, (secs - self.delay).max(0.0));
+    }
+}
+
+/// See `Animator::speed`.
+pub struct Speed<A> {
+    animator: A,
+    factor: f32,
+}
+
+impl<A: Animator> Animator for Speed<A> {
+    fn animate(&mut self, geometry: &mut Geometry, secs: f32) {
+        self.animator.animate(geometry, secs * self.factor);
+    }
+}
+
+/// Turns the object around its local x, y and z axes at the given rates in
+/// radians per second, on top of its resting rotation.
+pub fn spin(x_rate: f32, y_rate: f32, z_rate: f32) -> impl Animator {
+    move |geometry: &mut Geometry, secs: f32| {
+        let spin = math::x_rotation_matrix(x_rate * secs)
+            * math::y_rotation_matrix(y_rate * secs)
+            * math::z_rotation_matrix(z_rate * secs);
+        geometry.set_rotation_matrix(geometry.rotation_matrix() * spin);
+    }
+}
+
+/// Circles the object's resting position in the xy plane at `radius`,
+/// `angular_speed` radians per second, starting on the +x side.
+pub fn orbit(radius: f32, angular_speed: f32) -> impl Animator {
+    move |geometry: &mut Geometry, secs: f32| {
+        let angle = angular_speed * secs;
+        geometry.translate(direction(radius * angle.cos(), radius * angle.sin(), 0.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{point, triangle, Point};
+    use crate::math::f32_equals;
+    use std::f32::consts::PI;
+
+    fn position(geometry: &Geometry, secs: f32) -> Point {
+        geometry.animated_model_matrix(secs) * point(0.0, 0.0, 0.0)
+    }
+
+    fn assert_point_eq(left: Point, right: Point) {
+        for i in 0..4 {
+            assert!(f32_equals(left[i], right[i]), "{left:?} != {right:?}");
+        }
+    }
+
+    #[test]
+    fn test_parameters() {
+        let mut near = triangle();
+        near.set_animation(orbit(10.0, PI));
+        let mut far = triangle();
+        far.translate(direction(100.0, 0.0, 0.0));
+        far.set_animation(orbit(50.0, PI));
+        assert_point_eq(position(&near, 0.5), point(0.0, 10.0, 0.0));
+        assert_point_eq(position(&far, 1.0), point(50.0, 0.0, 0.0));
+        // animating a copy leaves the original at rest
+        assert_point_eq(
+            near.model_matrix() * point(0.0, 0.0, 0.0),
+            point(0.0, 0.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn test_combinators() {
+        let step = |x: f32| {
+            move |geometry: &mut Geometry, secs: f32| {
+                geometry.translate(direction(x * secs, 0.0, 0.0));
+            }
+        };
+        let mut geometry = triangle();
+        let lift = |geometry: &mut Geometry, secs: f32| {
+            geometry.translate(direction(0.0, secs, 0.0));
+        };
+        geometry.set_animation(step(1.0).and(lift));
+        assert_point_eq(position(&geometry, 2.0), point(2.0, 2.0, 0.0));
+
+        geometry.set_animation(step(1.0).then(2.0, step(-10.0)));
+        assert_point_eq(position(&geometry, 1.0), point(1.0, 0.0, 0.0));
+        assert_point_eq(position(&geometry, 3.0), point(-8.0, 0.0, 0.0));
+
+        geometry.set_animation(step(1.0).delayed(1.0).speed(2.0));
+        assert_point_eq(position(&geometry, 0.25), point(0.0, 0.0, 0.0));
+        assert_point_eq(position(&geometry, 2.0), point(3.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_stateful_animator() {
+        let mut geometry = triangle();
+        // integrates a velocity that grows every call, going by the time
+        // since the last one so repeated calls for a frame change nothing
+        let (mut last, mut speed, mut x) = (0.0, 1.0, 0.0);
+        geometry.set_animation(move |geometry: &mut Geometry, secs: f32| {
+            x += speed * (secs - last);
+            if secs > last {
+                speed *= 2.0;
+            }
+            last = secs;
+            geometry.translate(direction(x, 0.0, 0.0));
+        });
+        assert_point_eq(position(&geometry, 1.0), point(1.0, 0.0, 0.0));
+        assert_point_eq(position(&geometry, 1.0), point(1.0, 0.0, 0.0));
+        // copies share the animator, and with it the state
+        let copy = geometry.clone();
+        assert_point_eq(position(&copy, 2.0), point(3.0, 0.0, 0.0));
+        assert_point_eq(position(&geometry, 3.0), point(7.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_spin_keeps_rest_rotation() {
+        let mut geometry = triangle();
+        geometry.rotation(0.0, 0.0, PI / 2.0);
+        geometry.set_animation(spin(0.0, 0.0, PI / 2.0));
+        let turned = geometry.animated_model_matrix(1.0) * point(1.0, 0.0, 0.0);
+        assert_point_eq(turned, point(-1.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::shader::MAX_VARYINGS;
+
+/// Names an extra per-vertex value carried by a `Geometry`. `Color(0)` always
+/// refers to `Vertex::color`; further colors start at `Color(1)`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Attribute {
+    Normal,
+    Tangent,
+    TexCoord(usize),
+    Color(usize),
+    Custom(String),
+}
+
+/// One value of `components` floats for every entry of `Geometry::vertices`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeStream {
+    components: usize,
+    data: Vec<f32>,
+}
+
+impl AttributeStream {
+    pub fn new(components: usize) -> Self {
+        assert!(components > 0, "attributes need at least one component");
+        Self {
+            components,
+            data: vec![],
+        }
+    }
+
+    /// `data` holds the values back to back and must be a multiple of
+    /// `components` long.
+    pub fn from_vec(components: usize, data: Vec<f32>) -> Self {
+        assert!(components > 0, "attributes need at least one component");
+        assert!(
+            data.len().is_multiple_of(components),
+            "attribute data does not divide into {components} components"
+        );
+        Self { components, data }
+    }
+
+    pub fn push(&mut self, value: &[f32]) {
+        assert_eq!(value.len(), self.components);
+        self.data.extend_from_slice(value);
+    }
+
+    pub fn components(&self) -> usize {
+        self.components
+    }
+
+    /// Number of values, not floats.
+    pub fn len(&self) -> usize {
+        self.data.len() / self.components
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> &[f32] {
+        &self.data[index * self.components..(index + 1) * self.components]
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> &mut [f32] {
+        &mut self.data[index * self.components..(index + 1) * self.components]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[f32]> {
+        self.data.chunks_exact(self.components)
+    }
+}
+
+/// The attribute streams of a geometry, kept in insertion order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Attributes {
+    streams: Vec<(Attribute, AttributeStream)>,
+}
+
+impl Attributes {
+    /// Adds a stream or replaces the one with the same name.
+    pub fn insert(&mut self, attribute: Attribute, stream: AttributeStream) {
+        assert_ne!(
+            attribute,
+            Attribute::Color(0),
+            "Color(0) is Vertex::color; extra colors start at Color(1)"
+        );
+        match self.streams.iter_mut().find(|(name, _)| *name == attribute) {
+            Some((_, existing)) => *existing = stream,
+            None => self.streams.push((attribute, stream)),
+        }
+    }
+
+    pub fn remove(&mut self, attribute: &Attribute) -> Option<AttributeStream> {
+        let index = self
+            .streams
+            .iter()
+            .position(|(name, _)| name == attribute)?;
+        Some(self.streams.remove(index).1)
+    }
+
+    pub fn get(&self, attribute: &Attribute) -> Option<&AttributeStream> {
+        self.streams
+            .iter()
+            .find(|(name, _)| name == attribute)
+            .map(|(_, stream)| stream)
+    }
+
+    pub fn get_mut(&mut self, attribute: &Attribute) -> Option<&mut AttributeStream> {
+        self.streams
+            .iter_mut()
+            .find(|(name, _)| name == attribute)
+            .map(|(_, stream)| stream)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Attribute, &AttributeStream)> {
+        self.streams.iter().map(|(name, stream)| (name, stream))
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+}
+
+/// Where each attribute ends up in the varyings written by
+/// `VertexInput::pass_through`: the vertex color first, then every stream in
+/// order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VaryingLayout {
+    entries: Vec<(Attribute, usize, usize)>,
+}
+
+impl VaryingLayout {
+    /// `None` when the attributes do not fit in `MAX_VARYINGS` floats.
+    pub fn pass_through(attributes: &Attributes) -> Option<Self> {
+        let mut entries = vec![(Attribute::Color(0), 0, 4)];
+        let mut offset = 4;
+        for (name, stream) in attributes.iter() {
+            entries.push((name.clone(), offset, stream.components()));
+            offset += stream.components();
+        }
+        (offset <= MAX_VARYINGS).then_some(Self { entries })
+    }
+
+    /// Offset and component count of an attribute.
+    pub fn find(&self, attribute: &Attribute) -> Option<(usize, usize)> {
+        self.entries
+            .iter()
+            .find(|(name, _, _)| name == attribute)
+            .map(|(_, offset, components)| (*offset, *components))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream() {
+        let mut stream = AttributeStream::new(2);
+        stream.push(&[0.0, 1.0]);
+        stream.push(&[2.0, 3.0]);
+        assert_eq!(stream.len(), 2);
+        assert_eq!(stream.get(1), &[2.0, 3.0]);
+        stream.get_mut(0)[1] = 5.0;
+        assert_eq!(
+            stream.iter().collect::<Vec<_>>(),
+            vec![&[0.0, 5.0], &[2.0, 3.0]]
+        );
+    }
+
+    #[test]
+    fn test_attributes_and_layout() {
+        let mut attributes = Attributes::default();
+        attributes.insert(
+            Attribute::Normal,
+            AttributeStream::from_vec(3, vec![0.0; 6]),
+        );
+        attributes.insert(
+            Attribute::TexCoord(0),
+            AttributeStream::from_vec(2, vec![1.0; 4]),
+        );
+        // replacing keeps the original position
+        attributes.insert(
+            Attribute::Normal,
+            AttributeStream::from_vec(3, vec![1.0; 6]),
+        );
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(
+            attributes.get(&Attribute::Normal).unwrap().get(1),
+            &[1.0; 3]
+        );
+        let layout = VaryingLayout::pass_through(&attributes).unwrap();
+        assert_eq!(layout.find(&Attribute::Color(0)), Some((0, 4)));
+        assert_eq!(layout.find(&Attribute::Normal), Some((4, 3)));
+        assert_eq!(layout.find(&Attribute::TexCoord(0)), Some((7, 2)));
+        assert_eq!(layout.find(&Attribute::Tangent), None);
+        assert!(attributes.remove(&Attribute::Normal).is_some());
+        assert!(attributes.get(&Attribute::Normal).is_none());
+    }
+
+    #[test]
+    fn test_layout_too_large() {
+        let mut attributes = Attributes::default();
+        attributes.insert(
+            Attribute::Custom("big".to_string()),
+            AttributeStream::new(MAX_VARYINGS),
+        );
+        assert!(VaryingLayout::pass_through(&attributes).is_none());
+    }
+}
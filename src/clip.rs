@@ -0,0 +1,250 @@
+use crate::geometry::{GeometryType, Point, ShadedGeometry, ShadedVertex};
+
+/// The six frustum planes in homogeneous clip space. A point is inside a plane
+/// when its distance is non-negative; together they describe
+/// -w <= x <= w, -w <= y <= w and 0 <= z <= w.
+const PLANES: [fn(&Point) -> f32; 6] = [
+    |v| v.w + v.x,
+    |v| v.w - v.x,
+    |v| v.w + v.y,
+    |v| v.w - v.y,
+    |v| v.z,
+    |v| v.w - v.z,
+];
+
+impl ShadedVertex {
+    fn lerp(&self, other: &ShadedVertex, t: f32) -> ShadedVertex {
+        ShadedVertex {
+            position: self.position + (other.position - self.position) * t,
+            varyings: self.varyings.lerp(&other.varyings, t),
+        }
+    }
+}
+
+pub fn inside_frustum(v: &Point) -> bool {
+    PLANES.iter().all(|plane| plane(v) >= 0.0)
+}
+
+/// Clips a clip space object against the view frustum, interpolating the
+/// varyings of any new vertices. Triangles are clipped with Sutherland-Hodgman
+/// and re-triangulated as fans; line segments are clipped parametrically and
+/// points outside are dropped.
+/// Returns `None` when nothing is left.
+pub fn clip_geometry(mut geometry: ShadedGeometry) -> Option<ShadedGeometry> {
+    if geometry
+        .vertices
+        .iter()
+        .all(|vertex| inside_frustum(&vertex.position))
+    {
+        return Some(geometry);
+    }
+    geometry.vertices = match geometry.geo_type {
+        GeometryType::Triangle => clip_triangles(&geometry.vertices),
+        GeometryType::Line | GeometryType::Lines => clip_lines(&geometry.vertices),
+        GeometryType::Point => geometry
+            .vertices
+            .into_iter()
+            .filter(|vertex| inside_frustum(&vertex.position))
+            .collect(),
+    };
+    if geometry.vertices.is_empty() {
+        None
+    } else {
+        Some(geometry)
+    }
+}
+
+fn clip_triangles(vertices: &[ShadedVertex]) -> Vec<ShadedVertex> {
+    let mut out = vec![];
+    let mut polygon = vec![];
+    let mut scratch = vec![];
+    for triangle in vertices.chunks_exact(3) {
+        if triangle
+            .iter()
+            .all(|vertex| inside_frustum(&vertex.position))
+        {
+            out.extend_from_slice(triangle);
+            continue;
+        }
+        polygon.clear();
+        polygon.extend_from_slice(triangle);
+        for plane in PLANES {
+            clip_polygon(&polygon, plane, &mut scratch);
+            std::mem::swap(&mut polygon, &mut scratch);
+            if polygon.is_empty() {
+                break;
+            }
+        }
+        if polygon.len() < 3 {
+            continue;
+        }
+        for i in 1..polygon.len() - 1 {
+            out.push(polygon[0].clone());
+            out.push(polygon[i].clone());
+            out.push(polygon[i + 1].clone());
+        }
+    }
+    out
+}
+
+/// One Sutherland-Hodgman pass against a single plane.
+fn clip_polygon(input: &[ShadedVertex], plane: fn(&Point) -> f32, output: &mut Vec<ShadedVertex>) {
+    output.clear();
+    let len = input.len();
+    for i in 0..len {
+        let current = &input[i];
+        let next = &input[(i + 1) % len];
+        let d_current = plane(&current.position);
+        let d_next = plane(&next.position);
+        if d_current >= 0.0 {
+            output.push(current.clone());
+        }
+        if (d_current >= 0.0) != (d_next >= 0.0) {
+            let t = d_current / (d_current - d_next);
+            output.push(current.lerp(next, t));
+        }
+    }
+}
+
+fn clip_lines(vertices: &[ShadedVertex]) -> Vec<ShadedVertex> {
+    let mut out = vec![];
+    for segment in vertices.chunks_exact(2) {
+        let (start, end) = (&segment[0], &segment[1]);
+        if let Some((t_start, t_end)) = clip_segment(&start.position, &end.position) {
+            out.push(start.lerp(end, t_start));
+            out.push(start.lerp(end, t_end));
+        }
+    }
+    out
+}
+
+/// Parametric (Liang-Barsky style) clipping of the segment from `start` to
+/// `end`, returning the visible range of t, if any.
+fn clip_segment(start: &Point, end: &Point) -> Option<(f32, f32)> {
+    let mut t_start: f32 = 0.0;
+    let mut t_end: f32 = 1.0;
+    for plane in PLANES {
+        let d_start = plane(start);
+        let d_end = plane(end);
+        if d_start < 0.0 && d_end < 0.0 {
+            return None;
+        }
+        if d_start < 0.0 {
+            t_start = t_start.max(d_start / (d_start - d_end));
+        } else if d_end < 0.0 {
+            t_end = t_end.min(d_start / (d_start - d_end));
+        }
+    }
+    if t_start <= t_end {
+        Some((t_start, t_end))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::geometry::{line, point, triangle, Geometry, Vertex};
+    use crate::math::f32_equals;
+    use crate::shader::Uniforms;
+
+    fn homogeneous(x: f32, y: f32, z: f32, w: f32) -> Point {
+        Point::new(x, y, z, w)
+    }
+
+    /// The default shader with identity matrices passes positions through, so
+    /// these are already in clip space.
+    fn shaded(geometry: &Geometry) -> ShadedGeometry {
+        geometry.shade(0.0, Uniforms::default()).unwrap()
+    }
+
+    #[test]
+    fn test_inside_untouched() {
+        let mut tri = triangle();
+        tri.transform(nalgebra::matrix![0.5, 0.0, 0.0, 0.0;
+                                        0.0, 0.5, 0.0, 0.0;
+                                        0.0, 0.0, 1.0, 0.5;
+                                        0.0, 0.0, 0.0, 1.0]);
+        let clipped = clip_geometry(shaded(&tri)).unwrap();
+        let positions: Vec<Point> = clipped.vertices.iter().map(|v| v.position).collect();
+        assert_eq!(positions, tri.vertex_locations);
+    }
+
+    #[test]
+    fn test_triangle_clipped_by_right_plane() {
+        let mut tri = triangle();
+        tri.vertex_locations = vec![
+            homogeneous(0.0, -0.5, 0.5, 1.0),
+            homogeneous(0.0, 0.5, 0.5, 1.0),
+            homogeneous(2.0, 0.0, 0.5, 1.0),
+        ];
+        tri.set_color(Color::Custom(0.0, 0.0, 0.0, 1.0));
+        tri.vertices[2].color = Color::Custom(1.0, 0.0, 0.0, 1.0);
+        let clipped = clip_geometry(shaded(&tri)).unwrap();
+        // the tip is cut off, leaving a quad split into two triangles
+        assert_eq!(clipped.vertices.len(), 6);
+        for vertex in &clipped.vertices {
+            assert!(inside_frustum(&vertex.position));
+            // color is interpolated along with position
+            let red = vertex.varyings.rgba(0).r.0;
+            assert!(f32_equals(red, vertex.position.x / 2.0));
+        }
+    }
+
+    #[test]
+    fn test_triangle_behind_near_plane() {
+        let mut tri = triangle();
+        tri.vertex_locations = vec![
+            homogeneous(0.0, 0.0, -1.0, 1.0),
+            homogeneous(0.5, 0.0, -1.0, 1.0),
+            homogeneous(0.0, 0.5, -1.0, 1.0),
+        ];
+        assert!(clip_geometry(shaded(&tri)).is_none());
+    }
+
+    #[test]
+    fn test_triangle_crossing_camera_plane() {
+        let mut tri = triangle();
+        // one vertex behind the camera (negative w)
+        tri.vertex_locations = vec![
+            homogeneous(0.0, 0.0, 0.5, 1.0),
+            homogeneous(0.5, 0.0, 0.5, 1.0),
+            homogeneous(0.0, 0.0, 0.5, -1.0),
+        ];
+        let clipped = clip_geometry(shaded(&tri)).unwrap();
+        for vertex in &clipped.vertices {
+            assert!(vertex.position.w > 0.0);
+            assert!(inside_frustum(&vertex.position));
+        }
+    }
+
+    #[test]
+    fn test_line_clipping() {
+        let mut l = line();
+        l.vertex_locations = vec![point(-2.0, 0.0, 0.5), point(2.0, 0.0, 0.5)];
+        let clipped = clip_geometry(shaded(&l)).unwrap();
+        let positions: Vec<f32> = clipped.vertices.iter().map(|v| v.position.x).collect();
+        assert_eq!(positions, vec![-1.0, 1.0]);
+        // red to blue, so the start of the visible part is mostly red
+        let start = clipped.vertices[0].varyings.rgba(0);
+        assert!(f32_equals(start.r.0, 0.75) && f32_equals(start.b.0, 0.25));
+    }
+
+    #[test]
+    fn test_line_strip_segments() {
+        let mut l = line();
+        l.vertex_locations = vec![
+            point(-0.5, 0.0, 0.5),
+            point(0.0, 3.0, 0.5),
+            point(0.5, 0.0, 0.5),
+        ];
+        l.vertices.push(Vertex::new(2, Color::Green));
+        let clipped = clip_geometry(shaded(&l)).unwrap();
+        // both segments leave through the bottom and are shortened separately
+        assert_eq!(clipped.vertices.len(), 4);
+        assert!(f32_equals(clipped.vertices[1].position.y, 1.0));
+        assert!(f32_equals(clipped.vertices[2].position.y, 1.0));
+    }
+}
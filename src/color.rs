@@ -25,6 +25,12 @@ impl From<&Color> for Rgba {
     }
 }
 
+impl From<&Rgba> for Color {
+    fn from(rgba: &Rgba) -> Self {
+        Color::Custom(rgba.r.0, rgba.g.0, rgba.b.0, rgba.a.0)
+    }
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
 pub struct Rgba {
     pub r: OrdFloat,
@@ -64,6 +70,23 @@ impl Rgba {
         over.b *= over.a;
         *self += over;
     }
+
+    /// sRGB encoded color with linear, clamped alpha, for image files.
+    pub fn to_srgba8(&self) -> [u8; 4] {
+        let rgb = convert::f32x4_to_srgb8([self.r.0, self.g.0, self.b.0, 0.0]);
+        let a = (self.a.0.clamp(0.0, 1.0) * 255.0).round() as u8;
+        [rgb[0], rgb[1], rgb[2], a]
+    }
+
+    /// Inverse of `to_srgba8`.
+    pub fn from_srgba8(srgba: [u8; 4]) -> Self {
+        Rgba::color_a(
+            convert::srgb8_to_f32(srgba[0]),
+            convert::srgb8_to_f32(srgba[1]),
+            convert::srgb8_to_f32(srgba[2]),
+            srgba[3] as f32 / 255.0,
+        )
+    }
 }
 
 impl From<&Rgba> for u32 {
@@ -119,6 +142,19 @@ impl Mul<f32> for &Rgba {
     }
 }
 
+/// Componentwise, for tinting one color by another.
+impl Mul for &Rgba {
+    type Output = Rgba;
+    fn mul(self, rhs: Self) -> Self::Output {
+        Rgba::color_a(
+            self.r.0 * rhs.r.0,
+            self.g.0 * rhs.g.0,
+            self.b.0 * rhs.b.0,
+            self.a.0 * rhs.a.0,
+        )
+    }
+}
+
 impl Mul<&Rgba> for f32 {
     type Output = Rgba;
     fn mul(self, rhs: &Rgba) -> Self::Output {
@@ -129,7 +165,6 @@ impl Mul<&Rgba> for f32 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{u16, u32, u8};
 
     #[test]
     fn test_into_u32() {
@@ -146,6 +181,19 @@ mod tests {
         assert_eq!(green_rgba, green_u32);
     }
 
+    #[test]
+    fn test_to_srgba8() {
+        assert_eq!(Rgba::color(1.0, 0.0, 0.0).to_srgba8(), [255, 0, 0, 255]);
+        assert_eq!(
+            Rgba::color_a(0.0, 0.0, 1.0, 1.5).to_srgba8(),
+            [0, 0, 255, 255]
+        );
+        assert_eq!(
+            Rgba::color_a(0.0, 0.0, 0.0, 0.5).to_srgba8(),
+            [0, 0, 0, 128]
+        );
+    }
+
     #[test]
     fn test_color_eq() {
         assert_eq!(Rgba::from(&Color::Blue), (&Color::Blue).into());
@@ -0,0 +1,432 @@
+//! Triangle coverage from edge functions, computed for blocks of 8x8 pixels
+//! at a time. Vertices are snapped to 28.4 fixed point and pixels are
+//! sampled at their centers, so every edge function value is an exact
+//! integer: the SSE2 and AVX2 kernels give the same coverage as the scalar
+//! one, bit for bit. Samples exactly on an edge follow the top-left rule,
+//! which makes triangles sharing an edge cover each pixel along it once.
+
+/// Width and height of the blocks coverage is computed for.
+pub const BLOCK_SIZE: usize = 8;
+
+/// Fractional bits of snapped vertex positions.
+pub const SUBPIXEL_BITS: u32 = 4;
+const SUBPIXELS: i64 = 1 << SUBPIXEL_BITS;
+
+/// Snaps a screen space coordinate to 28.4 fixed point.
+pub fn to_fixed(coordinate: f32) -> i64 {
+    (coordinate * SUBPIXELS as f32).round() as i64
+}
+
+/// Center of pixel `pixel` in 28.4 fixed point.
+fn sample(pixel: i32) -> i64 {
+    pixel as i64 * SUBPIXELS + SUBPIXELS / 2
+}
+
+/// `a * x + b * y + c` in fixed point: zero on the edge, positive on the
+/// triangle's side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Edge {
+    a: i64,
+    b: i64,
+    c: i64,
+    /// -1 for edges that are neither top nor left edges, so samples exactly
+    /// on them are left to the neighbouring triangle.
+    bias: i64,
+}
+
+impl Edge {
+    fn through(from: [i64; 2], to: [i64; 2]) -> Self {
+        Self {
+            a: from[1] - to[1],
+            b: to[0] - from[0],
+            c: from[0] * to[1] - to[0] * from[1],
+            bias: 0,
+        }
+    }
+
+    fn flipped(self) -> Self {
+        Self {
+            a: -self.a,
+            b: -self.b,
+            c: -self.c,
+            bias: 0,
+        }
+    }
+
+    /// The triangle is to the right of a left edge and, with y pointing down,
+    /// below a horizontal top edge.
+    fn is_top_left(&self) -> bool {
+        self.a > 0 || (self.a == 0 && self.b > 0)
+    }
+
+    /// Value at `offset` from the center of pixel (x, y).
+    fn at(&self, x: i32, y: i32, offset: [i64; 2]) -> i64 {
+        self.a * (sample(x) + offset[0]) + self.b * (sample(y) + offset[1]) + self.c
+    }
+
+    /// Smallest and largest biased value over the samples at `offset` in the
+    /// block with its top left at (x, y).
+    fn block_range(&self, x: i32, y: i32, offset: [i64; 2]) -> (i64, i64) {
+        let span = (BLOCK_SIZE as i64 - 1) * SUBPIXELS;
+        let corner = self.at(x, y, offset) + self.bias;
+        let (a, b) = (self.a * span, self.b * span);
+        (corner + a.min(0) + b.min(0), corner + a.max(0) + b.max(0))
+    }
+
+    /// Biased values along the block row starting at (x, y), and how much
+    /// they change from one row to the next. Only for blocks the edge
+    /// crosses, where the values are small enough for 32 bits.
+    fn row(&self, x: i32, y: i32, offset: [i64; 2]) -> ([i32; BLOCK_SIZE], i32) {
+        let start = self.at(x, y, offset) + self.bias;
+        let row = std::array::from_fn(|column| (start + self.a * SUBPIXELS * column as i64) as i32);
+        (row, (self.b * SUBPIXELS) as i32)
+    }
+}
+
+/// How a block's coverage gets computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Sse2,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+}
+
+impl Kernel {
+    /// The widest kernel the running CPU supports.
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Kernel::Avx2;
+            }
+            if is_x86_feature_detected!("sse2") {
+                return Kernel::Sse2;
+            }
+        }
+        Kernel::Scalar
+    }
+
+    /// Every kernel the running CPU supports.
+    #[cfg(test)]
+    pub fn available() -> Vec<Self> {
+        let mut kernels = vec![Kernel::Scalar];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("sse2") {
+                kernels.push(Kernel::Sse2);
+            }
+            if is_x86_feature_detected!("avx2") {
+                kernels.push(Kernel::Avx2);
+            }
+        }
+        kernels
+    }
+}
+
+/// The three edges of a triangle, oriented so that pixel centers inside all
+/// of them are covered regardless of winding.
+#[derive(Debug, Clone)]
+pub struct Edges {
+    /// Opposite the first, second and third vertex.
+    edges: [Edge; 3],
+    /// One over twice the triangle's area, the sum of the three edge values
+    /// anywhere, so weights take a multiply rather than a divide.
+    inverse_area: f32,
+    /// First and last column and row the triangle reaches into.
+    pixels: [i32; 4],
+    kernel: Kernel,
+}
+
+impl Edges {
+    /// Takes vertices in 28.4 fixed point. `None` for triangles without any
+    /// area, which cover nothing.
+    pub fn new([v0, v1, v2]: [[i64; 2]; 3], kernel: Kernel) -> Option<Self> {
+        let mut edges = [
+            Edge::through(v1, v2),
+            Edge::through(v2, v0),
+            Edge::through(v0, v1),
+        ];
+        let mut area = edges[0].a * v0[0] + edges[0].b * v0[1] + edges[0].c;
+        if area == 0 {
+            return None;
+        }
+        if area < 0 {
+            edges = edges.map(Edge::flipped);
+            area = -area;
+        }
+        for edge in &mut edges {
+            edge.bias = if edge.is_top_left() { 0 } else { -1 };
+        }
+        let first = |values: [i64; 3]| values.into_iter().min().unwrap().div_euclid(SUBPIXELS);
+        // samples lie strictly inside their pixel, never on its borders
+        let last = |values: [i64; 3]| (values.into_iter().max().unwrap() - 1).div_euclid(SUBPIXELS);
+        let (xs, ys) = ([v0[0], v1[0], v2[0]], [v0[1], v1[1], v2[1]]);
+        let pixels = [first(xs), first(ys), last(xs), last(ys)].map(|p| p as i32);
+        Some(Self {
+            edges,
+            inverse_area: (area as f32).recip(),
+            pixels,
+            kernel,
+        })
+    }
+
+    /// First column, first row, last column and last row, inclusive, of the
+    /// pixels any sample could be covered in.
+    pub fn pixel_bounds(&self) -> [i32; 4] {
+        self.pixels
+    }
+
+    /// Weights of the three vertices at `offset`, in 1/16 pixels, from the
+    /// center of pixel (x, y). They are extrapolated outside of the triangle.
+    pub fn barycentric(&self, x: i32, y: i32, offset: [i64; 2]) -> [f32; 3] {
+        self.edges
+            .map(|edge| edge.at(x, y, offset) as f32 * self.inverse_area)
+    }
+
+    /// Coverage of the block with its top left at (x, y): bit
+    /// `row * BLOCK_SIZE + column` is set for pixels whose sample at
+    /// `offset`, in 1/16 pixels from the center, is inside the triangle.
+    /// Blocks entirely outside or inside of an edge are settled from their
+    /// corners; only edges crossing the block are evaluated per pixel.
+    pub fn block_mask(&self, x: i32, y: i32, offset: [i64; 2]) -> u64 {
+        let mut rows = [[0; BLOCK_SIZE]; 3];
+        let mut steps = [0; 3];
+        let mut crossing = false;
+        for (i, edge) in self.edges.iter().enumerate() {
+            let (min, max) = edge.block_range(x, y, offset);
+            if max < 0 {
+                return 0;
+            }
+            if min < 0 {
+                (rows[i], steps[i]) = edge.row(x, y, offset);
+                crossing = true;
+            }
+        }
+        if !crossing {
+            return u64::MAX;
+        }
+        match self.kernel {
+            Kernel::Scalar => block_mask_scalar(rows, steps),
+            // SAFETY: the kernel is only ever picked when the CPU has it
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse2 => unsafe { block_mask_sse2(rows, steps) },
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => unsafe { block_mask_avx2(rows, steps) },
+        }
+    }
+}
+
+/// Sets the bits of pixels where none of the edge values are negative.
+fn block_mask_scalar(mut rows: [[i32; BLOCK_SIZE]; 3], steps: [i32; 3]) -> u64 {
+    let mut mask = 0;
+    for row in 0..BLOCK_SIZE {
+        for column in 0..BLOCK_SIZE {
+            if rows.iter().all(|values| values[column] >= 0) {
+                mask |= 1 << (row * BLOCK_SIZE + column);
+            }
+        }
+        for (values, step) in rows.iter_mut().zip(steps) {
+            for value in values {
+                *value += step;
+            }
+        }
+    }
+    mask
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn block_mask_sse2(rows: [[i32; BLOCK_SIZE]; 3], steps: [i32; 3]) -> u64 {
+    use std::arch::x86_64::*;
+    let mut left = [_mm_setzero_si128(); 3];
+    let mut right = [_mm_setzero_si128(); 3];
+    let mut step = [_mm_setzero_si128(); 3];
+    for i in 0..3 {
+        left[i] = _mm_loadu_si128(rows[i].as_ptr().cast());
+        right[i] = _mm_loadu_si128(rows[i][4..].as_ptr().cast());
+        step[i] = _mm_set1_epi32(steps[i]);
+    }
+    let mut mask = 0;
+    for row in 0..BLOCK_SIZE {
+        // a pixel is outside when any of its edge values has the sign set
+        let outside = |values: &[__m128i; 3]| {
+            let any = _mm_or_si128(_mm_or_si128(values[0], values[1]), values[2]);
+            _mm_movemask_ps(_mm_castsi128_ps(any)) as u64
+        };
+        let negative = outside(&left) | outside(&right) << 4;
+        mask |= (!negative & 0xff) << (row * BLOCK_SIZE);
+        for i in 0..3 {
+            left[i] = _mm_add_epi32(left[i], step[i]);
+            right[i] = _mm_add_epi32(right[i], step[i]);
+        }
+    }
+    mask
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn block_mask_avx2(rows: [[i32; BLOCK_SIZE]; 3], steps: [i32; 3]) -> u64 {
+    use std::arch::x86_64::*;
+    let mut values = [_mm256_setzero_si256(); 3];
+    let mut step = [_mm256_setzero_si256(); 3];
+    for i in 0..3 {
+        values[i] = _mm256_loadu_si256(rows[i].as_ptr().cast());
+        step[i] = _mm256_set1_epi32(steps[i]);
+    }
+    let mut mask = 0;
+    for row in 0..BLOCK_SIZE {
+        let any = _mm256_or_si256(_mm256_or_si256(values[0], values[1]), values[2]);
+        let negative = _mm256_movemask_ps(_mm256_castsi256_ps(any)) as u64;
+        mask |= (!negative & 0xff) << (row * BLOCK_SIZE);
+        for i in 0..3 {
+            values[i] = _mm256_add_epi32(values[i], step[i]);
+        }
+    }
+    mask
+}
+
+/// Bits of a block mask for the pixels within `columns` and `rows`, which are
+/// inclusive and relative to the block.
+pub fn clip_mask(columns: [i32; 2], rows: [i32; 2]) -> u64 {
+    let span = |[start, end]: [i32; 2]| {
+        let (start, end) = (start.max(0), end.min(BLOCK_SIZE as i32 - 1));
+        if start > end {
+            0
+        } else {
+            (0xffu64 >> (BLOCK_SIZE as i32 - 1 - end + start)) << start
+        }
+    };
+    let row_bits = span(columns);
+    let mut mask = 0;
+    for row in 0..BLOCK_SIZE as i32 {
+        if span(rows) >> row & 1 == 1 {
+            mask |= row_bits << (row * BLOCK_SIZE as i32);
+        }
+    }
+    mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Triangles of all sizes and windings in 28.4 fixed point, some much
+    /// larger than a block.
+    fn triangles() -> Vec<[[i64; 2]; 3]> {
+        let mut seed = 12345u32;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as i64 % (48 * SUBPIXELS) - 8 * SUBPIXELS
+        };
+        (0..200)
+            .map(|_| [[next(), next()], [next(), next()], [next(), next()]])
+            .collect()
+    }
+
+    const CENTER: [i64; 2] = [0, 0];
+
+    /// Whether the sample at `offset` in pixel (x, y) is inside, worked out
+    /// directly.
+    fn covers(edges: &Edges, x: i32, y: i32, offset: [i64; 2]) -> bool {
+        edges.edges.iter().all(|edge| {
+            let value = edge.at(x, y, offset);
+            value > 0 || (value == 0 && edge.is_top_left())
+        })
+    }
+
+    #[test]
+    fn test_kernels_agree() {
+        for vertices in triangles() {
+            let Some(scalar) = Edges::new(vertices, Kernel::Scalar) else {
+                continue;
+            };
+            for kernel in Kernel::available() {
+                let edges = Edges::new(vertices, kernel).unwrap();
+                for (x, y) in [(0, 0), (8, 0), (0, 8), (16, 24), (-8, 8)] {
+                    for offset in [CENTER, [7, -7], [-5, 5]] {
+                        assert_eq!(
+                            edges.block_mask(x, y, offset),
+                            scalar.block_mask(x, y, offset),
+                            "{kernel:?} {vertices:?} at ({x}, {y}) {offset:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_mask() {
+        for vertices in triangles() {
+            let Some(edges) = Edges::new(vertices, Kernel::Scalar) else {
+                continue;
+            };
+            for (x, y) in [(0, 0), (8, 8), (16, 24), (32, 32)] {
+                for offset in [CENTER, [-2, -6], [6, -2]] {
+                    let mask = edges.block_mask(x, y, offset);
+                    for bit in 0..64 {
+                        let (px, py) = (x + bit % 8, y + bit / 8);
+                        assert_eq!(mask >> bit & 1 == 1, covers(&edges, px, py, offset));
+                    }
+                }
+            }
+        }
+        // winding does not matter, and a sliver has no area
+        let clockwise = Edges::new([[0, 0], [0, 128], [128, 0]], Kernel::Scalar).unwrap();
+        let counter = Edges::new([[0, 0], [128, 0], [0, 128]], Kernel::Scalar).unwrap();
+        assert_eq!(
+            clockwise.block_mask(0, 0, CENTER),
+            counter.block_mask(0, 0, CENTER)
+        );
+        assert!(Edges::new([[0, 0], [64, 64], [128, 128]], Kernel::Scalar).is_none());
+    }
+
+    #[test]
+    fn test_large_triangles() {
+        // far beyond what fits in 32 bits when evaluated across the screen
+        let size = 8192 * SUBPIXELS;
+        let edges = Edges::new([[0, 0], [size, 0], [0, size]], Kernel::detect()).unwrap();
+        assert_eq!(edges.block_mask(4000, 4000, CENTER), u64::MAX);
+        assert_eq!(edges.block_mask(8000, 8000, CENTER), 0);
+        let diagonal = edges.block_mask(4088, 4096, CENTER);
+        for bit in 0..64 {
+            let (x, y) = (4088 + bit % 8, 4096 + bit / 8);
+            assert_eq!(diagonal >> bit & 1 == 1, covers(&edges, x, y, CENTER));
+        }
+        assert_eq!(edges.pixel_bounds(), [0, 0, 8191, 8191]);
+    }
+
+    #[test]
+    fn test_top_left_rule() {
+        // a square split along its diagonal covers every pixel once
+        let corners = [[16, 8], [88, 8], [88, 72], [16, 72]];
+        let halves = [
+            Edges::new([corners[0], corners[1], corners[2]], Kernel::Scalar).unwrap(),
+            Edges::new([corners[0], corners[2], corners[3]], Kernel::Scalar).unwrap(),
+        ];
+        for y in 0..8 {
+            for x in 0..8 {
+                let count = halves
+                    .iter()
+                    .filter(|half| covers(half, x, y, CENTER))
+                    .count();
+                // the top edge at 0.5 and the right edge at 5.5 run through
+                // pixel centers, and only the top one covers them
+                let inside = (1..=4).contains(&x) && (0..=3).contains(&y);
+                assert_eq!(count, inside as usize, "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_clip_mask() {
+        assert_eq!(clip_mask([0, 7], [0, 7]), u64::MAX);
+        assert_eq!(clip_mask([-3, 20], [-1, 9]), u64::MAX);
+        assert_eq!(clip_mask([2, 3], [0, 0]), 0b1100);
+        assert_eq!(clip_mask([0, 0], [1, 1]), 1 << 8);
+        assert_eq!(clip_mask([5, 4], [0, 7]), 0);
+        assert_eq!(clip_mask([0, 7], [8, 9]), 0);
+    }
+}
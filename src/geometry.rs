@@ -1,18 +1,47 @@
 use core::fmt;
 
+use crate::animation::Animator;
+use crate::attribute::{Attribute, AttributeStream, Attributes, VaryingLayout};
 use crate::color::Color;
-use crate::math;
+use crate::math::{self, Quaternion};
+use crate::shader::{ColorShader, FragmentShader, Uniforms, Varyings, VertexInput, VertexShader};
 use nalgebra as na;
+use std::sync::{Arc, Mutex, PoisonError};
 
 #[derive(Debug)]
 pub enum GeoError<'a> {
     NotDiv3(&'a Geometry),
+    /// The attribute stream does not have one value per vertex.
+    AttributeLength(&'a Geometry, Attribute),
+    /// The vertex color and attributes do not fit in `MAX_VARYINGS`.
+    TooManyVaryings(&'a Geometry),
+}
+
+impl fmt::Display for GeoError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeoError::NotDiv3(geo) => write!(
+                f,
+                "{geo:?}: the number of vertices of a triangle is not divisible by 3"
+            ),
+            GeoError::AttributeLength(geo, attribute) => write!(
+                f,
+                "{geo:?}: attribute {attribute:?} does not have one value per vertex"
+            ),
+            GeoError::TooManyVaryings(geo) => {
+                write!(f, "{geo:?}: too many attributes to interpolate")
+            }
+        }
+    }
 }
 
 pub type Point = na::Vector4<f32>;
 pub type Direction = na::Vector4<f32>;
 pub type Transform = na::Matrix4<f32>;
-pub type Animation = fn(&mut Geometry, secs: f32);
+/// Shared between the copies of an object, like the ones the renderer
+/// animates every frame, so that state an animator keeps carries over from
+/// one frame to the next. Locked while it runs.
+pub type Animation = Arc<Mutex<dyn Animator>>;
 
 // assumption: in local space, center = (0,0)
 #[derive(Clone)]
@@ -25,6 +54,10 @@ pub struct Geometry {
     scale: Transform,
     animation: Option<Animation>,
     name: Option<String>,
+    interpolation: Interpolation,
+    vertex_shader: Arc<dyn VertexShader>,
+    fragment_shader: Arc<dyn FragmentShader>,
+    attributes: Attributes,
 }
 
 #[derive(Debug, Clone)]
@@ -33,14 +66,51 @@ pub struct Vertex {
     pub color: Color,
 }
 
+/// How vertex attributes are blended across a primitive. `Perspective` is
+/// correct for 3D surfaces; `Linear` blends in screen space, like GLSL's
+/// `noperspective`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    #[default]
+    Perspective,
+    Linear,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GeometryType {
+    /// Independent triangles, three vertices each.
     Triangle,
+    /// A strip through every vertex in order.
     Line,
+    /// Independent segments, two vertices each.
+    Lines,
+    /// One pixel per vertex.
+    Point,
+}
+
+/// Output of the vertex shader. `position` starts out in clip space and holds
+/// screen coordinates after `ShadedGeometry::clip_to_screen`.
+#[derive(Debug, Clone)]
+pub struct ShadedVertex {
+    pub position: Point,
+    pub varyings: Varyings,
+}
+
+/// An object after vertex shading, ready to be clipped and rasterized.
+/// Triangles use three vertices each; lines are split into separate segments
+/// of two vertices each.
+#[derive(Clone)]
+pub struct ShadedGeometry {
+    pub geo_type: GeometryType,
+    pub vertices: Vec<ShadedVertex>,
+    pub interpolation: Interpolation,
+    pub fragment_shader: Arc<dyn FragmentShader>,
+    pub uniforms: Uniforms,
+    pub layout: Arc<VaryingLayout>,
 }
 
 impl Geometry {
-    fn new(geo_type: GeometryType) -> Self {
+    pub fn new(geo_type: GeometryType) -> Self {
         Self {
             vertices: vec![],
             vertex_locations: vec![],
@@ -50,26 +120,81 @@ impl Geometry {
             rotation: na::Matrix4::identity(),
             animation: None,
             name: None,
+            interpolation: Interpolation::default(),
+            vertex_shader: Arc::new(ColorShader),
+            fragment_shader: Arc::new(ColorShader),
+            attributes: Attributes::default(),
         }
     }
 
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     pub fn set_name(&mut self, name: Option<String>) {
         self.name = name;
     }
 
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation;
+    }
+
+    pub fn set_vertex_shader(&mut self, shader: Arc<dyn VertexShader>) {
+        self.vertex_shader = shader;
+    }
+
+    pub fn set_fragment_shader(&mut self, shader: Arc<dyn FragmentShader>) {
+        self.fragment_shader = shader;
+    }
+
+    pub fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    pub fn attribute(&self, attribute: &Attribute) -> Option<&AttributeStream> {
+        self.attributes.get(attribute)
+    }
+
+    pub fn attribute_mut(&mut self, attribute: &Attribute) -> Option<&mut AttributeStream> {
+        self.attributes.get_mut(attribute)
+    }
+
+    /// Adds or replaces a stream holding one value per entry of `vertices`.
+    pub fn set_attribute(&mut self, attribute: Attribute, stream: AttributeStream) {
+        self.attributes.insert(attribute, stream);
+    }
+
+    pub fn remove_attribute(&mut self, attribute: &Attribute) -> Option<AttributeStream> {
+        self.attributes.remove(attribute)
+    }
+
     pub fn transform(&mut self, matrix: Transform) {
         for vertex in &mut self.vertex_locations {
             *vertex = matrix * *vertex;
         }
     }
 
-    pub fn set_animation(&mut self, animation: Animation) {
-        self.animation = Some(animation);
+    pub fn set_animation(&mut self, animator: impl Animator + 'static) {
+        self.animation = Some(Arc::new(Mutex::new(animator)));
+    }
+
+    pub fn animation(&self) -> Option<&Animation> {
+        self.animation.as_ref()
+    }
+
+    pub fn remove_animation(&mut self) -> Option<Animation> {
+        self.animation.take()
     }
 
     pub fn animate(&mut self, time: f32) {
-        if let Some(animation) = self.animation {
-            animation(self, time);
+        if let Some(animation) = self.animation.clone() {
+            // a panic in an earlier frame leaves the animator as it was
+            let mut animator = animation.lock().unwrap_or_else(PoisonError::into_inner);
+            animator.animate(self, time);
         }
     }
 
@@ -81,44 +206,161 @@ impl Geometry {
         self.translation *= math::translation_matrix(dir);
     }
 
+    /// Sets the rotation from Euler angles: around x, then y, then z.
     pub fn rotation(&mut self, x_rotation: f32, y_rotation: f32, z_rotation: f32) {
-        let mut rotation = na::Matrix4::<f32>::identity();
-        let rotations = [
-            math::x_rotation_matrix(x_rotation),
-            math::y_rotation_matrix(y_rotation),
-            math::z_rotation_matrix(z_rotation),
-        ];
-        for r in rotations {
-            rotation *= r;
+        self.set_orientation(math::euler_quaternion(x_rotation, y_rotation, z_rotation));
+    }
+
+    pub fn set_orientation(&mut self, orientation: Quaternion) {
+        self.rotation = orientation.to_homogeneous();
+    }
+
+    /// The rotation as a quaternion, or `None` if it was set to a matrix
+    /// that mirrors or scales.
+    pub fn orientation(&self) -> Option<Quaternion> {
+        math::rotation_quaternion(&self.rotation)
+    }
+
+    /// Turns the object so that its local -z axis points at `target`, with
+    /// -y towards `up`. Leaves the rotation alone if `target` is the
+    /// object's position or lies straight along `up`.
+    pub fn look_at(&mut self, target: Point, up: Direction) {
+        let forward = target - self.translation.column(3);
+        if let Some(orientation) = math::look_at_quaternion(forward, up) {
+            self.set_orientation(orientation);
         }
-        self.rotation = rotation;
     }
 
     pub fn scale(&mut self, scale: na::Vector3<f32>) {
         self.scale = math::scale_matrix(scale);
     }
 
-    pub fn local_to_world(&self, time: f32, cam_rotation: Transform) -> Self {
+    /// Replaces the rotation with an arbitrary matrix, for rotations that
+    /// come from elsewhere (e.g. imported scenes) rather than Euler angles.
+    pub fn set_rotation_matrix(&mut self, rotation: Transform) {
+        self.rotation = rotation;
+    }
+
+    pub fn translation_matrix(&self) -> Transform {
+        self.translation
+    }
+
+    pub fn rotation_matrix(&self) -> Transform {
+        self.rotation
+    }
+
+    pub fn scale_matrix(&self) -> Transform {
+        self.scale
+    }
+
+    pub fn model_matrix(&self) -> Transform {
+        self.translation * self.rotation * self.scale
+    }
+
+    /// The model matrix after animating to `time`.
+    pub fn animated_model_matrix(&self, time: f32) -> Transform {
+        match self.animation {
+            Some(_) => {
+                let mut animated = self.clone();
+                animated.animate(time);
+                animated.model_matrix()
+            }
+            None => self.model_matrix(),
+        }
+    }
+
+    /// Animates a copy of the object and moves it out of local space with
+    /// `camera_transform * model`. Passing a view projection matrix gives
+    /// clip space coordinates, with the homogeneous w kept in each point.
+    ///
+    /// The copy has an identity model matrix and no animation, so it can be
+    /// drawn or written to a file as is. Normals are transformed along with
+    /// the positions.
+    pub fn local_to_world(&self, time: f32, camera_transform: Transform) -> Self {
         let mut copy = self.clone();
         copy.animate(time);
-        let transformation_matrix = cam_rotation * copy.translation * copy.rotation * copy.scale;
+        let transformation_matrix = camera_transform * copy.model_matrix();
         copy.transform(transformation_matrix);
+        if let Some(normals) = copy.attributes.get_mut(&Attribute::Normal) {
+            let normal_matrix = transformation_matrix
+                .fixed_view::<3, 3>(0, 0)
+                .try_inverse()
+                .unwrap_or_else(na::Matrix3::identity)
+                .transpose();
+            for i in 0..normals.len() {
+                let normal = normals.get_mut(i);
+                let transformed = (normal_matrix
+                    * na::Vector3::new(normal[0], normal[1], normal[2]))
+                .try_normalize(0.0)
+                .unwrap_or_default();
+                normal.copy_from_slice(transformed.as_slice());
+            }
+        }
+        copy.translation = Transform::identity();
+        copy.rotation = Transform::identity();
+        copy.scale = Transform::identity();
+        copy.animation = None;
         copy
     }
 
-    pub fn camera_to_screen(
-        &mut self,
-        camera_width: f32,
-        camera_height: f32,
-        screen_width: f32,
-        screen_height: f32,
-    ) {
-        for vertex in &mut self.vertex_locations {
-            let x_ratio = vertex.x / camera_width;
-            let y_ratio = vertex.y / camera_height;
-            vertex.x = x_ratio * screen_width;
-            vertex.y = y_ratio * screen_height;
+    /// Animates the object and runs its vertex shader over every vertex.
+    /// `uniforms` should hold the camera values and, in `model`, the world
+    /// matrix of the object's parent (identity for objects without one). The
+    /// object's own model matrix is applied on top of it and the time is
+    /// filled in here.
+    pub fn shade(&self, time: f32, mut uniforms: Uniforms) -> Result<ShadedGeometry, GeoError<'_>> {
+        if self.geo_type == GeometryType::Triangle && !self.vertices.len().is_multiple_of(3) {
+            return Err(GeoError::NotDiv3(self));
         }
+        for (name, stream) in self.attributes.iter() {
+            if stream.len() != self.vertices.len() {
+                return Err(GeoError::AttributeLength(self, name.clone()));
+            }
+        }
+        let layout =
+            VaryingLayout::pass_through(&self.attributes).ok_or(GeoError::TooManyVaryings(self))?;
+        let mut animated = self.clone();
+        animated.animate(time);
+        uniforms.model *= animated.model_matrix();
+        uniforms.model_view_projection = uniforms.projection * uniforms.view * uniforms.model;
+        uniforms.time = time;
+        let shaded: Vec<ShadedVertex> = animated
+            .vertices
+            .iter()
+            .enumerate()
+            .map(|(i, vertex)| {
+                let input = VertexInput {
+                    position: animated.vertex_locations[vertex.index],
+                    color: (&vertex.color).into(),
+                    vertex: i,
+                    attributes: &animated.attributes,
+                };
+                let output = animated.vertex_shader.vertex(&input, &uniforms);
+                ShadedVertex {
+                    position: output.position,
+                    varyings: output.varyings,
+                }
+            })
+            .collect();
+        // strips are split into segments, so shaded geometry is never a `Line`
+        let (geo_type, vertices) = match self.geo_type {
+            GeometryType::Line => (
+                GeometryType::Lines,
+                shaded
+                    .windows(2)
+                    .flat_map(|segment| segment.iter().cloned())
+                    .collect(),
+            ),
+            ref geo_type => (geo_type.clone(), shaded),
+        };
+        Ok(ShadedGeometry {
+            geo_type,
+            vertices,
+            interpolation: self.interpolation,
+            fragment_shader: self.fragment_shader.clone(),
+            uniforms,
+            layout: Arc::new(layout),
+        })
     }
 
     pub fn set_color(&mut self, color: Color) {
@@ -130,12 +372,28 @@ impl Geometry {
 
 impl fmt::Debug for Geometry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(self.name.as_ref().unwrap().as_str())
+        f.write_str(self.name.as_deref().unwrap_or("unnamed geometry"))
+    }
+}
+
+impl ShadedGeometry {
+    /// Homogeneous divide and viewport transform. Afterwards x and y are in
+    /// pixels, z is the depth (greater is closer) and w holds 1 / w_clip for
+    /// interpolation.
+    pub fn clip_to_screen(&mut self, screen_width: f32, screen_height: f32) {
+        for vertex in &mut self.vertices {
+            let position = &mut vertex.position;
+            let inv_w = 1.0 / position.w;
+            position.x = (position.x * inv_w + 1.0) * 0.5 * screen_width;
+            position.y = (position.y * inv_w + 1.0) * 0.5 * screen_height;
+            position.z *= inv_w;
+            position.w = inv_w;
+        }
     }
 }
 
 impl Vertex {
-    fn new(index: usize, color: Color) -> Self {
+    pub fn new(index: usize, color: Color) -> Self {
         Self { index, color }
     }
 }
@@ -193,3 +451,29 @@ pub fn square() -> Geometry {
     square.vertices.push(Vertex::new(2, Color::Red));
     square
 }
+
+/// A 2x2 square split along its diagonal from (-1, -1) to (1, 1), with
+/// texture coordinates from 0 to 1 across it. Unlike `square`, whose two
+/// triangles overlap, it covers each point once.
+pub fn quad() -> Geometry {
+    let mut quad = Geometry::new(GeometryType::Triangle);
+    quad.vertex_locations.push(point(-1.0, -1.0, 0.0));
+    quad.vertex_locations.push(point(-1.0, 1.0, 0.0));
+    quad.vertex_locations.push(point(1.0, 1.0, 0.0));
+    quad.vertex_locations.push(point(1.0, -1.0, 0.0));
+    quad.vertices.push(Vertex::new(0, Color::Blue));
+    quad.vertices.push(Vertex::new(1, Color::White));
+    quad.vertices.push(Vertex::new(2, Color::Red));
+    quad.vertices.push(Vertex::new(0, Color::Blue));
+    quad.vertices.push(Vertex::new(3, Color::Green));
+    quad.vertices.push(Vertex::new(2, Color::Red));
+    // (0, 0) at the top left corner, which has the smallest y
+    quad.set_attribute(
+        Attribute::TexCoord(0),
+        AttributeStream::from_vec(
+            2,
+            vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0],
+        ),
+    );
+    quad
+}
@@ -0,0 +1,1150 @@
+use crate::attribute::{Attribute, AttributeStream};
+use crate::color::{Color, Rgba};
+use crate::geometry::{point, Geometry, GeometryType, Transform, Vertex};
+use crate::image::read_png;
+use crate::json::Json;
+use crate::shader::{
+    ColorShader, FragmentInput, FragmentShader, Uniforms, VertexInput, VertexOutput, VertexShader,
+};
+use crate::texture::{Filter, MipFilter, Sampler, Texture, TextureShader, Wrap};
+use crate::world::{Camera, World};
+use nalgebra as na;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum GltfError {
+    Io(io::Error),
+    /// Malformed or unsupported content.
+    Parse(String),
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Io(error) => write!(f, "could not read glTF file: {error}"),
+            GltfError::Parse(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {}
+
+impl From<io::Error> for GltfError {
+    fn from(error: io::Error) -> Self {
+        GltfError::Io(error)
+    }
+}
+
+fn parse_error(message: impl Into<String>) -> GltfError {
+    GltfError::Parse(message.into())
+}
+
+/// Everything imported from a glTF scene.
+pub struct GltfScene {
+    pub world: World,
+    /// Cameras in the order their nodes appear in the scene.
+    pub cameras: Vec<Camera>,
+}
+
+/// Reads a `.gltf` or `.glb` file, see `read`. External buffers and images
+/// are looked up next to it.
+pub fn load(path: impl AsRef<Path>) -> Result<GltfScene, GltfError> {
+    let path = path.as_ref();
+    read(&fs::read(path)?, path.parent())
+}
+
+/// Builds a world from glTF 2.0 JSON or a binary `.glb` container.
+///
+/// Every primitive of every mesh node in the scene becomes one `Geometry`:
+/// points, lines, line strips and loops, and triangles (strips and fans are
+/// split into separate triangles). `NORMAL` and `TEXCOORD_n` become the
+/// `Normal` and `TexCoord(n)` attributes, and the base color factor times
+/// `COLOR_0` becomes the vertex colors. Base color textures must be PNG;
+/// they are drawn with a `TextureShader` tinted by the vertex colors.
+///
+/// glTF has y pointing up while this crate has it pointing down, so the
+/// whole scene is mirrored in y. A node's global matrix lands in the
+/// geometry's translation, rotation and scale, where the rotation holds the
+/// normalized columns (it is not a pure rotation when the node is mirrored
+/// or sheared). Camera nodes are positioned with `Camera::set_transform`;
+/// perspective cameras without an aspect ratio get 1 and infinite ones get a
+/// far plane 100000 times the near distance.
+///
+/// External buffer and image files are resolved against `base_dir`; data
+/// URIs and the GLB binary chunk need no directory. Sparse accessors,
+/// extensions and animations are not supported.
+pub fn read(bytes: &[u8], base_dir: Option<&Path>) -> Result<GltfScene, GltfError> {
+    let (json, bin) = if bytes.starts_with(b"glTF") {
+        split_glb(bytes)?
+    } else {
+        (bytes, None)
+    };
+    let json = std::str::from_utf8(json).map_err(|_| parse_error("glTF JSON is not UTF-8"))?;
+    let json = Json::parse(json).map_err(GltfError::Parse)?;
+    let version = json
+        .get("asset")
+        .and_then(|asset| asset.get("version"))
+        .and_then(Json::as_str)
+        .ok_or_else(|| parse_error("glTF asset version is missing"))?;
+    if !version.starts_with("2.") {
+        return Err(parse_error(format!(
+            "glTF version {version} is not supported"
+        )));
+    }
+    let mut document = Document {
+        buffers: vec![],
+        json: &json,
+        base_dir: base_dir.map(Path::to_path_buf),
+        images: HashMap::new(),
+    };
+    for (i, buffer) in array(&json, "buffers").iter().enumerate() {
+        let data = document.buffer(buffer, i, bin)?;
+        document.buffers.push(data);
+    }
+    let mut importer = Importer {
+        document,
+        scene: GltfScene {
+            world: World::default(),
+            cameras: vec![],
+        },
+    };
+    for node in importer.document.scene_roots()? {
+        importer.node(node, mirror(), 0)?;
+    }
+    Ok(importer.scene)
+}
+
+/// Splits a GLB container into its JSON and optional binary chunk.
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), GltfError> {
+    let word = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()) as usize)
+    };
+    let truncated = || parse_error("GLB file is truncated");
+    if word(4) != Some(2) {
+        return Err(parse_error("only GLB version 2 is supported"));
+    }
+    let length = word(8).ok_or_else(truncated)?;
+    let bytes = bytes.get(..length).ok_or_else(truncated)?;
+    let mut chunks = vec![];
+    let mut offset = 12;
+    while offset < bytes.len() {
+        let len = word(offset).ok_or_else(truncated)?;
+        let kind = word(offset + 4).ok_or_else(truncated)?;
+        let data = bytes
+            .get(offset + 8..offset + 8 + len)
+            .ok_or_else(truncated)?;
+        chunks.push((kind, data));
+        offset += 8 + len;
+    }
+    const JSON: usize = 0x4e4f534a;
+    const BIN: usize = 0x004e4942;
+    match chunks.as_slice() {
+        [(JSON, json), (BIN, bin), ..] => Ok((json, Some(bin))),
+        [(JSON, json), ..] => Ok((json, None)),
+        _ => Err(parse_error("GLB file does not start with a JSON chunk")),
+    }
+}
+
+/// Flips y, turning glTF's y up space into this crate's y down space.
+fn mirror() -> Transform {
+    Transform::from_diagonal(&na::Vector4::new(1.0, -1.0, 1.0, 1.0))
+}
+
+/// The elements of an array member, empty if it is missing.
+fn array<'a>(json: &'a Json, key: &str) -> &'a [Json] {
+    json.get(key).and_then(Json::as_array).unwrap_or_default()
+}
+
+/// An optional index member.
+fn index(json: &Json, key: &str) -> Result<Option<usize>, GltfError> {
+    json.get(key)
+        .map(|value| {
+            value
+                .as_usize()
+                .ok_or_else(|| parse_error(format!("glTF `{key}` is not an index")))
+        })
+        .transpose()
+}
+
+fn number(json: &Json, key: &str, default: f32) -> Result<f32, GltfError> {
+    match json.get(key) {
+        None => Ok(default),
+        Some(value) => value
+            .as_f32()
+            .ok_or_else(|| parse_error(format!("glTF `{key}` is not a number"))),
+    }
+}
+
+/// A number the spec requires, which has to be positive.
+fn positive_number(json: &Json, key: &str) -> Result<f32, GltfError> {
+    json.get(key)
+        .and_then(Json::as_f32)
+        .filter(|number| *number > 0.0)
+        .ok_or_else(|| parse_error(format!("glTF `{key}` is missing or not positive")))
+}
+
+/// A fixed size array of numbers, or `default` if the member is missing.
+fn numbers<const N: usize>(
+    json: &Json,
+    key: &str,
+    default: [f32; N],
+) -> Result<[f32; N], GltfError> {
+    let Some(value) = json.get(key) else {
+        return Ok(default);
+    };
+    value
+        .as_array()
+        .filter(|values| values.len() == N)
+        .and_then(|values| values.iter().map(Json::as_f32).collect::<Option<Vec<_>>>())
+        .map(|values| values.try_into().unwrap())
+        .ok_or_else(|| parse_error(format!("glTF `{key}` should hold {N} numbers")))
+}
+
+/// Looks up element `i` of a top level array such as "meshes".
+fn element<'a>(json: &'a Json, kind: &str, i: usize) -> Result<&'a Json, GltfError> {
+    array(json, kind)
+        .get(i)
+        .ok_or_else(|| parse_error(format!("glTF {kind} index {i} is out of range")))
+}
+
+/// Most elements an accessor without a bufferView may have. Those are all
+/// zeros (sparse accessors are not supported), so their count is the only
+/// thing deciding how much memory they take.
+const MAX_ZEROED_ELEMENTS: usize = 1 << 24;
+
+/// Decoded accessor contents, one entry per component.
+struct Accessor {
+    components: usize,
+    values: Vec<f32>,
+}
+
+impl Accessor {
+    fn len(&self) -> usize {
+        self.values.len() / self.components
+    }
+
+    fn get(&self, i: usize) -> &[f32] {
+        &self.values[i * self.components..(i + 1) * self.components]
+    }
+}
+
+struct Document<'a> {
+    json: &'a Json,
+    buffers: Vec<Vec<u8>>,
+    base_dir: Option<PathBuf>,
+    /// Decoded images by index, shared between textures.
+    images: HashMap<usize, Arc<Texture>>,
+}
+
+impl Document<'_> {
+    fn buffer(&self, buffer: &Json, i: usize, bin: Option<&[u8]>) -> Result<Vec<u8>, GltfError> {
+        let length = index(buffer, "byteLength")?
+            .ok_or_else(|| parse_error(format!("glTF buffer {i} has no byteLength")))?;
+        let data = match buffer.get("uri").and_then(Json::as_str) {
+            Some(uri) => self.uri(uri)?,
+            None if i == 0 => bin
+                .ok_or_else(|| {
+                    parse_error("glTF buffer 0 has no uri and there is no GLB binary chunk")
+                })?
+                .to_vec(),
+            None => return Err(parse_error(format!("glTF buffer {i} has no uri"))),
+        };
+        if data.len() < length {
+            return Err(parse_error(format!(
+                "glTF buffer {i} holds {} bytes but declares {length}",
+                data.len()
+            )));
+        }
+        Ok(data)
+    }
+
+    /// Contents of a data URI or a file relative to the base directory.
+    fn uri(&self, uri: &str) -> Result<Vec<u8>, GltfError> {
+        if let Some(data) = uri.strip_prefix("data:") {
+            let (_, encoded) = data
+                .split_once(";base64,")
+                .ok_or_else(|| parse_error("glTF data URIs must be base64 encoded"))?;
+            return decode_base64(encoded)
+                .ok_or_else(|| parse_error("glTF data URI is not valid base64"));
+        }
+        let base_dir = self.base_dir.as_ref().ok_or_else(|| {
+            parse_error(format!("cannot resolve `{uri}` without a base directory"))
+        })?;
+        Ok(fs::read(base_dir.join(decode_percent(uri)))?)
+    }
+
+    fn buffer_view(&self, i: usize) -> Result<(&[u8], Option<usize>), GltfError> {
+        let view = element(self.json, "bufferViews", i)?;
+        let buffer = index(view, "buffer")?
+            .ok_or_else(|| parse_error(format!("glTF bufferView {i} has no buffer")))?;
+        let buffer = self
+            .buffers
+            .get(buffer)
+            .ok_or_else(|| parse_error(format!("glTF buffer index {buffer} is out of range")))?;
+        let offset = index(view, "byteOffset")?.unwrap_or(0);
+        let length = index(view, "byteLength")?
+            .ok_or_else(|| parse_error(format!("glTF bufferView {i} has no byteLength")))?;
+        let data = offset
+            .checked_add(length)
+            .and_then(|end| buffer.get(offset..end))
+            .ok_or_else(|| parse_error(format!("glTF bufferView {i} is out of bounds")))?;
+        Ok((data, index(view, "byteStride")?))
+    }
+
+    /// Reads an accessor as floats, normalizing integers if it says so.
+    fn accessor(&self, i: usize) -> Result<Accessor, GltfError> {
+        let (components, values) = self.accessor_values(i)?;
+        Ok(Accessor {
+            components,
+            values: values.into_iter().map(|value| value as f32).collect(),
+        })
+    }
+
+    /// Reads an index accessor.
+    fn indices(&self, i: usize) -> Result<Vec<usize>, GltfError> {
+        let (_, values) = self.accessor_values(i)?;
+        Ok(values.into_iter().map(|value| value as usize).collect())
+    }
+
+    /// The component count and values of an accessor, as f64 so that 32 bit
+    /// indices stay exact.
+    fn accessor_values(&self, i: usize) -> Result<(usize, Vec<f64>), GltfError> {
+        let accessor = element(self.json, "accessors", i)?;
+        let error = |message: &str| parse_error(format!("glTF accessor {i} {message}"));
+        if accessor.get("sparse").is_some() {
+            return Err(error("is sparse, which is not supported"));
+        }
+        let count = index(accessor, "count")?.ok_or_else(|| error("has no count"))?;
+        let components = match accessor.get("type").and_then(Json::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            _ => return Err(error("has an unsupported type")),
+        };
+        let component_type = index(accessor, "componentType")?;
+        let (size, read): (usize, fn(&[u8]) -> f64) = match component_type {
+            Some(5120) => (1, |b| b[0] as i8 as f64),
+            Some(5121) => (1, |b| b[0] as f64),
+            Some(5122) => (2, |b| i16::from_le_bytes([b[0], b[1]]) as f64),
+            Some(5123) => (2, |b| u16::from_le_bytes([b[0], b[1]]) as f64),
+            Some(5125) => (4, |b| u32::from_le_bytes(b[..4].try_into().unwrap()) as f64),
+            Some(5126) => (4, |b| f32::from_le_bytes(b[..4].try_into().unwrap()) as f64),
+            _ => return Err(error("has an unsupported componentType")),
+        };
+        let normalized = accessor.get("normalized") == Some(&Json::Bool(true));
+        let scale = match component_type {
+            Some(5120) => 127.0,
+            Some(5121) => 255.0,
+            Some(5122) => 32767.0,
+            Some(5123) => 65535.0,
+            _ => 1.0,
+        };
+        let Some(view) = index(accessor, "bufferView")? else {
+            // without a view every element is zero, and nothing in the file
+            // bounds how many there are
+            if count > MAX_ZEROED_ELEMENTS {
+                return Err(error(&format!(
+                    "has no bufferView and {count} elements, more than the {MAX_ZEROED_ELEMENTS} supported"
+                )));
+            }
+            return Ok((components, vec![0.0; count * components]));
+        };
+        let (data, stride) = self.buffer_view(view)?;
+        let element_size = size * components;
+        let stride = stride.unwrap_or(element_size);
+        if stride < element_size {
+            return Err(error("has a byteStride smaller than its elements"));
+        }
+        let offset = index(accessor, "byteOffset")?.unwrap_or(0);
+        // with the stride at least an element, this also bounds the count
+        let end = match count {
+            0 => Some(offset),
+            _ => stride
+                .checked_mul(count - 1)
+                .and_then(|last| last.checked_add(offset))
+                .and_then(|last| last.checked_add(element_size)),
+        };
+        if end.is_none_or(|end| end > data.len()) {
+            return Err(error("reads past the end of its bufferView"));
+        }
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            let start = offset + element * stride;
+            for component in 0..components {
+                let value = read(&data[start + component * size..]);
+                if normalized {
+                    // signed types have two encodings of -1
+                    values.push((value / scale).max(-1.0));
+                } else {
+                    values.push(value);
+                }
+            }
+        }
+        Ok((components, values))
+    }
+
+    /// Finds the root nodes of the default scene, of the first scene if there
+    /// is no default, or every node without a parent if there are no scenes.
+    fn scene_roots(&self) -> Result<Vec<usize>, GltfError> {
+        let scenes = array(self.json, "scenes");
+        if !scenes.is_empty() {
+            let scene = index(self.json, "scene")?.unwrap_or(0);
+            let scene = element(self.json, "scenes", scene)?;
+            return array(scene, "nodes")
+                .iter()
+                .map(|node| {
+                    node.as_usize()
+                        .ok_or_else(|| parse_error("glTF scene node is not an index"))
+                })
+                .collect();
+        }
+        let nodes = array(self.json, "nodes");
+        let mut is_child = vec![false; nodes.len()];
+        for node in nodes {
+            for child in array(node, "children") {
+                if let Some(is_child) = child.as_usize().and_then(|child| is_child.get_mut(child)) {
+                    *is_child = true;
+                }
+            }
+        }
+        Ok((0..nodes.len()).filter(|&node| !is_child[node]).collect())
+    }
+
+    /// The image of a texture, decoded once.
+    fn image(&mut self, i: usize) -> Result<Arc<Texture>, GltfError> {
+        if let Some(texture) = self.images.get(&i) {
+            return Ok(texture.clone());
+        }
+        let image = element(self.json, "images", i)?;
+        let bytes = match (
+            image.get("uri").and_then(Json::as_str),
+            index(image, "bufferView")?,
+        ) {
+            (Some(uri), _) => self.uri(uri)?,
+            (None, Some(view)) => self.buffer_view(view)?.0.to_vec(),
+            (None, None) => return Err(parse_error(format!("glTF image {i} has no data"))),
+        };
+        let texture =
+            read_png(&bytes).map_err(|error| parse_error(format!("glTF image {i}: {error}")))?;
+        let texture = Arc::new(texture);
+        self.images.insert(i, texture.clone());
+        Ok(texture)
+    }
+
+    fn sampler(&self, texture: &Json) -> Result<Sampler, GltfError> {
+        let Some(i) = index(texture, "sampler")? else {
+            return Ok(Sampler::trilinear(Wrap::Repeat));
+        };
+        let sampler = element(self.json, "samplers", i)?;
+        let wrap = |key| match index(sampler, key)? {
+            None | Some(10497) => Ok(Wrap::Repeat),
+            Some(33071) => Ok(Wrap::Clamp),
+            Some(33648) => Ok(Wrap::Mirror),
+            Some(other) => Err(parse_error(format!("unknown glTF wrap mode {other}"))),
+        };
+        let min_filter = index(sampler, "minFilter")?;
+        let filter = match index(sampler, "magFilter")?.or(min_filter) {
+            Some(9728 | 9984 | 9986) => Filter::Nearest,
+            _ => Filter::Bilinear,
+        };
+        let mip_filter = match min_filter {
+            Some(9728 | 9729) => MipFilter::None,
+            Some(9984 | 9985) => MipFilter::Nearest,
+            _ => MipFilter::Linear,
+        };
+        Ok(Sampler {
+            filter,
+            mip_filter,
+            wrap_u: wrap("wrapS")?,
+            wrap_v: wrap("wrapT")?,
+            max_anisotropy: 1,
+        })
+    }
+}
+
+/// How a material's alpha is used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AlphaMode {
+    Opaque,
+    /// Fully opaque at or above the cutoff, discarded below it.
+    Mask(f32),
+    Blend,
+}
+
+/// Base color of a material, for building a primitive's shader.
+struct Material {
+    factor: [f32; 4],
+    texture: Option<TextureShader>,
+    alpha_mode: AlphaMode,
+}
+
+/// Colors like a `TextureShader` (or a `ColorShader` without a texture),
+/// then applies the material's alpha mode.
+struct MaterialShader {
+    texture: Option<TextureShader>,
+    alpha_mode: AlphaMode,
+}
+
+impl VertexShader for MaterialShader {
+    fn vertex(&self, input: &VertexInput, uniforms: &Uniforms) -> VertexOutput {
+        ColorShader.vertex(input, uniforms)
+    }
+}
+
+impl FragmentShader for MaterialShader {
+    fn fragment(&self, input: &FragmentInput, uniforms: &Uniforms) -> Option<Rgba> {
+        let mut color = match &self.texture {
+            Some(texture) => texture.fragment(input, uniforms)?,
+            None => ColorShader.fragment(input, uniforms)?,
+        };
+        match self.alpha_mode {
+            AlphaMode::Opaque => color.a.0 = 1.0,
+            AlphaMode::Mask(cutoff) if color.a.0 < cutoff => return None,
+            AlphaMode::Mask(_) => color.a.0 = 1.0,
+            AlphaMode::Blend => {}
+        }
+        Some(color)
+    }
+}
+
+struct Importer<'a> {
+    document: Document<'a>,
+    scene: GltfScene,
+}
+
+/// Node hierarchies deeper than this are taken to be cycles.
+const MAX_NODE_DEPTH: usize = 256;
+
+impl Importer<'_> {
+    /// Imports a node and its children, `parent` being the (mirrored) global
+    /// matrix of its parent.
+    fn node(&mut self, i: usize, parent: Transform, depth: usize) -> Result<(), GltfError> {
+        if depth > MAX_NODE_DEPTH {
+            return Err(parse_error(
+                "glTF node hierarchy is too deep or has a cycle",
+            ));
+        }
+        let json = self.document.json;
+        let node = element(json, "nodes", i)?;
+        let matrix = parent * local_matrix(node)?;
+        if let Some(camera) = index(node, "camera")? {
+            let mut camera = self.camera(camera)?;
+            camera.set_transform(matrix * mirror());
+            self.scene.cameras.push(camera);
+        }
+        if let Some(mesh) = index(node, "mesh")? {
+            let mesh_json = element(json, "meshes", mesh)?;
+            let name = node
+                .get("name")
+                .or_else(|| mesh_json.get("name"))
+                .and_then(Json::as_str);
+            let primitives = array(mesh_json, "primitives");
+            for (p, primitive) in primitives.iter().enumerate() {
+                let mut geometry = self.primitive(primitive)?;
+                geometry.set_name(name.map(|name| match primitives.len() {
+                    1 => name.to_string(),
+                    _ => format!("{name}/{p}"),
+                }));
+                place(&mut geometry, matrix);
+                self.scene.world.insert(geometry);
+            }
+        }
+        for child in array(node, "children") {
+            let child = child
+                .as_usize()
+                .ok_or_else(|| parse_error("glTF node child is not an index"))?;
+            self.node(child, matrix, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn camera(&self, i: usize) -> Result<Camera, GltfError> {
+        let camera = element(self.document.json, "cameras", i)?;
+        let origin = point(0.0, 0.0, 0.0);
+        match camera.get("type").and_then(Json::as_str) {
+            Some("perspective") => {
+                let perspective = camera
+                    .get("perspective")
+                    .ok_or_else(|| parse_error(format!("glTF camera {i} has no perspective")))?;
+                let near = positive_number(perspective, "znear")?;
+                // without a far plane the projection is infinite
+                let far = number(perspective, "zfar", near * 100000.0)?;
+                if far <= near {
+                    return Err(parse_error(format!(
+                        "glTF camera {i} has zfar {far} before znear {near}"
+                    )));
+                }
+                let aspect = match perspective.get("aspectRatio") {
+                    Some(_) => positive_number(perspective, "aspectRatio")?,
+                    None => 1.0,
+                };
+                Ok(Camera::perspective(
+                    origin,
+                    positive_number(perspective, "yfov")?,
+                    aspect,
+                    near,
+                    far,
+                ))
+            }
+            Some("orthographic") => {
+                let orthographic = camera
+                    .get("orthographic")
+                    .ok_or_else(|| parse_error(format!("glTF camera {i} has no orthographic")))?;
+                let required = |key: &str| {
+                    orthographic.get(key).and_then(Json::as_f32).ok_or_else(|| {
+                        parse_error(format!("glTF `{key}` is missing or not a number"))
+                    })
+                };
+                let (xmag, ymag) = (required("xmag")?, required("ymag")?);
+                let (near, far) = (required("znear")?, positive_number(orthographic, "zfar")?);
+                if xmag == 0.0 || ymag == 0.0 || near < 0.0 || far <= near {
+                    return Err(parse_error(format!(
+                        "glTF camera {i} has an empty orthographic volume"
+                    )));
+                }
+                Ok(Camera::orthographic(
+                    origin,
+                    2.0 * xmag,
+                    2.0 * ymag,
+                    near,
+                    far,
+                ))
+            }
+            _ => Err(parse_error(format!("glTF camera {i} has an unknown type"))),
+        }
+    }
+
+    fn material(&mut self, i: Option<usize>) -> Result<Material, GltfError> {
+        let Some(i) = i else {
+            return Ok(Material {
+                factor: [1.0; 4],
+                texture: None,
+                alpha_mode: AlphaMode::Opaque,
+            });
+        };
+        let json = self.document.json;
+        let material = element(json, "materials", i)?;
+        let pbr = material.get("pbrMetallicRoughness");
+        let factor = match pbr {
+            Some(pbr) => numbers(pbr, "baseColorFactor", [1.0; 4])?,
+            None => [1.0; 4],
+        };
+        let texture = match pbr.and_then(|pbr| pbr.get("baseColorTexture")) {
+            Some(info) => {
+                let texture_index = index(info, "index")?.ok_or_else(|| {
+                    parse_error(format!("glTF material {i} texture has no index"))
+                })?;
+                let texture = element(json, "textures", texture_index)?;
+                let image = index(texture, "source")?.ok_or_else(|| {
+                    parse_error(format!("glTF texture {texture_index} has no source"))
+                })?;
+                let mut shader = TextureShader::new(
+                    self.document.image(image)?,
+                    self.document.sampler(texture)?,
+                );
+                shader.texcoord = index(info, "texCoord")?.unwrap_or(0);
+                shader.modulate = true;
+                Some(shader)
+            }
+            None => None,
+        };
+        let alpha_mode = match material.get("alphaMode").and_then(Json::as_str) {
+            None | Some("OPAQUE") => AlphaMode::Opaque,
+            Some("MASK") => AlphaMode::Mask(number(material, "alphaCutoff", 0.5)?),
+            Some("BLEND") => AlphaMode::Blend,
+            Some(other) => return Err(parse_error(format!("unknown glTF alphaMode {other}"))),
+        };
+        Ok(Material {
+            factor,
+            texture,
+            alpha_mode,
+        })
+    }
+
+    fn primitive(&mut self, primitive: &Json) -> Result<Geometry, GltfError> {
+        let material = self.material(index(primitive, "material")?)?;
+        let attributes = primitive
+            .get("attributes")
+            .ok_or_else(|| parse_error("glTF primitive has no attributes"))?;
+        let accessor = |name: &str| -> Result<Option<Accessor>, GltfError> {
+            index(attributes, name)?
+                .map(|i| self.document.accessor(i))
+                .transpose()
+        };
+        let positions =
+            accessor("POSITION")?.ok_or_else(|| parse_error("glTF primitive has no POSITION"))?;
+        if positions.components != 3 {
+            return Err(parse_error("glTF POSITION must be VEC3"));
+        }
+        let indices = match index(primitive, "indices")? {
+            Some(i) => {
+                let indices = self.document.indices(i)?;
+                if let Some(&bad) = indices.iter().find(|&&i| i >= positions.len()) {
+                    return Err(parse_error(format!(
+                        "glTF index {bad} is out of range for {} vertices",
+                        positions.len()
+                    )));
+                }
+                indices
+            }
+            None => (0..positions.len()).collect(),
+        };
+        let (geo_type, indices) = assemble(index(primitive, "mode")?.unwrap_or(4), indices)?;
+
+        let colors = accessor("COLOR_0")?;
+        if let Some(colors) = &colors {
+            if !matches!(colors.components, 3 | 4) {
+                return Err(parse_error("glTF COLOR_0 must be VEC3 or VEC4"));
+            }
+            if colors.len() != positions.len() {
+                return Err(parse_error(format!(
+                    "glTF COLOR_0 has {} values for {} positions",
+                    colors.len(),
+                    positions.len()
+                )));
+            }
+        }
+        let mut geometry = Geometry::new(geo_type);
+        for position in positions.values.chunks_exact(3) {
+            geometry
+                .vertex_locations
+                .push(point(position[0], position[1], position[2]));
+        }
+        for &i in &indices {
+            let mut rgba = material.factor;
+            if let Some(colors) = &colors {
+                for (channel, value) in rgba.iter_mut().zip(colors.get(i)) {
+                    *channel *= value;
+                }
+            }
+            if material.alpha_mode == AlphaMode::Opaque {
+                rgba[3] = 1.0;
+            }
+            let [r, g, b, a] = rgba;
+            geometry
+                .vertices
+                .push(Vertex::new(i, Color::Custom(r, g, b, a)));
+        }
+        let mut streams = vec![(Attribute::Normal, accessor("NORMAL")?)];
+        for set in 0.. {
+            match accessor(&format!("TEXCOORD_{set}"))? {
+                Some(texcoords) => streams.push((Attribute::TexCoord(set), Some(texcoords))),
+                None => break,
+            }
+        }
+        for (attribute, values) in streams {
+            let Some(values) = values else {
+                continue;
+            };
+            if values.len() != positions.len() {
+                return Err(parse_error(format!(
+                    "glTF {attribute:?} has {} values for {} positions",
+                    values.len(),
+                    positions.len()
+                )));
+            }
+            let mut stream = AttributeStream::new(values.components);
+            for &i in &indices {
+                stream.push(values.get(i));
+            }
+            geometry.set_attribute(attribute, stream);
+        }
+        // opaque vertex colors were already made opaque above
+        if material.texture.is_some() || matches!(material.alpha_mode, AlphaMode::Mask(_)) {
+            let shader = Arc::new(MaterialShader {
+                texture: material.texture,
+                alpha_mode: material.alpha_mode,
+            });
+            geometry.set_vertex_shader(shader.clone());
+            geometry.set_fragment_shader(shader);
+        }
+        Ok(geometry)
+    }
+}
+
+/// Turns a glTF primitive mode and its indices into a geometry type and the
+/// vertex order it expects.
+fn assemble(mode: usize, indices: Vec<usize>) -> Result<(GeometryType, Vec<usize>), GltfError> {
+    let triangles = |corners: &dyn Fn(usize) -> [usize; 3]| -> Vec<usize> {
+        (0..indices.len().saturating_sub(2))
+            .flat_map(|i| corners(i).map(|corner| indices[corner]))
+            .collect()
+    };
+    Ok(match mode {
+        0 => (GeometryType::Point, indices),
+        1 => (GeometryType::Lines, indices),
+        2 => {
+            let mut closed = indices.clone();
+            closed.extend(indices.first());
+            (GeometryType::Line, closed)
+        }
+        3 => (GeometryType::Line, indices),
+        4 => {
+            if !indices.len().is_multiple_of(3) {
+                return Err(parse_error("glTF triangle count is not a multiple of 3"));
+            }
+            (GeometryType::Triangle, indices)
+        }
+        // odd triangles of a strip swap two corners to keep the winding
+        5 => (
+            GeometryType::Triangle,
+            triangles(&|i| [i, i + 1 + i % 2, i + 2 - i % 2]),
+        ),
+        6 => (GeometryType::Triangle, triangles(&|i| [i + 1, i + 2, 0])),
+        _ => return Err(parse_error(format!("unknown glTF primitive mode {mode}"))),
+    })
+}
+
+/// A node's own matrix, from `matrix` or its translation, rotation and
+/// scale.
+fn local_matrix(node: &Json) -> Result<Transform, GltfError> {
+    if node.get("matrix").is_some() {
+        let values = numbers::<16>(node, "matrix", [0.0; 16])?;
+        // stored column by column
+        return Ok(Transform::from_column_slice(&values));
+    }
+    let [tx, ty, tz] = numbers(node, "translation", [0.0; 3])?;
+    let [x, y, z, w] = numbers(node, "rotation", [0.0, 0.0, 0.0, 1.0])?;
+    let [sx, sy, sz] = numbers(node, "scale", [1.0; 3])?;
+    let rotation = na::UnitQuaternion::from_quaternion(na::Quaternion::new(w, x, y, z));
+    Ok(na::Matrix4::new_translation(&na::Vector3::new(tx, ty, tz))
+        * rotation.to_homogeneous()
+        * na::Matrix4::new_nonuniform_scaling(&na::Vector3::new(sx, sy, sz)))
+}
+
+/// Splits a global matrix into the geometry's translation, rotation and
+/// scale. The rotation gets the normalized columns and the scale their
+/// lengths, which multiply back to the original matrix.
+fn place(geometry: &mut Geometry, matrix: Transform) {
+    geometry.set_position(matrix.column(3).into());
+    let mut rotation = Transform::identity();
+    let mut scale = na::Vector3::zeros();
+    for column in 0..3 {
+        let axis = matrix.fixed_view::<3, 1>(0, column);
+        scale[column] = axis.norm();
+        if scale[column] > 0.0 {
+            rotation
+                .fixed_view_mut::<3, 1>(0, column)
+                .copy_from(&(axis / scale[column]));
+        }
+    }
+    geometry.set_rotation_matrix(rotation);
+    geometry.scale(scale);
+}
+
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in encoded.bytes().take_while(|&byte| byte != b'=') {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Undoes `%XX` escapes in a relative URI.
+fn decode_percent(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::write_png;
+    use crate::math::f32_equals;
+    use crate::renderer::{render_headless, Framebuffer};
+
+    /// A red triangle pointing up, seen by a camera 3 units in front of it.
+    const TRIANGLE: &str = r#"{
+        "asset": {"version": "2.0"},
+        "scene": 0,
+        "scenes": [{"nodes": [0, 1]}],
+        "nodes": [
+            {"name": "tri", "mesh": 0},
+            {"camera": 0, "translation": [0, 0, 3]}
+        ],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "indices": 1, "material": 0}]}],
+        "materials": [{"pbrMetallicRoughness": {"baseColorFactor": [1, 0, 0, 1]}}],
+        "cameras": [{"type": "perspective", "perspective": {"yfov": 1.5707964, "znear": 0.1}}],
+        "buffers": [{
+            "byteLength": 44,
+            "uri": "data:application/octet-stream;base64,AACAvwAAgL8AAAAAAACAPwAAgL8AAAAAAAAAAAAAgD8AAAAAAAABAAIAAAA="
+        }],
+        "bufferViews": [
+            {"buffer": 0, "byteLength": 36},
+            {"buffer": 0, "byteOffset": 36, "byteLength": 6}
+        ],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"},
+            {"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}
+        ]
+    }"#;
+
+    #[test]
+    fn test_triangle_and_camera() {
+        let scene = read(TRIANGLE.as_bytes(), None).unwrap();
+        let objects: Vec<&Geometry> = scene.world.iter().map(|(_, obj)| obj).collect();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].name(), Some("tri"));
+        assert_eq!(objects[0].vertices.len(), 3);
+        assert!(matches!(
+            objects[0].vertices[2].color,
+            Color::Custom(1.0, 0.0, 0.0, 1.0)
+        ));
+
+        assert_eq!(scene.cameras.len(), 1);
+        let camera = &scene.cameras[0];
+        assert_eq!(camera.position(), point(0.0, 0.0, 3.0));
+        let (yaw, pitch, roll) = camera.orientation();
+        assert!(f32_equals(yaw, 0.0) && f32_equals(pitch, 0.0) && f32_equals(roll, 0.0));
+        assert!(f32_equals(camera.far(), 0.1 * 100000.0));
+
+        // the tip points up on screen, so the wide base is below the center
+        let frame = render_headless(&scene.world, camera, 30, 30, 0.0);
+        let red = Rgba::color(1.0, 0.0, 0.0);
+        assert_eq!(frame.get(18, 19), Some(&red));
+        assert_ne!(frame.get(18, 11), Some(&red));
+    }
+
+    /// Wraps JSON and binary data in a GLB container.
+    fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut bin = bin.to_vec();
+        bin.resize(bin.len().next_multiple_of(4), 0);
+        let mut out = b"glTF".to_vec();
+        out.extend_from_slice(&2u32.to_le_bytes());
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+        out.extend_from_slice(&(length as u32).to_le_bytes());
+        for (kind, data) in [(b"JSON", json), (b"BIN\0", bin)] {
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(kind);
+            out.extend_from_slice(&data);
+        }
+        out
+    }
+
+    #[test]
+    fn test_glb_hierarchy_and_texture() {
+        let mut bin = vec![];
+        for value in [-1.0f32, -1.0, 0.0, 1.0, -1.0, 0.0, 0.0, 1.0, 0.0] {
+            bin.extend_from_slice(&value.to_le_bytes());
+        }
+        // yellow vertex colors
+        for _ in 0..3 {
+            bin.extend_from_slice(&[255, 255, 0, 255]);
+        }
+        for _ in 0..6 {
+            bin.extend_from_slice(&0.5f32.to_le_bytes());
+        }
+        // tinting a magenta texel gives red
+        let magenta = Framebuffer::new(1, 1, &Rgba::color(1.0, 0.0, 1.0));
+        let mut png = vec![];
+        write_png(&magenta, &mut png).unwrap();
+        bin.extend_from_slice(&png);
+        let json = format!(
+            r#"{{
+            "asset": {{"version": "2.0"}},
+            "nodes": [
+                {{"scale": [2, 2, 2], "children": [1]}},
+                {{"mesh": 0, "translation": [0, 0.5, 0]}},
+                {{"camera": 0, "translation": [0, 0, 5]}}
+            ],
+            "meshes": [{{"name": "panel", "primitives": [{{
+                "attributes": {{"POSITION": 0, "COLOR_0": 1, "TEXCOORD_0": 2}},
+                "material": 0
+            }}]}}],
+            "materials": [{{"pbrMetallicRoughness": {{"baseColorTexture": {{"index": 0}}}}}}],
+            "textures": [{{"source": 0, "sampler": 0}}],
+            "samplers": [{{"magFilter": 9728, "wrapS": 33071, "wrapT": 33648}}],
+            "images": [{{"bufferView": 3, "mimeType": "image/png"}}],
+            "cameras": [{{"type": "orthographic", "orthographic": {{
+                "xmag": 4, "ymag": 4, "znear": 0.1, "zfar": 10
+            }}}}],
+            "buffers": [{{"byteLength": {}}}],
+            "bufferViews": [
+                {{"buffer": 0, "byteLength": 36}},
+                {{"buffer": 0, "byteOffset": 36, "byteLength": 12}},
+                {{"buffer": 0, "byteOffset": 48, "byteLength": 24}},
+                {{"buffer": 0, "byteOffset": 72, "byteLength": {}}}
+            ],
+            "accessors": [
+                {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}},
+                {{"bufferView": 1, "componentType": 5121, "normalized": true, "count": 3, "type": "VEC4"}},
+                {{"bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC2"}}
+            ]
+        }}"#,
+            bin.len(),
+            png.len()
+        );
+        let scene = read(&glb(&json, &bin), None).unwrap();
+        // nodes without a scene are all imported from their roots
+        let objects: Vec<&Geometry> = scene.world.iter().map(|(_, obj)| obj).collect();
+        assert_eq!(objects.len(), 1);
+        let panel = &objects[0];
+        assert_eq!(panel.name(), Some("panel"));
+        assert!(matches!(
+            panel.vertices[0].color,
+            Color::Custom(1.0, 1.0, 0.0, 1.0)
+        ));
+        assert_eq!(
+            panel.attribute(&Attribute::TexCoord(0)).unwrap().get(1),
+            &[0.5, 0.5]
+        );
+        // scaled by the parent, moved by the child, then mirrored in y
+        assert_eq!(
+            panel.model_matrix() * point(0.0, 1.0, 0.0),
+            point(0.0, -3.0, 0.0)
+        );
+
+        let camera = &scene.cameras[0];
+        let frame = render_headless(&scene.world, camera, 20, 20, 0.0);
+        assert_eq!(frame.get(10, 10), Some(&Rgba::color(1.0, 0.0, 0.0)));
+        assert_ne!(frame.get(10, 17), Some(&Rgba::color(1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_assemble() {
+        let (geo_type, loop_indices) = assemble(2, vec![0, 1, 2]).unwrap();
+        assert_eq!(geo_type, GeometryType::Line);
+        assert_eq!(loop_indices, vec![0, 1, 2, 0]);
+        let (_, strip) = assemble(5, vec![0, 1, 2, 3]).unwrap();
+        assert_eq!(strip, vec![0, 1, 2, 1, 3, 2]);
+        let (_, fan) = assemble(6, vec![0, 1, 2, 3]).unwrap();
+        assert_eq!(fan, vec![1, 2, 0, 2, 3, 0]);
+        assert!(assemble(4, vec![0, 1]).is_err());
+        assert!(assemble(7, vec![]).is_err());
+    }
+
+    #[test]
+    fn test_uris() {
+        assert_eq!(decode_base64("aGVsbG8="), Some(b"hello".to_vec()));
+        assert_eq!(decode_base64("aGk"), Some(b"hi".to_vec()));
+        assert_eq!(decode_base64("a*"), None);
+        assert_eq!(decode_percent("my%20mesh.bin"), "my mesh.bin");
+        assert_eq!(decode_percent("100%"), "100%");
+    }
+
+    fn parse_message(source: &str) -> String {
+        match read(source.as_bytes(), None) {
+            Err(GltfError::Parse(message)) => message,
+            Err(other) => panic!("expected a parse error, got {other}"),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let old = TRIANGLE.replace("\"2.0\"", "\"1.0\"");
+        assert_eq!(parse_message(&old), "glTF version 1.0 is not supported");
+        let sparse = TRIANGLE.replace(
+            "\"count\": 3, \"type\": \"VEC3\"",
+            "\"count\": 3, \"type\": \"VEC3\", \"sparse\": {}",
+        );
+        assert_eq!(
+            parse_message(&sparse),
+            "glTF accessor 0 is sparse, which is not supported"
+        );
+        let past_end = TRIANGLE.replace("\"byteLength\": 36", "\"byteLength\": 30");
+        assert_eq!(
+            parse_message(&past_end),
+            "glTF accessor 0 reads past the end of its bufferView"
+        );
+        let no_fov = TRIANGLE.replace("\"yfov\": 1.5707964, ", "");
+        assert_eq!(
+            parse_message(&no_fov),
+            "glTF `yfov` is missing or not positive"
+        );
+        let no_near = TRIANGLE.replace(", \"znear\": 0.1", "");
+        assert_eq!(
+            parse_message(&no_near),
+            "glTF `znear` is missing or not positive"
+        );
+        let inverted = TRIANGLE.replace("\"znear\": 0.1", "\"znear\": 2, \"zfar\": 1");
+        assert_eq!(
+            parse_message(&inverted),
+            "glTF camera 0 has zfar 1 before znear 2"
+        );
+        let orthographic = TRIANGLE.replace(
+            "\"type\": \"perspective\", \"perspective\": {\"yfov\": 1.5707964, \"znear\": 0.1}",
+            "\"type\": \"orthographic\", \"orthographic\": {\"xmag\": 1, \"znear\": 0, \"zfar\": 1}",
+        );
+        assert_eq!(
+            parse_message(&orthographic),
+            "glTF `ymag` is missing or not a number"
+        );
+        let flat = orthographic.replace("\"xmag\": 1,", "\"xmag\": 1, \"ymag\": 0,");
+        assert_eq!(
+            parse_message(&flat),
+            "glTF camera 0 has an empty orthographic volume"
+        );
+        let colors = TRIANGLE
+            .replace("\"POSITION\": 0", "\"POSITION\": 0, \"COLOR_0\": 2")
+            .replace(
+                "\"type\": \"SCALAR\"}",
+                "\"type\": \"SCALAR\"},\n{\"bufferView\": 0, \"componentType\": 5126, \"count\": 1, \"type\": \"VEC3\"}",
+            );
+        assert_eq!(
+            parse_message(&colors),
+            "glTF COLOR_0 has 1 values for 3 positions"
+        );
+        let zeros = TRIANGLE.replace(
+            "\"bufferView\": 0, \"componentType\": 5126, \"count\": 3",
+            "\"componentType\": 5126, \"count\": 1e300",
+        );
+        assert!(parse_message(&zeros).starts_with("glTF accessor 0 has no bufferView and"));
+        let stride = TRIANGLE.replace(
+            "\"byteLength\": 36}",
+            "\"byteLength\": 36, \"byteStride\": 1e300}",
+        );
+        assert_eq!(
+            parse_message(&stride),
+            "glTF accessor 0 reads past the end of its bufferView"
+        );
+        let offset = TRIANGLE.replace("\"byteOffset\": 36", "\"byteOffset\": 1e300");
+        assert_eq!(parse_message(&offset), "glTF bufferView 1 is out of bounds");
+        let external = TRIANGLE.replace("data:application/octet-stream;base64,", "");
+        assert!(parse_message(&external).starts_with("cannot resolve"));
+        assert!(parse_message("{").starts_with("JSON line 1"));
+    }
+}
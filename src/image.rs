@@ -0,0 +1,725 @@
+use crate::inflate;
+use crate::renderer::Framebuffer;
+use crate::texture::Texture;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Ppm,
+    Pam,
+    Bmp,
+    Tga,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "pam" => Some(ImageFormat::Pam),
+            "bmp" => Some(ImageFormat::Bmp),
+            "tga" => Some(ImageFormat::Tga),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+/// Saves the framebuffer, picking the format from the file extension.
+pub fn save(framebuffer: &Framebuffer, path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    let format = ImageFormat::from_path(path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported image extension: {}", path.display()),
+        )
+    })?;
+    let mut out = BufWriter::new(File::create(path)?);
+    write_image(framebuffer, format, &mut out)?;
+    out.flush()
+}
+
+pub fn write_image(
+    framebuffer: &Framebuffer,
+    format: ImageFormat,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match format {
+        ImageFormat::Ppm => write_ppm(framebuffer, out),
+        ImageFormat::Pam => write_pam(framebuffer, out),
+        ImageFormat::Bmp => write_bmp(framebuffer, out),
+        ImageFormat::Tga => write_tga(framebuffer, out),
+        ImageFormat::Png => write_png(framebuffer, out),
+    }
+}
+
+/// Binary (P6) PPM. The format has no alpha channel, so it is dropped.
+pub fn write_ppm(framebuffer: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+    write!(
+        out,
+        "P6\n{} {}\n255\n",
+        framebuffer.width(),
+        framebuffer.height()
+    )?;
+    let data: Vec<u8> = srgba8_pixels(framebuffer)
+        .flat_map(|[r, g, b, _]| [r, g, b])
+        .collect();
+    out.write_all(&data)
+}
+
+/// PAM (P7) with an RGB_ALPHA tuple type.
+pub fn write_pam(framebuffer: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+    write!(
+        out,
+        "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+        framebuffer.width(),
+        framebuffer.height()
+    )?;
+    let data: Vec<u8> = srgba8_pixels(framebuffer).flatten().collect();
+    out.write_all(&data)
+}
+
+/// 32 bit BMP using a BITMAPV4HEADER so readers pick up the alpha mask.
+/// Rows are stored top-down (negative height). The file size is stored in 32
+/// bits, so frames that do not fit are an `InvalidInput` error.
+pub fn write_bmp(framebuffer: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+    const FILE_HEADER_SIZE: u32 = 14;
+    const INFO_HEADER_SIZE: u32 = 108;
+    const BI_BITFIELDS: u32 = 3;
+    let offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}x{} is too large for BMP",
+                framebuffer.width(),
+                framebuffer.height()
+            ),
+        )
+    };
+    let (Ok(width), Ok(height)) = (
+        i32::try_from(framebuffer.width()),
+        i32::try_from(framebuffer.height()),
+    ) else {
+        return Err(too_large());
+    };
+    let data_size = (width as u32)
+        .checked_mul(height as u32)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .filter(|size| size.checked_add(offset).is_some())
+        .ok_or_else(too_large)?;
+    let mut header = Vec::with_capacity(offset as usize);
+    // BITMAPFILEHEADER
+    header.extend_from_slice(b"BM");
+    header.extend_from_slice(&(offset + data_size).to_le_bytes());
+    header.extend_from_slice(&0_u32.to_le_bytes());
+    header.extend_from_slice(&offset.to_le_bytes());
+    // BITMAPV4HEADER
+    header.extend_from_slice(&INFO_HEADER_SIZE.to_le_bytes());
+    header.extend_from_slice(&width.to_le_bytes());
+    header.extend_from_slice(&(-height).to_le_bytes());
+    header.extend_from_slice(&1_u16.to_le_bytes());
+    header.extend_from_slice(&32_u16.to_le_bytes());
+    header.extend_from_slice(&BI_BITFIELDS.to_le_bytes());
+    header.extend_from_slice(&data_size.to_le_bytes());
+    // 72 dpi in pixels per meter
+    header.extend_from_slice(&2835_i32.to_le_bytes());
+    header.extend_from_slice(&2835_i32.to_le_bytes());
+    header.extend_from_slice(&0_u32.to_le_bytes());
+    header.extend_from_slice(&0_u32.to_le_bytes());
+    for mask in [0x00ff0000_u32, 0x0000ff00, 0x000000ff, 0xff000000] {
+        header.extend_from_slice(&mask.to_le_bytes());
+    }
+    // LCS_sRGB
+    header.extend_from_slice(b"BGRs");
+    // endpoints and gamma are ignored for sRGB
+    header.extend_from_slice(&[0; 48]);
+    out.write_all(&header)?;
+    let data: Vec<u8> = srgba8_pixels(framebuffer)
+        .flat_map(|[r, g, b, a]| [b, g, r, a])
+        .collect();
+    out.write_all(&data)
+}
+
+/// Uncompressed 32 bit true-color TGA with a top-left origin. TGA stores
+/// the size in 16 bits, so larger frames are an `InvalidInput` error.
+pub fn write_tga(framebuffer: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+    const UNCOMPRESSED_TRUE_COLOR: u8 = 2;
+    // 8 alpha bits, top-left origin
+    const DESCRIPTOR: u8 = 0x08 | 0x20;
+    let (Ok(width), Ok(height)) = (
+        u16::try_from(framebuffer.width()),
+        u16::try_from(framebuffer.height()),
+    ) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}x{} is too large for TGA",
+                framebuffer.width(),
+                framebuffer.height()
+            ),
+        ));
+    };
+    let mut header = vec![0, 0, UNCOMPRESSED_TRUE_COLOR, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    header.extend_from_slice(&width.to_le_bytes());
+    header.extend_from_slice(&height.to_le_bytes());
+    header.push(32);
+    header.push(DESCRIPTOR);
+    out.write_all(&header)?;
+    let data: Vec<u8> = srgba8_pixels(framebuffer)
+        .flat_map(|[r, g, b, a]| [b, g, r, a])
+        .collect();
+    out.write_all(&data)
+}
+
+/// RGBA8 PNG. The image data is wrapped in stored (uncompressed) deflate
+/// blocks, so no compression library is needed.
+pub fn write_png(framebuffer: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+    const RGBA: u8 = 6;
+    out.write_all(&PNG_SIGNATURE)?;
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(framebuffer.width() as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(framebuffer.height() as u32).to_be_bytes());
+    // bit depth, color type, compression, filter, interlace
+    ihdr.extend_from_slice(&[8, RGBA, 0, 0, 0]);
+    write_png_chunk(out, b"IHDR", &ihdr)?;
+    let row_len = framebuffer.width() * 4;
+    let mut raw = Vec::with_capacity((row_len + 1) * framebuffer.height());
+    let pixels: Vec<u8> = srgba8_pixels(framebuffer).flatten().collect();
+    if row_len > 0 {
+        for row in pixels.chunks(row_len) {
+            // filter type none
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+    }
+    write_png_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+    write_png_chunk(out, b"IEND", &[])
+}
+
+fn write_png_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32_update(crc32_update(0xffffffff, kind), data) ^ 0xffffffff;
+    out.write_all(&crc.to_be_bytes())
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = u16::MAX as usize;
+    // deflate, 32K window, no preset dictionary, fastest
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none();
+        out.push(is_final as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Reads a PNG file into a texture, see `read_png`.
+pub fn load_texture(path: impl AsRef<Path>) -> io::Result<Texture> {
+    read_png(&fs::read(path)?)
+}
+
+/// Decodes a PNG of any standard color type and bit depth, interlaced or
+/// not. Color is taken to be sRGB; gamma and color profile chunks are
+/// ignored. Sixteen bit samples are truncated to eight.
+pub fn read_png(bytes: &[u8]) -> io::Result<Texture> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut rest = bytes
+        .strip_prefix(&PNG_SIGNATURE)
+        .ok_or_else(|| invalid("not a PNG file".to_string()))?;
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = vec![];
+    loop {
+        let (kind, data, next) = png_chunk(rest)
+            .ok_or_else(|| invalid("PNG chunk is truncated or fails its checksum".to_string()))?;
+        rest = next;
+        match &kind {
+            b"IHDR" => header = Some(PngHeader::parse(data).map_err(invalid)?),
+            b"PLTE" => palette = data,
+            b"tRNS" => transparency = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            // bit 5 of the first letter marks chunks that are safe to skip
+            _ if kind[0] & 0x20 == 0 => {
+                let name = String::from_utf8_lossy(&kind);
+                return Err(invalid(format!("unknown critical PNG chunk {name}")));
+            }
+            _ => {}
+        }
+    }
+    let header = header.ok_or_else(|| invalid("PNG is missing its header".to_string()))?;
+    let passes: &[[usize; 4]] = if header.interlaced {
+        // x offset, y offset, x step, y step of the Adam7 passes
+        &[
+            [0, 0, 8, 8],
+            [4, 0, 8, 8],
+            [0, 4, 4, 8],
+            [2, 0, 4, 4],
+            [0, 2, 2, 4],
+            [1, 0, 2, 2],
+            [0, 1, 1, 2],
+        ]
+    } else {
+        &[[0, 0, 1, 1]]
+    };
+    let pass_size = |[x0, y0, dx, dy]: [usize; 4]| {
+        let width = (header.width + dx - 1 - x0) / dx;
+        let height = (header.height + dy - 1 - y0) / dy;
+        (width, height)
+    };
+    // the header is checked against the image data before anything is
+    // allocated for it; each row of a pass is a filter byte and its pixels
+    let raw_size = passes.iter().try_fold(0_usize, |total, &pass| {
+        let (width, height) = match pass_size(pass) {
+            (0, _) | (_, 0) => return Some(total),
+            size => size,
+        };
+        let row = width.checked_mul(header.bits_per_pixel())?.div_ceil(8) + 1;
+        total.checked_add(row.checked_mul(height)?)
+    });
+    let rgba_size = header
+        .width
+        .checked_mul(header.height)
+        .and_then(|pixels| pixels.checked_mul(4));
+    let (Some(raw_size), Some(rgba_size)) = (raw_size, rgba_size) else {
+        return Err(invalid("PNG image is too large".to_string()));
+    };
+    let raw = inflate::zlib_decompress(&compressed, raw_size).map_err(invalid)?;
+    if raw.len() < raw_size {
+        return Err(invalid(
+            "PNG image data is truncated or corrupt".to_string(),
+        ));
+    }
+    let mut rgba = vec![0; rgba_size];
+    let mut raw = raw.as_slice();
+    for &[x0, y0, dx, dy] in passes {
+        let (width, height) = pass_size([x0, y0, dx, dy]);
+        if width == 0 || height == 0 {
+            continue;
+        }
+        let pixels = unfilter(&mut raw, width, height, header.bits_per_pixel())
+            .ok_or_else(|| invalid("PNG image data is truncated or corrupt".to_string()))?;
+        let stride = pixels.len() / height;
+        for (y, row) in pixels.chunks_exact(stride).enumerate() {
+            for x in 0..width {
+                let pixel = header
+                    .pixel(row, x, palette, transparency)
+                    .ok_or_else(|| invalid("PNG palette index out of range".to_string()))?;
+                let i = ((y0 + y * dy) * header.width + x0 + x * dx) * 4;
+                rgba[i..i + 4].copy_from_slice(&pixel);
+            }
+        }
+    }
+    Ok(Texture::from_srgba8(header.width, header.height, &rgba))
+}
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Splits off the next chunk as its type, data and the bytes after it.
+fn png_chunk(bytes: &[u8]) -> Option<([u8; 4], &[u8], &[u8])> {
+    let len = u32::from_be_bytes(bytes.get(..4)?.try_into().unwrap()) as usize;
+    let body = bytes.get(4..8 + len)?;
+    let crc = u32::from_be_bytes(bytes.get(8 + len..12 + len)?.try_into().unwrap());
+    if crc32_update(0xffffffff, body) ^ 0xffffffff != crc {
+        return None;
+    }
+    Some((
+        body[..4].try_into().unwrap(),
+        &body[4..],
+        &bytes[12 + len..],
+    ))
+}
+
+struct PngHeader {
+    width: usize,
+    height: usize,
+    depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl PngHeader {
+    fn parse(data: &[u8]) -> Result<Self, String> {
+        let &[w0, w1, w2, w3, h0, h1, h2, h3, depth, color_type, compression, filter, interlace] =
+            data
+        else {
+            return Err("PNG header has the wrong size".to_string());
+        };
+        let width = u32::from_be_bytes([w0, w1, w2, w3]) as usize;
+        let height = u32::from_be_bytes([h0, h1, h2, h3]) as usize;
+        let depths: &[u8] = match color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => return Err(format!("unknown PNG color type {color_type}")),
+        };
+        if !depths.contains(&depth) {
+            return Err(format!(
+                "bit depth {depth} is not allowed for PNG color type {color_type}"
+            ));
+        }
+        if width == 0 || height == 0 {
+            return Err("PNG image is empty".to_string());
+        }
+        if compression != 0 || filter != 0 || interlace > 1 {
+            return Err("unknown PNG compression, filter or interlace method".to_string());
+        }
+        Ok(Self {
+            width,
+            height,
+            depth,
+            color_type,
+            interlaced: interlace == 1,
+        })
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.depth as usize
+    }
+
+    /// Sample `channel` of pixel `x`, at the file's bit depth.
+    fn sample(&self, row: &[u8], x: usize, channel: usize) -> u16 {
+        let index = x * self.channels() + channel;
+        match self.depth {
+            16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+            8 => row[index] as u16,
+            depth => {
+                let bit = index * depth as usize;
+                let shift = 8 - depth as usize - bit % 8;
+                ((row[bit / 8] >> shift) & ((1 << depth) - 1)) as u16
+            }
+        }
+    }
+
+    /// Pixel `x` of an unfiltered row as sRGB bytes with alpha.
+    fn pixel(&self, row: &[u8], x: usize, palette: &[u8], transparency: &[u8]) -> Option<[u8; 4]> {
+        let sample = |channel| self.sample(row, x, channel);
+        let to_u8 = |value: u16| match self.depth {
+            16 => (value >> 8) as u8,
+            depth => (value as u32 * 255 / ((1 << depth) - 1)) as u8,
+        };
+        // tRNS holds a single fully transparent color for types without alpha
+        let transparent = |values: &[u16]| {
+            transparency.len() == values.len() * 2
+                && values.iter().enumerate().all(|(i, value)| {
+                    u16::from_be_bytes([transparency[i * 2], transparency[i * 2 + 1]]) == *value
+                })
+        };
+        let opaque = |values: &[u16]| if transparent(values) { 0 } else { 255 };
+        Some(match self.color_type {
+            0 => {
+                let gray = sample(0);
+                let value = to_u8(gray);
+                [value, value, value, opaque(&[gray])]
+            }
+            2 => {
+                let rgb = [sample(0), sample(1), sample(2)];
+                let [r, g, b] = rgb.map(to_u8);
+                [r, g, b, opaque(&rgb)]
+            }
+            3 => {
+                let index = sample(0) as usize;
+                let rgb = palette.get(index * 3..index * 3 + 3)?;
+                let alpha = transparency.get(index).copied().unwrap_or(255);
+                [rgb[0], rgb[1], rgb[2], alpha]
+            }
+            4 => {
+                let value = to_u8(sample(0));
+                [value, value, value, to_u8(sample(1))]
+            }
+            _ => [sample(0), sample(1), sample(2), sample(3)].map(to_u8),
+        })
+    }
+}
+
+/// Reverses the per row filters of one (pass of an) image, consuming its
+/// bytes from `raw`.
+fn unfilter(
+    raw: &mut &[u8],
+    width: usize,
+    height: usize,
+    bits_per_pixel: usize,
+) -> Option<Vec<u8>> {
+    let stride = (width * bits_per_pixel).div_ceil(8);
+    // filters work on whole bytes, pairing a byte with the one a pixel back
+    let back = bits_per_pixel.div_ceil(8);
+    let data = raw.get(..(stride + 1) * height)?;
+    *raw = &raw[data.len()..];
+    let mut pixels = vec![0u8; stride * height];
+    for (y, line) in data.chunks_exact(stride + 1).enumerate() {
+        let (done, current) = pixels.split_at_mut(y * stride);
+        let previous = done.get(done.len().wrapping_sub(stride)..);
+        let current = &mut current[..stride];
+        for i in 0..stride {
+            let left = if i >= back { current[i - back] } else { 0 };
+            let up = previous.map_or(0, |row| row[i]);
+            let up_left = match previous {
+                Some(row) if i >= back => row[i - back],
+                _ => 0,
+            };
+            let predicted = match line[0] {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return None,
+            };
+            current[i] = line[1 + i].wrapping_add(predicted);
+        }
+    }
+    Some(pixels)
+}
+
+/// Whichever neighbor is closest to left + up - up left.
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let distance = |value: u8| (estimate - value as i16).abs();
+    if distance(left) <= distance(up) && distance(left) <= distance(up_left) {
+        left
+    } else if distance(up) <= distance(up_left) {
+        up
+    } else {
+        up_left
+    }
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+pub(crate) fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let mut a = 1;
+    let mut b = 0;
+    // 5552 is the largest run that cannot overflow before taking the modulo
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+fn srgba8_pixels(framebuffer: &Framebuffer) -> impl Iterator<Item = [u8; 4]> + '_ {
+    framebuffer.pixels().iter().map(|pixel| pixel.to_srgba8())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Color, Rgba};
+
+    fn two_pixels() -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(2, 1, &Rgba::from(&Color::Red));
+        framebuffer.set(1, 0, Rgba::color_a(0.0, 0.0, 1.0, 0.5));
+        framebuffer
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32_update(0xffffffff, b"IEND") ^ 0xffffffff, 0xae426082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn test_ppm() {
+        let mut out = vec![];
+        write_ppm(&two_pixels(), &mut out).unwrap();
+        let mut target = b"P6\n2 1\n255\n".to_vec();
+        target.extend_from_slice(&[255, 0, 0, 0, 0, 255]);
+        assert_eq!(out, target);
+    }
+
+    #[test]
+    fn test_pam_keeps_alpha() {
+        let mut out = vec![];
+        write_pam(&two_pixels(), &mut out).unwrap();
+        assert!(out.starts_with(b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 4\n"));
+        assert_eq!(out[out.len() - 8..], [255, 0, 0, 255, 0, 0, 255, 128]);
+    }
+
+    #[test]
+    fn test_bmp_and_tga_layout() {
+        let mut bmp = vec![];
+        write_bmp(&two_pixels(), &mut bmp).unwrap();
+        assert_eq!(&bmp[..2], b"BM");
+        assert_eq!(bmp.len(), 14 + 108 + 8);
+        assert_eq!(bmp[bmp.len() - 8..], [0, 0, 255, 255, 255, 0, 0, 128]);
+        let mut tga = vec![];
+        write_tga(&two_pixels(), &mut tga).unwrap();
+        assert_eq!(tga.len(), 18 + 8);
+        assert_eq!(tga[tga.len() - 8..], [0, 0, 255, 255, 255, 0, 0, 128]);
+        let wide = Framebuffer::new(1 << 16, 1, &Rgba::from(&Color::Black));
+        let error = write_tga(&wide, &mut vec![]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        // empty, so nothing is allocated, but still too wide for the header
+        let wide = Framebuffer::new(1 << 31, 0, &Rgba::from(&Color::Black));
+        let error = write_bmp(&wide, &mut vec![]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_png_structure() {
+        let mut out = vec![];
+        write_png(&two_pixels(), &mut out).unwrap();
+        assert_eq!(
+            out[..8],
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']
+        );
+        assert_eq!(&out[12..16], b"IHDR");
+        assert_eq!(&out[37..41], b"IDAT");
+        let idat_len = u32::from_be_bytes(out[33..37].try_into().unwrap()) as usize;
+        let zlib = &out[41..41 + idat_len];
+        // header, one final stored block of 9 bytes, adler32
+        assert_eq!(zlib[..7], [0x78, 0x01, 1, 9, 0, 0xf6, 0xff]);
+        let raw = [0, 255, 0, 0, 255, 0, 0, 255, 128];
+        assert_eq!(zlib[7..16], raw);
+        assert_eq!(zlib[16..], adler32(&raw).to_be_bytes());
+        assert_eq!(&out[out.len() - 8..out.len() - 4], b"IEND");
+    }
+
+    /// PNG with the given header fields, extra chunks before the image data
+    /// and already filtered image data.
+    fn png(
+        size: [u32; 2],
+        depth_and_type: [u8; 2],
+        interlace: u8,
+        chunks: &[(&[u8; 4], &[u8])],
+        raw: &[u8],
+    ) -> Vec<u8> {
+        let mut out = PNG_SIGNATURE.to_vec();
+        let mut ihdr = vec![];
+        ihdr.extend_from_slice(&size[0].to_be_bytes());
+        ihdr.extend_from_slice(&size[1].to_be_bytes());
+        ihdr.extend_from_slice(&[depth_and_type[0], depth_and_type[1], 0, 0, interlace]);
+        write_png_chunk(&mut out, b"IHDR", &ihdr).unwrap();
+        for (kind, data) in chunks {
+            write_png_chunk(&mut out, kind, data).unwrap();
+        }
+        write_png_chunk(&mut out, b"IDAT", &zlib_stored(raw)).unwrap();
+        write_png_chunk(&mut out, b"IEND", &[]).unwrap();
+        out
+    }
+
+    fn srgba8(texture: &Texture) -> Vec<[u8; 4]> {
+        texture.texels().iter().map(Rgba::to_srgba8).collect()
+    }
+
+    fn gray(value: u8) -> [u8; 4] {
+        [value, value, value, 255]
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let mut out = vec![];
+        write_png(&two_pixels(), &mut out).unwrap();
+        let texture = read_png(&out).unwrap();
+        assert_eq!((texture.width(), texture.height()), (2, 1));
+        assert_eq!(srgba8(&texture), vec![[255, 0, 0, 255], [0, 0, 255, 128]]);
+    }
+
+    #[test]
+    fn test_png_filters() {
+        #[rustfmt::skip]
+        let raw = [
+            1, 10, 10, 10, // sub
+            4, 30, 10, 10, // paeth
+            3, 50, 20, 20, // average
+        ];
+        let texture = read_png(&png([3, 3], [8, 0], 0, &[], &raw)).unwrap();
+        let expected: Vec<_> = [10, 20, 30, 40, 50, 60, 70, 80, 90].map(gray).into();
+        assert_eq!(srgba8(&texture), expected);
+    }
+
+    #[test]
+    fn test_png_palette() {
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        // two bit indices 0 1 2 then 2 1 0, the second row filtered as up
+        let raw = [0, 0b0001_1000, 2, 0b1001_0000u8.wrapping_sub(0b0001_1000)];
+        let chunks: [(&[u8; 4], &[u8]); 2] = [(b"PLTE", &palette), (b"tRNS", &[255, 128])];
+        let texture = read_png(&png([3, 2], [2, 3], 0, &chunks, &raw)).unwrap();
+        let (red, green, blue) = ([255, 0, 0, 255], [0, 255, 0, 128], [0, 0, 255, 255]);
+        assert_eq!(srgba8(&texture), vec![red, green, blue, blue, green, red]);
+    }
+
+    #[test]
+    fn test_png_interlaced() {
+        // the Adam7 passes of a 3x3 image whose pixels count up
+        #[rustfmt::skip]
+        let raw = [
+            0, 0,
+            0, 2,
+            0, 6, 8,
+            0, 1, 0, 7,
+            0, 3, 4, 5,
+        ];
+        let texture = read_png(&png([3, 3], [8, 0], 1, &[], &raw)).unwrap();
+        let expected: Vec<_> = [0, 1, 2, 3, 4, 5, 6, 7, 8].map(gray).into();
+        assert_eq!(srgba8(&texture), expected);
+    }
+
+    #[test]
+    fn test_png_errors() {
+        assert!(read_png(b"not a png").is_err());
+        let mut good = png([1, 1], [8, 0], 0, &[], &[0, 0]);
+        assert!(read_png(&good).is_ok());
+        // any flipped bit fails the chunk checksum
+        good[45] ^= 1;
+        assert!(read_png(&good).is_err());
+        assert!(read_png(&png([1, 1], [8, 0], 0, &[], &[0])).is_err());
+        assert!(read_png(&png([1, 1], [4, 2], 0, &[], &[0, 0])).is_err());
+        assert!(read_png(&png([1, 1], [8, 0], 0, &[(b"CRIT", &[])], &[0, 0])).is_err());
+        // a huge header is measured against the data instead of allocated
+        let huge = read_png(&png([60000, 60000], [8, 6], 0, &[], &[0, 0])).unwrap_err();
+        assert_eq!(huge.to_string(), "PNG image data is truncated or corrupt");
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("frame.PNG")),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(ImageFormat::from_path(Path::new("frame.jpg")), None);
+        assert_eq!(ImageFormat::from_path(Path::new("frame")), None);
+    }
+}
@@ -0,0 +1,351 @@
+//! DEFLATE decompression (RFC 1951) in the zlib wrapper (RFC 1950), for
+//! reading PNG images. Decoding follows zlib's `puff` reference decoder:
+//! canonical Huffman codes are decoded a bit at a time, favoring clarity
+//! over speed.
+
+use crate::image::adler32;
+
+const MAX_BITS: usize = 15;
+
+/// Base lengths and extra bits for length codes 257..285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Base offsets and extra bits for distance codes 0..29.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which code length code lengths are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Unwraps and decompresses a zlib stream, checking its Adler-32. Streams
+/// that inflate to more than `limit` bytes are an error.
+pub fn zlib_decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let [cmf, flg, ..] = *data else {
+        return Err("zlib stream is too short".to_string());
+    };
+    if cmf & 0x0f != 8 || (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        return Err("not a deflate zlib stream".to_string());
+    }
+    if flg & 0x20 != 0 {
+        return Err("zlib preset dictionaries are not supported".to_string());
+    }
+    let (out, used) = inflate(&data[2..], limit)?;
+    let checksum = data
+        .get(2 + used..2 + used + 4)
+        .ok_or("zlib stream is missing its checksum")?;
+    if u32::from_be_bytes(checksum.try_into().unwrap()) != adler32(&out) {
+        return Err("zlib checksum mismatch".to_string());
+    }
+    Ok(out)
+}
+
+/// Decompresses raw deflate data, returning the output and the number of
+/// input bytes consumed. Output beyond `limit` bytes is an error, so a small
+/// stream cannot exhaust memory.
+pub fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize), String> {
+    let mut bits = Bits {
+        data,
+        position: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut out = vec![];
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => stored(&mut bits, &mut out, limit)?,
+            1 => {
+                let (lengths, distances) = fixed_codes();
+                codes(&mut bits, &mut out, &lengths, &distances, limit)?;
+            }
+            2 => {
+                let (lengths, distances) = dynamic_codes(&mut bits)?;
+                codes(&mut bits, &mut out, &lengths, &distances, limit)?;
+            }
+            _ => return Err("invalid deflate block type".to_string()),
+        }
+        if last {
+            return Ok((out, bits.position));
+        }
+    }
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn take(&mut self, needed: u32) -> Result<u32, String> {
+        while self.count < needed {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or("deflate data ends early")?;
+            self.position += 1;
+            self.buffer |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << needed) - 1);
+        self.buffer >>= needed;
+        self.count -= needed;
+        Ok(value)
+    }
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>, limit: usize) -> Result<(), String> {
+    // stored blocks start on a byte boundary
+    bits.buffer = 0;
+    bits.count = 0;
+    let header = bits
+        .data
+        .get(bits.position..bits.position + 4)
+        .ok_or("deflate data ends early")?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err("corrupt stored deflate block".to_string());
+    }
+    bits.position += 4;
+    let block = bits
+        .data
+        .get(bits.position..bits.position + len as usize)
+        .ok_or("deflate data ends early")?;
+    if out.len() + block.len() > limit {
+        return Err(too_long(limit));
+    }
+    out.extend_from_slice(block);
+    bits.position += len as usize;
+    Ok(())
+}
+
+/// Canonical Huffman code: how many symbols have each length, and the
+/// symbols ordered by code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, String> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        // reject over-subscribed codes; incomplete ones are allowed
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = 2 * left - i32::from(count);
+            if left < 0 {
+                return Err("over-subscribed Huffman code".to_string());
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..=MAX_BITS {
+            code |= bits.take(1)? as i32;
+            let count = i32::from(self.counts[length]);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    // the fixed tables are always valid
+    (
+        Huffman::new(&lengths).unwrap(),
+        Huffman::new(&[5; 30]).unwrap(),
+    )
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let literal_count = bits.take(5)? as usize + 257;
+    let distance_count = bits.take(5)? as usize + 1;
+    let code_count = bits.take(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err("too many deflate codes".to_string());
+    }
+    let mut code_lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_count] {
+        code_lengths[index] = bits.take(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths)?;
+    let mut lengths = vec![];
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_lengths.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or("length repeat with no previous length")?;
+                (previous, 3 + bits.take(2)?)
+            }
+            17 => (0, 3 + bits.take(3)?),
+            _ => (0, 11 + bits.take(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err("deflate code lengths overrun".to_string());
+    }
+    if lengths[256] == 0 {
+        return Err("deflate block has no end code".to_string());
+    }
+    Ok((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..])?,
+    ))
+}
+
+fn codes(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    lengths: &Huffman,
+    distances: &Huffman,
+    limit: usize,
+) -> Result<(), String> {
+    loop {
+        let symbol = lengths.decode(bits)? as usize;
+        match symbol {
+            0..=255 if out.len() == limit => return Err(too_long(limit)),
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    return Err("invalid deflate length code".to_string());
+                }
+                let length = LENGTH_BASE[index] as usize
+                    + bits.take(u32::from(LENGTH_EXTRA[index]))? as usize;
+                let index = distances.decode(bits)? as usize;
+                if index >= DISTANCE_BASE.len() {
+                    return Err("invalid deflate distance code".to_string());
+                }
+                let distance = DISTANCE_BASE[index] as usize
+                    + bits.take(u32::from(DISTANCE_EXTRA[index]))? as usize;
+                if distance > out.len() {
+                    return Err("deflate distance reaches before the start".to_string());
+                }
+                if out.len() + length > limit {
+                    return Err(too_long(limit));
+                }
+                // copies may overlap their own output
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+fn too_long(limit: usize) -> String {
+    format!("deflate data inflates past the expected {limit} bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_and_stored() {
+        // zlib.compress(b"hello hello hello hello")
+        let compressed = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01, 0x68, 0x03,
+            0x08, 0xb1,
+        ];
+        assert_eq!(
+            zlib_decompress(&compressed, usize::MAX).unwrap(),
+            b"hello hello hello hello"
+        );
+        assert_eq!(
+            zlib_decompress(&compressed, 22).unwrap_err(),
+            "deflate data inflates past the expected 22 bytes"
+        );
+        // zlib.compress(b"abc", 0)
+        let stored = [
+            0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, 0x61, 0x62, 0x63, 0x02, 0x4d, 0x01, 0x27,
+        ];
+        assert_eq!(zlib_decompress(&stored, usize::MAX).unwrap(), b"abc");
+        assert!(zlib_decompress(&stored, 2).is_err());
+    }
+
+    #[test]
+    fn test_dynamic() {
+        let text: Vec<String> = (0..40)
+            .map(|i| format!("the quick brown fox {i} jumps over the lazy dog"))
+            .collect();
+        // zlib.compress(text, 9)
+        let compressed = [
+            0x78, 0xda, 0x95, 0xd4, 0xc9, 0x0d, 0xc2, 0x50, 0x10, 0x04, 0xd1, 0x54, 0x26, 0x04,
+            0xa6, 0x1b, 0xb3, 0x84, 0x03, 0xd8, 0xec, 0xf0, 0xc1, 0xd8, 0x66, 0x89, 0x1e, 0xc4,
+            0x11, 0x89, 0x43, 0x1d, 0x47, 0xaa, 0xdb, 0x53, 0x4f, 0xb7, 0x6d, 0xe2, 0xda, 0xef,
+            0x56, 0x87, 0x58, 0xb6, 0xe5, 0x7e, 0x8e, 0x75, 0x79, 0xc4, 0x28, 0xf6, 0xfd, 0xe9,
+            0x72, 0x8b, 0x32, 0x34, 0x6d, 0x74, 0x9f, 0xe0, 0xb8, 0x78, 0x3d, 0xa3, 0x2e, 0x9b,
+            0xef, 0xf1, 0x5b, 0x27, 0xaa, 0x85, 0x6a, 0xa3, 0x7a, 0x8c, 0xea, 0x0a, 0xd5, 0x13,
+            0x54, 0x4f, 0x51, 0x3d, 0x43, 0xf5, 0x9c, 0xe9, 0x40, 0x4c, 0xa6, 0x99, 0x8c, 0x33,
+            0x99, 0x67, 0x32, 0xd0, 0x64, 0xa2, 0xc9, 0x48, 0x93, 0x99, 0x26, 0x43, 0x4d, 0xa6,
+            0x2a, 0xa6, 0x2a, 0xb8, 0x51, 0xa6, 0x2a, 0xa6, 0x2a, 0xa6, 0x2a, 0xa6, 0x2a, 0xa6,
+            0x2a, 0xa6, 0x2a, 0xa6, 0x2a, 0xa6, 0x6a, 0xa6, 0x6a, 0xa6, 0x6a, 0xf8, 0x7a, 0x99,
+            0xaa, 0x99, 0xaa, 0x99, 0xaa, 0x99, 0xaa, 0x99, 0xaa, 0x99, 0xaa, 0xff, 0xaa, 0xbe,
+            0x01, 0x96, 0x77, 0x96, 0xf7,
+        ];
+        assert_eq!(
+            zlib_decompress(&compressed, usize::MAX).unwrap(),
+            text.join(" ").as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut stored = [
+            0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, 0x61, 0x62, 0x63, 0x02, 0x4d, 0x01, 0x27,
+        ];
+        stored[8] = b'x';
+        assert_eq!(
+            zlib_decompress(&stored, usize::MAX).unwrap_err(),
+            "zlib checksum mismatch"
+        );
+        assert!(zlib_decompress(&[0x78, 0x9c, 0xff], usize::MAX).is_err());
+    }
+}
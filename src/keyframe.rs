@@ -0,0 +1,624 @@
+use crate::animation::Animator;
+use crate::color::Color;
+use crate::geometry::{point, Geometry};
+use crate::math::{self, Quaternion};
+use crate::text_file::{self, LineError};
+use std::f32::consts::PI;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// How a track moves from one keyframe to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    /// Holds the value until the next keyframe.
+    Step,
+    Linear,
+    /// Smooth curve through the keyframes, using the keyframes on either side
+    /// of the segment to shape it.
+    CatmullRom,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    /// Reaches the next value early and bounces back to it a few times, like
+    /// a dropped ball.
+    Bounce,
+}
+
+impl Easing {
+    const NAMES: [(Easing, &'static str); 7] = [
+        (Easing::Step, "step"),
+        (Easing::Linear, "linear"),
+        (Easing::CatmullRom, "catmull_rom"),
+        (Easing::EaseIn, "ease_in"),
+        (Easing::EaseOut, "ease_out"),
+        (Easing::EaseInOut, "ease_in_out"),
+        (Easing::Bounce, "bounce"),
+    ];
+
+    pub fn name(self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(easing, _)| *easing == self)
+            .map_or("linear", |(_, name)| name)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(easing, _)| *easing)
+    }
+
+    /// Maps the fraction `s` of a segment, in [0, 1], to the fraction of the
+    /// way between the two values. Catmull-Rom is not a curve on its own and
+    /// is handled by `Track::sample`.
+    fn ease(self, s: f32) -> f32 {
+        match self {
+            Easing::Step => 0.0,
+            Easing::Linear | Easing::CatmullRom => s,
+            Easing::EaseIn => s * s,
+            Easing::EaseOut => s * (2.0 - s),
+            Easing::EaseInOut => s * s * (3.0 - 2.0 * s),
+            Easing::Bounce => bounce(s),
+        }
+    }
+}
+
+/// The usual ease out bounce: a falling curve hitting 1 at s = 1/2.75 and
+/// then three shrinking bounces.
+fn bounce(s: f32) -> f32 {
+    const N: f32 = 7.5625;
+    const D: f32 = 2.75;
+    if s < 1.0 / D {
+        N * s * s
+    } else if s < 2.0 / D {
+        let s = s - 1.5 / D;
+        N * s * s + 0.75
+    } else if s < 2.5 / D {
+        let s = s - 2.25 / D;
+        N * s * s + 0.9375
+    } else {
+        let s = s - 2.625 / D;
+        N * s * s + 0.984375
+    }
+}
+
+/// A value at a point in time. `easing` shapes the way to the next keyframe.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe<const N: usize> {
+    pub time: f32,
+    pub value: [f32; N],
+    pub easing: Easing,
+}
+
+/// Keyframes of one property, kept sorted by time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track<const N: usize> {
+    keys: Vec<Keyframe<N>>,
+}
+
+impl<const N: usize> Track<N> {
+    /// Adds a keyframe, replacing any other at the same time.
+    pub fn insert(&mut self, key: Keyframe<N>) {
+        let index = self.keys.partition_point(|k| k.time < key.time);
+        match self.keys.get(index) {
+            Some(k) if k.time == key.time => self.keys[index] = key,
+            _ => self.keys.insert(index, key),
+        }
+    }
+
+    pub fn keys(&self) -> &[Keyframe<N>] {
+        &self.keys
+    }
+
+    /// Time of the last keyframe.
+    pub fn end(&self) -> f32 {
+        self.keys.last().map_or(0.0, |k| k.time)
+    }
+
+    /// The value at `time`, holding the first and last values outside the
+    /// keyframes. `None` for an empty track.
+    pub fn sample(&self, time: f32) -> Option<[f32; N]> {
+        let (next, s) = match self.segment(time)? {
+            Segment::Hold(key) => return Some(key.value),
+            Segment::Between(next, s) => (next, s),
+        };
+        let (k0, k1) = (&self.keys[next - 1], &self.keys[next]);
+        let h = k1.time - k0.time;
+        let mut value = [0.0; N];
+        if k0.easing == Easing::CatmullRom {
+            // cubic Hermite with tangents from the neighbouring keyframes
+            let m0 = self.tangent(next - 1);
+            let m1 = self.tangent(next);
+            let (s2, s3) = (s * s, s * s * s);
+            let h00 = 2.0 * s3 - 3.0 * s2 + 1.0;
+            let h10 = s3 - 2.0 * s2 + s;
+            let h01 = -2.0 * s3 + 3.0 * s2;
+            let h11 = s3 - s2;
+            for (i, v) in value.iter_mut().enumerate() {
+                *v = h00 * k0.value[i] + h10 * h * m0[i] + h01 * k1.value[i] + h11 * h * m1[i];
+            }
+        } else {
+            let t = k0.easing.ease(s);
+            for (i, v) in value.iter_mut().enumerate() {
+                *v = k0.value[i] + (k1.value[i] - k0.value[i]) * t;
+            }
+        }
+        Some(value)
+    }
+
+    /// Where `time` falls: on a held value before the first or after the last
+    /// keyframe, or the fraction of the way from keyframe `next - 1` to `next`.
+    fn segment(&self, time: f32) -> Option<Segment<'_, N>> {
+        let next = self.keys.partition_point(|k| k.time <= time);
+        if next == 0 || next == self.keys.len() {
+            let key = self
+                .keys
+                .get(next.saturating_sub(1))
+                .or(self.keys.first())?;
+            return Some(Segment::Hold(key));
+        }
+        let (k0, k1) = (&self.keys[next - 1], &self.keys[next]);
+        Some(Segment::Between(
+            next,
+            (time - k0.time) / (k1.time - k0.time),
+        ))
+    }
+
+    /// Rate of change at keyframe `i`, from its neighbours, or one sided at
+    /// the ends.
+    fn tangent(&self, i: usize) -> [f32; N] {
+        let before = &self.keys[i.saturating_sub(1)];
+        let after = &self.keys[(i + 1).min(self.keys.len() - 1)];
+        let dt = after.time - before.time;
+        let mut tangent = [0.0; N];
+        if dt > 0.0 {
+            for (j, t) in tangent.iter_mut().enumerate() {
+                *t = (after.value[j] - before.value[j]) / dt;
+            }
+        }
+        tangent
+    }
+}
+
+enum Segment<'a, const N: usize> {
+    Hold(&'a Keyframe<N>),
+    Between(usize, f32),
+}
+
+impl Track<3> {
+    /// Samples a track of Euler angles, as taken by `Geometry::rotation`, as
+    /// an orientation. Between keyframes less than half a turn apart on every
+    /// axis this is a spherical interpolation, turning the shortest way at a
+    /// steady rate shaped by the easing. Larger steps, which would otherwise
+    /// be cut short or lost entirely (a full turn slerps to no turn at all),
+    /// and Catmull-Rom segments, which need their neighbours for the curve,
+    /// interpolate the angles themselves as `sample` does.
+    pub fn sample_orientation(&self, time: f32) -> Option<Quaternion> {
+        let orientation = |[x, y, z]: [f32; 3]| math::euler_quaternion(x, y, z);
+        match self.segment(time)? {
+            Segment::Hold(key) => Some(orientation(key.value)),
+            Segment::Between(next, s) => {
+                let (k0, k1) = (&self.keys[next - 1], &self.keys[next]);
+                let half_turn = (0..3).any(|i| (k1.value[i] - k0.value[i]).abs() >= PI);
+                if half_turn || k0.easing == Easing::CatmullRom {
+                    return self.sample(time).map(orientation);
+                }
+                let t = k0.easing.ease(s);
+                Some(orientation(k0.value).slerp(&orientation(k1.value), t))
+            }
+        }
+    }
+}
+
+/// What a clip does once the time passes its last keyframe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Playback {
+    /// Plays once and holds the final pose.
+    #[default]
+    Once,
+    /// Starts over from the beginning.
+    Loop,
+    /// Plays backwards to the beginning, then forwards again.
+    PingPong,
+}
+
+/// Keyframed animation of an object's transform and color. Each track that is
+/// present replaces the matching resting value; rotations are Euler angles in
+/// radians as taken by `Geometry::rotation`, interpolated with
+/// `Track::sample_orientation`, and colors are RGBA.
+///
+/// A clip is an `Animator`, so it is attached with `Geometry::set_animation`
+/// and driven by the time passed to the renderer.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Clip {
+    pub playback: Playback,
+    pub translation: Track<3>,
+    pub rotation: Track<3>,
+    pub scale: Track<3>,
+    pub color: Track<4>,
+}
+
+impl Clip {
+    /// Time of the last keyframe in any track.
+    pub fn duration(&self) -> f32 {
+        [
+            self.translation.end(),
+            self.rotation.end(),
+            self.scale.end(),
+            self.color.end(),
+        ]
+        .into_iter()
+        .fold(0.0, f32::max)
+    }
+
+    /// Maps the time since the clip started to a time within it.
+    pub fn local_time(&self, secs: f32) -> f32 {
+        let duration = self.duration();
+        if duration <= 0.0 {
+            return 0.0;
+        }
+        match self.playback {
+            Playback::Once => secs.clamp(0.0, duration),
+            Playback::Loop => secs.rem_euclid(duration),
+            Playback::PingPong => {
+                let t = secs.rem_euclid(2.0 * duration);
+                if t > duration {
+                    2.0 * duration - t
+                } else {
+                    t
+                }
+            }
+        }
+    }
+}
+
+impl Animator for Clip {
+    fn animate(&mut self, geometry: &mut Geometry, secs: f32) {
+        let time = self.local_time(secs);
+        if let Some([x, y, z]) = self.translation.sample(time) {
+            geometry.set_position(point(x, y, z));
+        }
+        if let Some(orientation) = self.rotation.sample_orientation(time) {
+            geometry.set_orientation(orientation);
+        }
+        if let Some(scale) = self.scale.sample(time) {
+            geometry.scale(scale.into());
+        }
+        if let Some([r, g, b, a]) = self.color.sample(time) {
+            geometry.set_color(Color::Custom(r, g, b, a));
+        }
+    }
+}
+
+/// Reads an animation file, see `parse`.
+pub fn load(path: impl AsRef<Path>) -> Result<Clip, LineError> {
+    text_file::load(path, parse)
+}
+
+/// Builds a clip from its text description.
+///
+/// Each line holds one statement; `#` starts a comment. A track lists its
+/// keyframes as `key <time> <easing> <value>`, where the easing is one of
+/// `step`, `linear`, `catmull_rom`, `ease_in`, `ease_out`, `ease_in_out` or
+/// `bounce`. Rotations are in degrees.
+///
+/// ```text
+/// playback ping_pong                  # once, loop or ping_pong
+///
+/// track translation
+///   key 0 ease_in_out 0 0 0
+///   key 2 linear 300 0 0
+/// end
+///
+/// track rotation                      # also scale and color (r g b a)
+///   key 0 catmull_rom 0 0 0
+///   key 1 catmull_rom 0 0 90
+///   key 2 catmull_rom 0 0 0
+/// end
+/// ```
+pub fn parse(source: &str) -> Result<Clip, LineError> {
+    let mut clip = Clip::default();
+    let mut track: Option<(&str, usize)> = None;
+    let mut line_number = 0;
+    for (i, line) in source.lines().enumerate() {
+        line_number = i + 1;
+        let error = |message: String| LineError::Parse {
+            line: i + 1,
+            message,
+        };
+        let line = line.split('#').next().unwrap_or_default();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match (track, words.as_slice()) {
+            (_, []) => {}
+            (None, ["playback", playback]) => {
+                clip.playback = match *playback {
+                    "once" => Playback::Once,
+                    "loop" => Playback::Loop,
+                    "ping_pong" => Playback::PingPong,
+                    _ => return Err(error("expected `once`, `loop` or `ping_pong`".to_string())),
+                }
+            }
+            (None, ["track", name]) => {
+                let size = match *name {
+                    "translation" | "rotation" | "scale" => 3,
+                    "color" => 4,
+                    _ => {
+                        return Err(error(format!(
+                            "unknown track `{name}`, expected `translation`, `rotation`, \
+                             `scale` or `color`"
+                        )))
+                    }
+                };
+                track = Some((name, size));
+            }
+            (Some(_), ["end"]) => track = None,
+            (Some((name, size)), ["key", time, easing, values @ ..]) => {
+                let number = |word: &str| {
+                    word.parse::<f32>()
+                        .map_err(|_| error(format!("`{word}` is not a number")))
+                };
+                let time = number(time)?;
+                let easing = Easing::from_name(easing)
+                    .ok_or_else(|| error(format!("unknown easing `{easing}`")))?;
+                if values.len() != size {
+                    return Err(error(format!(
+                        "expected {size} numbers, found {}",
+                        values.len()
+                    )));
+                }
+                let mut value = [0.0; 4];
+                for (v, word) in value.iter_mut().zip(values) {
+                    *v = number(word)?;
+                }
+                let [x, y, z, w] = value;
+                let key = |value| Keyframe {
+                    time,
+                    value,
+                    easing,
+                };
+                match name {
+                    "translation" => clip.translation.insert(key([x, y, z])),
+                    "rotation" => {
+                        let radians = [x, y, z].map(|angle| angle * PI / 180.0);
+                        clip.rotation.insert(key(radians))
+                    }
+                    "scale" => clip.scale.insert(key([x, y, z])),
+                    _ => clip.color.insert(Keyframe {
+                        time,
+                        value: [x, y, z, w],
+                        easing,
+                    }),
+                }
+            }
+            (Some(_), [keyword, ..]) => {
+                return Err(error(format!("unknown track statement `{keyword}`")))
+            }
+            (None, ["end", ..]) => return Err(error("`end` without a `track`".to_string())),
+            (None, [keyword, ..]) => return Err(error(format!("unknown statement `{keyword}`"))),
+        }
+    }
+    if track.is_some() {
+        return Err(LineError::Parse {
+            line: line_number,
+            message: "track is missing its `end`".to_string(),
+        });
+    }
+    Ok(clip)
+}
+
+/// Saves an animation file, see `write`.
+pub fn save(clip: &Clip, path: impl AsRef<Path>) -> io::Result<()> {
+    text_file::save(path, |out| write(clip, out))
+}
+
+/// Writes a clip in the format read by `parse`, leaving out empty tracks.
+pub fn write(clip: &Clip, out: &mut impl Write) -> io::Result<()> {
+    let playback = match clip.playback {
+        Playback::Once => "once",
+        Playback::Loop => "loop",
+        Playback::PingPong => "ping_pong",
+    };
+    writeln!(out, "playback {playback}")?;
+    let to_degrees = |value: [f32; 3]| value.map(|angle| angle * 180.0 / PI);
+    write_track(out, "translation", &clip.translation, |v| v)?;
+    write_track(out, "rotation", &clip.rotation, to_degrees)?;
+    write_track(out, "scale", &clip.scale, |v| v)?;
+    write_track(out, "color", &clip.color, |v| v)
+}
+
+fn write_track<const N: usize>(
+    out: &mut impl Write,
+    name: &str,
+    track: &Track<N>,
+    convert: impl Fn([f32; N]) -> [f32; N],
+) -> io::Result<()> {
+    if track.keys().is_empty() {
+        return Ok(());
+    }
+    writeln!(out)?;
+    writeln!(out, "track {name}")?;
+    for key in track.keys() {
+        write!(out, "  key {} {}", key.time, key.easing.name())?;
+        for value in convert(key.value) {
+            write!(out, " {value}")?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "end")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::triangle;
+    use crate::math::f32_equals;
+
+    fn track(keys: &[(f32, f32, Easing)]) -> Track<1> {
+        let mut track = Track::default();
+        for &(time, value, easing) in keys {
+            track.insert(Keyframe {
+                time,
+                value: [value],
+                easing,
+            });
+        }
+        track
+    }
+
+    fn assert_sample(track: &Track<1>, time: f32, expected: f32) {
+        let [value] = track.sample(time).unwrap();
+        assert!(
+            f32_equals(value, expected),
+            "{value} != {expected} at {time}"
+        );
+    }
+
+    #[test]
+    fn test_easing() {
+        for easing in Easing::NAMES.map(|(easing, _)| easing) {
+            let t = track(&[(1.0, 10.0, easing), (3.0, 20.0, easing)]);
+            // held outside the keyframes
+            assert_sample(&t, 0.0, 10.0);
+            assert_sample(&t, 5.0, 20.0);
+            assert_sample(&t, 1.0, 10.0);
+            assert_eq!(Easing::from_name(easing.name()), Some(easing));
+        }
+        let halfway = |easing| {
+            let t = track(&[(0.0, 0.0, easing), (1.0, 1.0, easing)]);
+            t.sample(0.5).unwrap()[0]
+        };
+        assert_eq!(halfway(Easing::Step), 0.0);
+        assert_eq!(halfway(Easing::Linear), 0.5);
+        assert_eq!(halfway(Easing::EaseIn), 0.25);
+        assert_eq!(halfway(Easing::EaseOut), 0.75);
+        assert_eq!(halfway(Easing::EaseInOut), 0.5);
+        assert!(bounce(0.9) < 1.0 && f32_equals(bounce(1.0), 1.0));
+        assert!(f32_equals(bounce(1.0 / 2.75), 1.0));
+        assert!(track(&[]).sample(0.0).is_none());
+    }
+
+    #[test]
+    fn test_catmull_rom() {
+        let easing = Easing::CatmullRom;
+        let t = track(&[(0.0, 0.0, easing), (1.0, 1.0, easing), (2.0, 2.0, easing)]);
+        // points on a line stay on it
+        assert_sample(&t, 0.5, 0.5);
+        assert_sample(&t, 1.25, 1.25);
+        let t = track(&[(0.0, 0.0, easing), (1.0, 1.0, easing), (2.0, 0.0, easing)]);
+        // passes through the keyframes and rounds the peak
+        assert_sample(&t, 1.0, 1.0);
+        let [before] = t.sample(0.9).unwrap();
+        assert!(before > 0.9 && before < 1.0);
+    }
+
+    #[test]
+    fn test_playback() {
+        let mut clip = Clip::default();
+        clip.translation.insert(Keyframe {
+            time: 0.0,
+            value: [0.0, 0.0, 0.0],
+            easing: Easing::Linear,
+        });
+        clip.translation.insert(Keyframe {
+            time: 2.0,
+            value: [100.0, 0.0, 0.0],
+            easing: Easing::Linear,
+        });
+        let x = |clip: &Clip, secs| {
+            let mut geometry = triangle();
+            geometry.set_animation(clip.clone());
+            (geometry.animated_model_matrix(secs) * point(0.0, 0.0, 0.0)).x
+        };
+        assert_eq!(x(&clip, 1.0), 50.0);
+        assert_eq!(x(&clip, 3.0), 100.0);
+        clip.playback = Playback::Loop;
+        assert_eq!(x(&clip, 3.0), 50.0);
+        clip.playback = Playback::PingPong;
+        assert_eq!(x(&clip, 2.5), 75.0);
+        assert_eq!(x(&clip, 4.5), 25.0);
+    }
+
+    #[test]
+    fn test_orientation() {
+        let key = |time, degrees: f32| Keyframe {
+            time,
+            value: [0.0, 0.0, degrees.to_radians()],
+            easing: Easing::Linear,
+        };
+        let mut track = Track::default();
+        track.insert(key(0.0, 0.0));
+        track.insert(key(1.0, 90.0));
+        track.insert(key(2.0, 360.0));
+        track.insert(key(3.0, 380.0));
+        let angle = |time| {
+            let q = track.sample_orientation(time).unwrap();
+            q.angle() * q.axis().map_or(1.0, |axis| axis.z.signum())
+        };
+        assert!(f32_equals(angle(0.5), PI / 4.0));
+        // 270 degrees is turned the long way round, as the keys ask
+        assert!(f32_equals(angle(1.5), -0.75 * PI));
+        assert!(f32_equals(angle(2.5), (10.0f32).to_radians()));
+
+        // Catmull-Rom keeps its curve through the neighbouring keys
+        let mut track = Track::default();
+        for (time, degrees) in [(0.0, 0.0), (1.0, 30.0), (2.0, 40.0), (3.0, 100.0)] {
+            track.insert(Keyframe {
+                easing: Easing::CatmullRom,
+                ..key(time, degrees)
+            });
+        }
+        let [x, y, z] = track.sample(1.5).unwrap();
+        let expected = math::euler_quaternion(x, y, z);
+        let q = track.sample_orientation(1.5).unwrap();
+        assert!(q.angle_to(&expected) < 1e-3);
+        assert!(!f32_equals(z, 35f32.to_radians()));
+    }
+
+    const CLIP: &str = "
+playback ping_pong
+
+track rotation   # degrees
+  key 0 ease_in_out 0 0 0
+  key 1 bounce 0 0 90
+end
+
+track color
+  key 0.5 step 1 0 0 1
+end
+";
+
+    #[test]
+    fn test_round_trip() {
+        let clip = parse(CLIP).unwrap();
+        assert_eq!(clip.playback, Playback::PingPong);
+        assert!(clip.translation.keys().is_empty());
+        assert!(f32_equals(clip.rotation.keys()[1].value[2], PI / 2.0));
+        assert_eq!(clip.color.keys()[0].value, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(clip.duration(), 1.0);
+
+        let mut out = vec![];
+        write(&clip, &mut out).unwrap();
+        let read = parse(&String::from_utf8(out).unwrap()).unwrap();
+        assert_eq!(read.playback, clip.playback);
+        assert_eq!(read.color, clip.color);
+        for (read, written) in read.rotation.keys().iter().zip(clip.rotation.keys()) {
+            assert_eq!(read.easing, written.easing);
+            assert!(f32_equals(read.value[2], written.value[2]));
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let line = |source: &str| match parse(source) {
+            Err(LineError::Parse { line, .. }) => line,
+            _ => panic!("expected a parse error"),
+        };
+        assert_eq!(line("playback sometimes"), 1);
+        assert_eq!(line("track size"), 1);
+        assert_eq!(line("track scale\n  key 0 linear 1 1\nend"), 2);
+        assert_eq!(line("track scale\n  key 0 wobble 1 1 1\nend"), 2);
+        assert_eq!(line("track scale\n  key 0 linear 1 1 1\n"), 2);
+        assert_eq!(line("\nend"), 2);
+    }
+}
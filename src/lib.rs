@@ -0,0 +1,24 @@
+pub mod animation;
+pub mod attribute;
+pub mod clip;
+pub mod color;
+mod edge;
+pub mod geometry;
+pub mod gltf;
+pub mod image;
+mod inflate;
+mod json;
+pub mod keyframe;
+pub mod math;
+pub mod obj;
+pub mod ply;
+pub mod rasterizer;
+pub mod renderer;
+pub mod scene;
+pub mod shader;
+pub mod stl;
+pub mod text;
+pub mod text_file;
+pub mod texture;
+pub mod timer;
+pub mod world;
@@ -1,33 +1,119 @@
-// Remove once in 3D?
-#![allow(dead_code, unused_imports)]
+use std::f32::consts::PI;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
 
-mod color;
-mod geometry;
-mod math;
-mod rasterizer;
-mod timer;
-mod world;
-
-use std::cell::RefCell;
-use std::f32::{consts::PI, INFINITY};
-
-use color::{Color, Rgba};
-use geometry::{direction, line, point, right_triangle, square, triangle, GeoError, Geometry};
-use math::{f32_equals, OrdFloat};
-use minifb::{Key, Window, WindowOptions};
+use ground_up_rasterizer::animation::{orbit, spin, Animator};
+use ground_up_rasterizer::color::{Color, Rgba};
+use ground_up_rasterizer::geometry::{direction, line, point, quad, square, triangle, Geometry};
+use ground_up_rasterizer::image;
+use ground_up_rasterizer::math::f32_equals;
+use ground_up_rasterizer::rasterizer::Msaa;
+use ground_up_rasterizer::renderer::{Framebuffer, Renderer};
+use ground_up_rasterizer::scene::{self, Scene};
+use ground_up_rasterizer::text;
+use ground_up_rasterizer::texture::{Filter, Sampler, Texture, TextureShader, Wrap};
+use ground_up_rasterizer::timer::Timer;
+use ground_up_rasterizer::world::{Camera, Projection, World};
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
 use nalgebra as na;
-use rasterizer::rasterize_geometry;
-use timer::Timer;
-use world::{Camera, World};
 
 fn main() {
     let mut timer = Timer::default();
 
     let width = 1500;
     let height = 1000;
-    let mut world = World::default();
-    let mut camera = Camera::new(-200., -200., width as f32, height as f32, 0.0);
     let mut window = Window::new("Rasterizer", width, height, WindowOptions::default()).unwrap();
+    let mut renderer = Renderer::new(width, height);
+    // an optional scene file replaces the built in demo and is reloaded on
+    // change, and `--animate` sets the demo's triangle in motion
+    let (flags, paths): (Vec<_>, Vec<_>) = std::env::args_os()
+        .skip(1)
+        .partition(|arg| arg == "--animate");
+    let mut watcher = paths.into_iter().next().map(SceneWatcher::new);
+    let mut world = match watcher {
+        Some(_) => World::default(),
+        None => demo_world(!flags.is_empty()),
+    };
+    let mut camera = Camera::new(-200., -200., width as f32, height as f32, 0.0);
+    let mut scene_loaded = false;
+    let mut cur = 0;
+    let mut u32_buffer: Vec<u32> = vec![0; width * height];
+    let mut fps_sum = 0.;
+    let mut fps_count = 0.;
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        // update timer
+        timer.tick();
+        let delta_time = timer.delta_time_secs();
+        let current_time = timer.time_elapsed_secs();
+        // fps count
+        cur += 1;
+        if cur % 60 == 0 {
+            let cur_fps = 1.0 / delta_time;
+            fps_sum += cur_fps;
+            fps_count += 1.;
+            println!(
+                "{} fps; {} delta time; {} average fps",
+                cur_fps,
+                delta_time,
+                fps_sum / fps_count
+            );
+        }
+        if let Some(scene) = watcher.as_mut().and_then(SceneWatcher::poll) {
+            world = scene.world;
+            renderer.set_clear_color(scene.background);
+            // the file only places the camera until the user has moved it
+            if !scene_loaded {
+                camera = scene.camera;
+                scene_loaded = true;
+            }
+        }
+        // camera movement
+        let angle = rotate_camera(&window);
+        camera.add_rotation(angle * delta_time);
+        let (x, y) = move_camera(&window);
+        let x = x * delta_time;
+        let y = y * delta_time;
+        camera.translate(x, y);
+        let (yaw, pitch) = look_camera(&window);
+        camera.rotate(yaw * delta_time, pitch * delta_time, 0.0);
+        let forward = zoom_camera(&window);
+        camera.translate_local(direction(0.0, 0.0, forward * delta_time));
+        if window.is_key_pressed(Key::C, KeyRepeat::No) {
+            toggle_projection(&mut camera, width as f32, height as f32);
+        }
+        if window.is_key_pressed(Key::M, KeyRepeat::No) {
+            let msaa = match renderer.msaa() {
+                Msaa::Off => Msaa::X2,
+                Msaa::X2 => Msaa::X4,
+                Msaa::X4 => Msaa::X8,
+                Msaa::X8 => Msaa::Off,
+            };
+            println!("MSAA {msaa:?}");
+            renderer.set_msaa(msaa);
+        }
+        // render
+        renderer.render(&world, &camera, current_time);
+        if let Some(error) = watcher.as_ref().and_then(SceneWatcher::error) {
+            draw_error(renderer.framebuffer_mut(), error);
+        }
+        renderer.framebuffer().copy_to_u32(&mut u32_buffer);
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            match image::save(renderer.framebuffer(), "screenshot.png") {
+                Ok(()) => println!("Saved screenshot.png"),
+                Err(error) => eprintln!("Could not save screenshot: {error}"),
+            }
+        }
+        window
+            .update_with_buffer(&u32_buffer, width, height)
+            .unwrap();
+    }
+}
+
+fn demo_world(animate: bool) -> World {
+    let mut world = World::default();
     // Triangle depth and alpha testing
     let mut t1 = triangle();
     t1.set_name(Some("Solid triangle for transparency testing".to_string()));
@@ -66,116 +152,106 @@ fn main() {
     s.scale(na::matrix![200.0;200.0;200.0]);
     s.translate(direction(500.0, 500.0, 0.0));
     s.set_name(Some("Pointing square".to_string()));
+    let size = na::Vector3::new(200.0, -200.0, 0.0);
     let mut t = triangle();
-    t.scale(na::matrix![200.0; -200.0; 0.0]);
+    t.scale(size);
     t.rotation(0.0, 0.0, PI);
     t.translate(direction(500.0, -200.0, 0.0));
     s.set_name(Some("Triangle above pointing square".to_string()));
-    // t.set_animation(|geo: &mut Geometry, time: f32| {
-    //     geo.rotation(0.0, 0.0, time * 2.0);
-    //     let scale = 100.0 * time.sin();
-    //     geo.scale(na::matrix![scale; scale; 0.0]);
-    //     let pos_x = 300.0 * time.cos();
-    //     let pos_y = 300.0 * time.sin();
-    //     geo.set_position(point(pos_x, pos_y, 0.0));
-    // });
+    // spins and circles its resting place while pulsing around its size
+    let amplitude = 0.25;
+    let pulse = move |geo: &mut Geometry, time: f32| {
+        geo.scale(size * (1.0 + amplitude * (time * 3.0).sin()));
+    };
+    if animate {
+        t.set_animation(spin(0.0, 0.0, 2.0).and(pulse).and(orbit(100.0, 1.0)));
+    }
+    let checker = Texture::from_fn(8, 8, |x, y| {
+        if (x + y) % 2 == 0 {
+            (&Color::White).into()
+        } else {
+            (&Color::Custom(0.1, 0.1, 0.1, 1.0)).into()
+        }
+    });
+    let texture_shader = Arc::new(TextureShader::new(
+        Arc::new(checker),
+        Sampler::new(Filter::Nearest, Wrap::Repeat),
+    ));
+    let mut textured = quad();
+    textured.scale(na::matrix![150.0;150.0;1.0]);
+    textured.translate(direction(900.0, 200.0, 0.0));
+    textured.set_name(Some("Textured square".to_string()));
+    textured.set_vertex_shader(texture_shader.clone());
+    textured.set_fragment_shader(texture_shader);
     world.insert(t);
     world.insert(s);
-    let mut cur = 0;
-    let mut rgba_buffer = vec![Rgba::from(&Color::Black); width * height];
-    let mut depth_buffer = vec![OrdFloat(-f32::INFINITY); width * height];
-    let mut draw_buffer = vec![];
-    let mut u32_buffer: Vec<u32> = vec![0; width * height];
-    let mut opaque = vec![];
-    let mut transparent = vec![];
-    let mut fps_sum = 0.;
-    let mut fps_count = 0.;
+    world.insert(textured);
+    world
+}
 
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        // update timer
-        timer.tick();
-        let delta_time = timer.delta_time_secs();
-        let current_time = timer.time_elapsed_secs();
-        // fps count
-        cur += 1;
-        if cur % 60 == 0 {
-            let cur_fps = 1.0 / delta_time;
-            fps_sum += cur_fps;
-            fps_count += 1.;
-            println!(
-                "{} fps; {} delta time; {} average fps",
-                cur_fps,
-                delta_time,
-                fps_sum / fps_count
-            );
-        }
-        // camera movement
-        let angle = rotate_camera(&window);
-        camera.add_rotation(angle * delta_time);
-        let (x, y) = move_camera(&window);
-        let x = x * delta_time;
-        let y = y * delta_time;
-        camera.translate(x, y);
-        // render
-        let to_render = camera.world_view(&world, width as f32, height as f32, current_time);
-        for obj in &to_render {
-            rasterize_geometry(obj, &mut draw_buffer).unwrap_or_else(|error| {
-                match error {
-                    GeoError::NotDiv3(_) => {
-                        eprintln!("The number of vertices of a triangle is not divisible by 3");
-                    }
-                };
-            });
-        }
-        let db_len = draw_buffer.len();
-        for i in 0..db_len {
-            if draw_buffer[i].color.a == OrdFloat(1.0) {
-                opaque.push(i);
-            } else {
-                transparent.push(i);
-            }
+/// Reloads a scene file whenever its modification time changes, keeping the
+/// last error around for display.
+struct SceneWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    error: Option<String>,
+}
+
+impl SceneWatcher {
+    fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            modified: None,
+            error: None,
         }
-        for i in opaque.iter() {
-            let obj = &draw_buffer[*i];
-            if let Some(index) = xy_to_1d(obj.x, obj.y, width as i32, height as i32) {
-                if obj.depth > depth_buffer[index] {
-                    rgba_buffer[index] = obj.color.clone();
-                    depth_buffer[index] = obj.depth;
+    }
+
+    /// The freshly loaded scene if the file changed since the last call.
+    fn poll(&mut self) -> Option<Scene> {
+        let modified = match fs::metadata(&self.path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(error) => {
+                // editors may briefly remove the file while saving
+                if self.modified.is_none() {
+                    self.error = Some(format!("{}: {error}", self.path.display()));
                 }
+                return None;
             }
+        };
+        if self.modified == Some(modified) {
+            return None;
         }
-        // layer transparent on top of opaque
-        transparent.sort_unstable_by_key(|cur| draw_buffer[*cur].depth);
-        for i in transparent.iter() {
-            let obj = &draw_buffer[*i];
-            if let Some(index) = xy_to_1d(obj.x, obj.y, width as i32, height as i32) {
-                if obj.depth > depth_buffer[index] {
-                    rgba_buffer[index].over_blend(obj.color.clone());
-                    // does not need to be updated because sorted; maybe remove?
-                    depth_buffer[index] = obj.depth;
-                }
+        self.modified = Some(modified);
+        match scene::load(&self.path) {
+            Ok(scene) => {
+                self.error = None;
+                Some(scene)
+            }
+            Err(error) => {
+                self.error = Some(format!("{}: {error}", self.path.display()));
+                None
             }
         }
-        for i in 0..(width * height) {
-            u32_buffer[i] = u32::from(&rgba_buffer[i]);
-        }
-        window
-            .update_with_buffer(&u32_buffer, width, height)
-            .unwrap();
-        // reset buffers
-        for item in &mut rgba_buffer {
-            *item = Rgba::color(0.0, 0.0, 0.0);
-        }
-        for item in &mut depth_buffer {
-            *item = OrdFloat(-f32::INFINITY);
-        }
-        for item in &mut u32_buffer {
-            *item = 0;
+    }
+
+    fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+/// Draws `message` in a box at the top left of the frame.
+fn draw_error(framebuffer: &mut Framebuffer, message: &str) {
+    let scale = 2;
+    let margin = 8;
+    let (text_width, text_height) = text::text_size(message, scale);
+    let background = Rgba::color(0.5, 0.0, 0.0);
+    for y in 0..text_height + 2 * margin {
+        for x in 0..text_width + 2 * margin {
+            framebuffer.set(x, y, background.clone());
         }
-        transparent.clear();
-        opaque.clear();
-        draw_buffer.clear();
     }
+    let white = Rgba::from(&Color::White);
+    text::draw_text(framebuffer, margin, margin, message, &white, scale);
 }
 
 fn move_camera(window: &Window) -> (f32, f32) {
@@ -203,35 +279,58 @@ fn move_camera(window: &Window) -> (f32, f32) {
     (x_vel, y_vel)
 }
 
-fn rotate_camera(window: &Window) -> f32 {
-    use Key::{E, Q};
-    let speed = PI / 15.0;
-    match (window.is_key_down(Q), window.is_key_down(E)) {
+fn look_camera(window: &Window) -> (f32, f32) {
+    use Key::{Down, Left, Right, Up};
+    let speed = PI / 6.0;
+    let axis =
+        |negative, positive| match (window.is_key_down(negative), window.is_key_down(positive)) {
+            (true, false) => -speed,
+            (false, true) => speed,
+            _ => 0.0,
+        };
+    (axis(Left, Right), axis(Down, Up))
+}
+
+fn zoom_camera(window: &Window) -> f32 {
+    use Key::{F, R};
+    let speed = 500.0;
+    match (window.is_key_down(R), window.is_key_down(F)) {
         (true, false) => -speed,
         (false, true) => speed,
         _ => 0.0,
     }
 }
 
-fn xy_to_1d(x: i32, y: i32, width: i32, height: i32) -> Option<usize> {
-    if x >= width || x < 0 || y < 0 || y >= height {
-        None
-    } else {
-        Some((y * width + x) as usize)
+/// Switches between the 2D view and a perspective camera that frames the same
+/// area of the z = 0 plane.
+fn toggle_projection(camera: &mut Camera, width: f32, height: f32) {
+    let fov_y = PI / 3.0;
+    let distance = (height / 2.0) / (fov_y / 2.0).tan();
+    let mut position = camera.position();
+    match camera.projection() {
+        Projection::Orthographic { .. } => {
+            position.z += distance;
+            camera.set_projection(Projection::Perspective {
+                fov_y,
+                aspect: width / height,
+            });
+            camera.set_clip_planes(1.0, 10000.0);
+        }
+        Projection::Perspective { .. } => {
+            position.z = 0.0;
+            camera.set_projection(Projection::Orthographic { width, height });
+            camera.set_clip_planes(-10000.0, 10000.0);
+        }
     }
+    camera.set_position(position);
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_xy_to_1d() {
-        let height = 500;
-        let width = 500;
-        assert_eq!(xy_to_1d(0, 0, width, height), Some(0));
-        assert_eq!(xy_to_1d(0, 1, width, height), Some(500));
-        assert_eq!(xy_to_1d(499, 499, width, height), Some(249999));
-        assert_eq!(xy_to_1d(500, 499, width, height), None);
+fn rotate_camera(window: &Window) -> f32 {
+    use Key::{E, Q};
+    let speed = PI / 15.0;
+    match (window.is_key_down(Q), window.is_key_down(E)) {
+        (true, false) => -speed,
+        (false, true) => speed,
+        _ => 0.0,
     }
 }
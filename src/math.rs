@@ -120,10 +120,91 @@ pub fn scale_matrix(vec: na::Vector3<f32>) -> Transform {
                 0.0, 0.0, 0.0, 1.0]
 }
 
+/// Unit quaternions stand in for rotations where Euler angles would lock or
+/// interpolate badly.
+pub type Quaternion = na::UnitQuaternion<f32>;
+
+/// The rotation built by `Geometry::rotation(x, y, z)`: around x, then y,
+/// then z, each in the object's already rotated frame.
+pub fn euler_quaternion(x: f32, y: f32, z: f32) -> Quaternion {
+    Quaternion::from_axis_angle(&na::Vector3::x_axis(), x)
+        * Quaternion::from_axis_angle(&na::Vector3::y_axis(), y)
+        * Quaternion::from_axis_angle(&na::Vector3::z_axis(), z)
+}
+
+/// Rotation by `angle` radians around `axis`, or none for a zero axis.
+pub fn axis_angle_quaternion(axis: Direction, angle: f32) -> Quaternion {
+    match na::Unit::try_new(axis.xyz(), 0.0) {
+        Some(axis) => Quaternion::from_axis_angle(&axis, angle),
+        None => Quaternion::identity(),
+    }
+}
+
+/// Rotation turning the local -z axis towards `forward` and -y towards
+/// `up`, as far as it is perpendicular to `forward`. This is the camera's
+/// convention, where -y is up on screen. `None` if `forward` is zero or
+/// parallel to `up`.
+pub fn look_at_quaternion(forward: Direction, up: Direction) -> Option<Quaternion> {
+    let forward = forward.xyz().try_normalize(0.0)?;
+    let up = up.xyz();
+    let up = (up - forward * up.dot(&forward)).try_normalize(1e-6)?;
+    let z = -forward;
+    let y = -up;
+    let x = y.cross(&z);
+    let rotation = na::Rotation3::from_matrix_unchecked(na::Matrix3::from_columns(&[x, y, z]));
+    Some(Quaternion::from_rotation_matrix(&rotation))
+}
+
+/// The rotation in the upper 3x3 block of `matrix`, or `None` if that block
+/// is not orthonormal with a positive determinant (it scales or mirrors).
+pub fn rotation_quaternion(matrix: &Transform) -> Option<Quaternion> {
+    if !is_rotation(matrix) {
+        return None;
+    }
+    let r = matrix.fixed_view::<3, 3>(0, 0).into_owned();
+    Some(Quaternion::from_rotation_matrix(
+        &na::Rotation3::from_matrix_unchecked(r),
+    ))
+}
+
+fn is_rotation(matrix: &Transform) -> bool {
+    let r = matrix.fixed_view::<3, 3>(0, 0);
+    let orthonormal = (r.transpose() * r - na::Matrix3::identity()).amax() < 1e-4;
+    orthonormal && r.determinant() > 0.0
+}
+
+/// Angles `[x, y, z]` such that `Geometry::rotation(x, y, z)` rebuilds
+/// `rotation`, or `None` if it is not a pure rotation.
+pub fn euler_angles(rotation: &Transform) -> Option<[f32; 3]> {
+    if !is_rotation(rotation) {
+        return None;
+    }
+    let r = rotation.fixed_view::<3, 3>(0, 0);
+    // rotation(x, y, z) builds Rx * Ry * Rz
+    let y = r[(0, 2)].clamp(-1.0, 1.0).asin();
+    let angles = if y.cos() > 1e-6 {
+        [
+            (-r[(1, 2)]).atan2(r[(2, 2)]),
+            y,
+            (-r[(0, 1)]).atan2(r[(0, 0)]),
+        ]
+    } else {
+        // gimbal lock, only x + z is known
+        [r[(2, 1)].atan2(r[(1, 1)]), y, 0.0]
+    };
+    let rebuilt =
+        x_rotation_matrix(angles[0]) * y_rotation_matrix(angles[1]) * z_rotation_matrix(angles[2]);
+    ((rebuilt - rotation).amax() < 1e-4).then_some(angles)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn assert_close(left: Transform, right: Transform) {
+        assert!((left - right).amax() < 1e-5, "{left} != {right}");
+    }
+
     // to-do: update
     #[test]
     fn test_f32_comparison() {
@@ -142,4 +223,39 @@ mod tests {
         assert_eq!(f32_compare(0.1, 0.0), Some(cmp::Ordering::Greater));
         assert_eq!(f32_compare(0.0, 0.1), Some(cmp::Ordering::Less));
     }
+
+    #[test]
+    fn test_euler_angles() {
+        for [x, y, z] in [[0.3, -0.2, 1.1], [0.0, std::f32::consts::FRAC_PI_2, 0.4]] {
+            let rotation = x_rotation_matrix(x) * y_rotation_matrix(y) * z_rotation_matrix(z);
+            let [a, b, c] = euler_angles(&rotation).unwrap();
+            let rebuilt = x_rotation_matrix(a) * y_rotation_matrix(b) * z_rotation_matrix(c);
+            assert_close(rebuilt, rotation);
+        }
+        let scaled = scale_matrix(na::Vector3::new(2.0, 1.0, 1.0));
+        assert_eq!(euler_angles(&scaled), None);
+    }
+
+    #[test]
+    fn test_quaternions() {
+        let (x, y, z) = (0.3, -1.2, 2.0);
+        assert_close(
+            euler_quaternion(x, y, z).to_homogeneous(),
+            x_rotation_matrix(x) * y_rotation_matrix(y) * z_rotation_matrix(z),
+        );
+        let q = axis_angle_quaternion(Direction::new(0.0, 0.0, 2.0, 0.0), 0.5);
+        assert_close(q.to_homogeneous(), z_rotation_matrix(0.5));
+        assert_eq!(rotation_quaternion(&q.to_homogeneous()).unwrap(), q);
+        assert!(rotation_quaternion(&scale_matrix(na::Vector3::new(1.0, -1.0, 1.0))).is_none());
+
+        // looking down -z with -y up is no rotation at all
+        let forward = Direction::new(0.0, 0.0, -1.0, 0.0);
+        let up = Direction::new(0.0, -1.0, 0.0, 0.0);
+        assert!(look_at_quaternion(forward, up).unwrap().angle() < 1e-6);
+        let right = Direction::new(1.0, 0.0, 0.0, 0.0);
+        let turned = look_at_quaternion(right, up + right).unwrap();
+        let ahead = turned * na::Vector3::new(0.0, 0.0, -1.0);
+        assert!((ahead - right.xyz()).amax() < 1e-6);
+        assert!(look_at_quaternion(up, up).is_none());
+    }
 }
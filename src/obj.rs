@@ -0,0 +1,316 @@
+use crate::attribute::{Attribute, AttributeStream};
+use crate::color::Color;
+use crate::geometry::{Geometry, GeometryType, Point, Vertex};
+use crate::text_file::{self, LineError};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Reads a Wavefront OBJ file, see `parse`.
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Geometry>, LineError> {
+    text_file::load(path, parse)
+}
+
+/// Builds one triangle geometry per object or group with faces.
+///
+/// Positions are shared between the faces of a geometry through
+/// `vertex_locations`, with each face corner becoming a `Vertex`. Polygons are
+/// triangulated as fans, so they should be convex. Normals become the
+/// `Normal` attribute and texture coordinates `TexCoord(0)`, with v flipped
+/// so that (0, 0) is the top left of a texture like everywhere else in the
+/// crate. Corners without them get zeros. Vertex colors written after the
+/// position (`v x y z r g b`) are kept; other vertices are white.
+///
+/// Geometries are named after their `o` and `g` statements. Materials, smoothing
+/// groups, lines, points, free-form surfaces and any other statements are
+/// ignored.
+pub fn parse(source: &str) -> Result<Vec<Geometry>, LineError> {
+    let mut parser = Parser::default();
+    for (i, line) in source.lines().enumerate() {
+        parser.line = i + 1;
+        // comments can also trail a statement
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let words: Vec<&str> = words.collect();
+        match keyword {
+            "v" => parser.position(&words)?,
+            "vn" => {
+                let normal = parser.floats(&words, 3, 3)?;
+                parser.normals.push([normal[0], normal[1], normal[2]]);
+            }
+            "vt" => {
+                let uv = parser.floats(&words, 1, 3)?;
+                let v = uv.get(1).copied().unwrap_or(0.0);
+                parser.texcoords.push([uv[0], 1.0 - v]);
+            }
+            "f" => parser.face(&words)?,
+            "o" => {
+                parser.finish_group();
+                parser.object = Some(words.join(" "));
+                parser.group = None;
+            }
+            "g" => {
+                parser.finish_group();
+                parser.group = Some(words.join(" "));
+            }
+            // materials, smoothing groups, curves, surfaces and so on
+            _ => {}
+        }
+    }
+    parser.finish_group();
+    Ok(parser.geometries)
+}
+
+/// A face corner as indices into the file-wide lists.
+#[derive(Debug, Clone, Copy)]
+struct Corner {
+    position: usize,
+    texcoord: Option<usize>,
+    normal: Option<usize>,
+}
+
+#[derive(Default)]
+struct Parser {
+    line: usize,
+    positions: Vec<(Point, Color)>,
+    normals: Vec<[f32; 3]>,
+    texcoords: Vec<[f32; 2]>,
+    object: Option<String>,
+    group: Option<String>,
+    /// Triangulated corners of the current group.
+    corners: Vec<Corner>,
+    geometries: Vec<Geometry>,
+}
+
+impl Parser {
+    fn error(&self, message: String) -> LineError {
+        LineError::Parse {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn floats(&self, words: &[&str], min: usize, max: usize) -> Result<Vec<f32>, LineError> {
+        if words.len() < min || words.len() > max {
+            return Err(self.error(format!(
+                "expected {min} to {max} numbers, found {}",
+                words.len()
+            )));
+        }
+        words
+            .iter()
+            .map(|word| {
+                word.parse::<f32>()
+                    .map_err(|_| self.error(format!("`{word}` is not a number")))
+            })
+            .collect()
+    }
+
+    fn position(&mut self, words: &[&str]) -> Result<(), LineError> {
+        let values = self.floats(words, 3, 7)?;
+        let (w, color) = match values.len() {
+            3 => (1.0, Color::White),
+            4 => (values[3], Color::White),
+            6 => (1.0, Color::Custom(values[3], values[4], values[5], 1.0)),
+            7 => (
+                values[3],
+                Color::Custom(values[4], values[5], values[6], 1.0),
+            ),
+            _ => return Err(self.error("expected x y z [w] [r g b]".to_string())),
+        };
+        self.positions
+            .push((Point::new(values[0], values[1], values[2], w), color));
+        Ok(())
+    }
+
+    /// Resolves a 1 based, or negative relative, index.
+    fn index(&self, word: &str, len: usize, what: &str) -> Result<usize, LineError> {
+        let index: i64 = word
+            .parse()
+            .map_err(|_| self.error(format!("`{word}` is not a {what} index")))?;
+        let resolved = match index {
+            1.. => index - 1,
+            ..0 => len as i64 + index,
+            0 => -1,
+        };
+        if resolved < 0 || resolved >= len as i64 {
+            return Err(self.error(format!(
+                "{what} index {index} is out of range, {len} defined so far"
+            )));
+        }
+        Ok(resolved as usize)
+    }
+
+    fn corner(&self, word: &str) -> Result<Corner, LineError> {
+        let mut parts = word.split('/');
+        let position = parts.next().unwrap_or_default();
+        let position = self.index(position, self.positions.len(), "position")?;
+        let texcoord = match parts.next() {
+            None | Some("") => None,
+            Some(index) => Some(self.index(index, self.texcoords.len(), "texture coordinate")?),
+        };
+        let normal = match parts.next() {
+            None | Some("") => None,
+            Some(index) => Some(self.index(index, self.normals.len(), "normal")?),
+        };
+        if parts.next().is_some() {
+            return Err(self.error(format!("`{word}` has too many indices")));
+        }
+        Ok(Corner {
+            position,
+            texcoord,
+            normal,
+        })
+    }
+
+    fn face(&mut self, words: &[&str]) -> Result<(), LineError> {
+        if words.len() < 3 {
+            return Err(self.error(format!(
+                "faces need at least 3 vertices, found {}",
+                words.len()
+            )));
+        }
+        let corners = words
+            .iter()
+            .map(|word| self.corner(word))
+            .collect::<Result<Vec<_>, _>>()?;
+        for i in 1..corners.len() - 1 {
+            self.corners
+                .extend_from_slice(&[corners[0], corners[i], corners[i + 1]]);
+        }
+        Ok(())
+    }
+
+    /// Turns the faces read since the last `o` or `g` into a geometry.
+    fn finish_group(&mut self) {
+        if self.corners.is_empty() {
+            return;
+        }
+        let mut geometry = Geometry::new(GeometryType::Triangle);
+        let mut locations = HashMap::new();
+        let has_normals = self.corners.iter().any(|corner| corner.normal.is_some());
+        let has_texcoords = self.corners.iter().any(|corner| corner.texcoord.is_some());
+        let mut normals = AttributeStream::new(3);
+        let mut texcoords = AttributeStream::new(2);
+        for corner in self.corners.drain(..) {
+            let (location, color) = &self.positions[corner.position];
+            let index = *locations.entry(corner.position).or_insert_with(|| {
+                geometry.vertex_locations.push(*location);
+                geometry.vertex_locations.len() - 1
+            });
+            geometry.vertices.push(Vertex::new(index, *color));
+            if has_normals {
+                normals.push(&corner.normal.map_or([0.0; 3], |i| self.normals[i]));
+            }
+            if has_texcoords {
+                texcoords.push(&corner.texcoord.map_or([0.0; 2], |i| self.texcoords[i]));
+            }
+        }
+        if has_normals {
+            geometry.set_attribute(Attribute::Normal, normals);
+        }
+        if has_texcoords {
+            geometry.set_attribute(Attribute::TexCoord(0), texcoords);
+        }
+        let name = match (&self.object, &self.group) {
+            (Some(object), Some(group)) => Some(format!("{object}/{group}")),
+            (Some(name), None) | (None, Some(name)) => Some(name.clone()),
+            (None, None) => None,
+        };
+        geometry.set_name(name);
+        self.geometries.push(geometry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::point;
+
+    const CUBE_FACE: &str = "
+# a quad and a triangle sharing an edge
+o panel
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 2 0 0 1 0 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 4/4/1
+g tip
+f -4 5 -3 # relative indices
+";
+
+    #[test]
+    fn test_parse() {
+        let geometries = parse(CUBE_FACE).unwrap();
+        assert_eq!(geometries.len(), 2);
+        let quad = &geometries[0];
+        assert_eq!(quad.name(), Some("panel"));
+        // the quad is split into two triangles sharing two positions
+        assert_eq!(quad.vertices.len(), 6);
+        assert_eq!(quad.vertex_locations.len(), 4);
+        let indices: Vec<usize> = quad.vertices.iter().map(|v| v.index).collect();
+        assert_eq!(indices, vec![0, 1, 2, 0, 2, 3]);
+        let normals = quad.attribute(&Attribute::Normal).unwrap();
+        assert_eq!(normals.get(4), &[0.0, 0.0, 1.0]);
+        let texcoords = quad.attribute(&Attribute::TexCoord(0)).unwrap();
+        // flipped so the first corner is at the bottom of the texture
+        assert_eq!(texcoords.get(0), &[0.0, 1.0]);
+        assert_eq!(texcoords.get(2), &[1.0, 0.0]);
+
+        let tip = &geometries[1];
+        assert_eq!(tip.name(), Some("panel/tip"));
+        assert_eq!(
+            tip.vertex_locations,
+            vec![
+                point(1.0, 0.0, 0.0),
+                point(2.0, 0.0, 0.0),
+                point(1.0, 1.0, 0.0)
+            ]
+        );
+        assert!(matches!(
+            tip.vertices[1].color,
+            Color::Custom(1.0, 0.0, 0.0, 1.0)
+        ));
+        assert!(tip.attribute(&Attribute::Normal).is_none());
+    }
+
+    fn error_line(source: &str) -> usize {
+        match parse(source) {
+            Err(LineError::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_skips_other_statements() {
+        let source = "mg 1 2\ncstype bspline\ndeg 3\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+                      curv 0 1 1 2\nparm u 0 1\nbevel off\nlod 1\nmaplib a.map\n\
+                      usemap a\nshadow_obj s.obj\ntrace_obj t.obj\nf 1 2 3\n";
+        let geometries = parse(source).unwrap();
+        assert_eq!(geometries.len(), 1);
+        assert_eq!(geometries[0].vertices.len(), 3);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(error_line("v 0 0 0\nv 1 0\n"), 2);
+        assert_eq!(error_line("v 0 0 0\nv 1 0 0\nf 1 2\n"), 3);
+        assert_eq!(error_line("v 0 0 0\n\nf 1 2 4\n"), 3);
+        assert_eq!(error_line("v 0 0 0\nf 1 1/1 1\n"), 2);
+        assert_eq!(error_line("v 0 0 x\n"), 1);
+        assert_eq!(error_line("v 0 0 0\nf 0 1 1\n"), 2);
+        let message = parse("v 0 0 0\nf 1 2 3").unwrap_err().to_string();
+        assert_eq!(
+            message,
+            "line 2: position index 2 is out of range, 1 defined so far"
+        );
+    }
+}
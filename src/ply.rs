@@ -0,0 +1,771 @@
+use crate::attribute::{Attribute, AttributeStream};
+use crate::color::{Color, Rgba};
+use crate::geometry::{Geometry, GeometryType, Point, Vertex};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io(io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlyError::Io(error) => write!(f, "could not read PLY file: {error}"),
+            PlyError::Parse(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for PlyError {}
+
+impl From<io::Error> for PlyError {
+    fn from(error: io::Error) -> Self {
+        PlyError::Io(error)
+    }
+}
+
+fn parse_error(message: impl Into<String>) -> PlyError {
+    PlyError::Parse(message.into())
+}
+
+/// `value` as a count or index, if it is a non-negative whole number.
+fn to_usize(value: f64) -> Option<usize> {
+    (value >= 0.0 && value.fract() == 0.0).then_some(value as usize)
+}
+
+/// Reads an ASCII or binary PLY file, see `read`.
+pub fn load(path: impl AsRef<Path>) -> Result<Geometry, PlyError> {
+    read(&fs::read(path)?)
+}
+
+/// Builds a triangle geometry from the `vertex` and `face` elements of a PLY
+/// file in any of its three encodings.
+///
+/// Every PLY vertex becomes an entry of `vertex_locations` and faces are
+/// triangulated as fans. Per vertex `red`/`green`/`blue`/`alpha` become
+/// `Vertex::color` (8 bit values are sRGB encoded, floats linear); vertices
+/// without them are white. `nx`/`ny`/`nz` become the `Normal` attribute and
+/// `s`/`t` (or `u`/`v`) `TexCoord(0)`, with t flipped so (0, 0) is the top
+/// left of a texture. Other elements and properties are skipped.
+pub fn read(bytes: &[u8]) -> Result<Geometry, PlyError> {
+    let (header, body) = Header::parse(bytes)?;
+    let mut values: Box<dyn ValueReader> = match header.format {
+        Format::Ascii => Box::new(AsciiReader::new(body)?),
+        Format::BinaryLittleEndian => Box::new(BinaryReader {
+            bytes: body,
+            big_endian: false,
+        }),
+        Format::BinaryBigEndian => Box::new(BinaryReader {
+            bytes: body,
+            big_endian: true,
+        }),
+    };
+    let mut vertices = None;
+    let mut faces = None;
+    for element in &header.elements {
+        let data = element.read(values.as_mut())?;
+        match element.name.as_str() {
+            "vertex" => vertices = Some(VertexData::new(element, &data)?),
+            "face" => faces = Some(face_indices(element, data)?),
+            _ => {}
+        }
+    }
+    let vertices = vertices.ok_or_else(|| parse_error("no vertex element"))?;
+    let mut geometry = Geometry::new(GeometryType::Triangle);
+    geometry.vertex_locations = vertices.positions.clone();
+    let mut normals = AttributeStream::new(3);
+    let mut texcoords = AttributeStream::new(2);
+    for face in faces.unwrap_or_default() {
+        if face.len() < 3 {
+            return Err(parse_error(format!(
+                "faces need at least 3 vertices, found {}",
+                face.len()
+            )));
+        }
+        for i in 1..face.len() - 1 {
+            for index in [face[0], face[i], face[i + 1]] {
+                if index >= vertices.positions.len() {
+                    return Err(parse_error(format!(
+                        "face uses vertex {index}, but there are only {}",
+                        vertices.positions.len()
+                    )));
+                }
+                geometry
+                    .vertices
+                    .push(Vertex::new(index, vertices.colors[index]));
+                if let Some(all) = &vertices.normals {
+                    normals.push(&all[index]);
+                }
+                if let Some(all) = &vertices.texcoords {
+                    texcoords.push(&all[index]);
+                }
+            }
+        }
+    }
+    if vertices.normals.is_some() {
+        geometry.set_attribute(Attribute::Normal, normals);
+    }
+    if vertices.texcoords.is_some() {
+        geometry.set_attribute(Attribute::TexCoord(0), texcoords);
+    }
+    Ok(geometry)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Type {
+    fn parse(name: &str) -> Result<Self, PlyError> {
+        Ok(match name {
+            "char" | "int8" => Type::I8,
+            "uchar" | "uint8" => Type::U8,
+            "short" | "int16" => Type::I16,
+            "ushort" | "uint16" => Type::U16,
+            "int" | "int32" => Type::I32,
+            "uint" | "uint32" => Type::U32,
+            "float" | "float32" => Type::F32,
+            "double" | "float64" => Type::F64,
+            _ => return Err(parse_error(format!("unknown property type `{name}`"))),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::F32 => 4,
+            Type::F64 => 8,
+        }
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, Type::F32 | Type::F64)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PropertyKind {
+    Scalar(Type),
+    /// Count type and item type.
+    List(Type, Type),
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn property(&self, name: &str) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|property| property.name == name)
+    }
+
+    /// Every item as one list of values per property; scalars are one long.
+    /// The count comes from the header and may be made up, so the items are
+    /// only allocated as the body actually provides them.
+    fn read(&self, values: &mut dyn ValueReader) -> Result<Vec<Vec<Vec<f64>>>, PlyError> {
+        if self.properties.is_empty() {
+            // nothing to read, nor to keep
+            return Ok(vec![]);
+        }
+        let mut items = Vec::new();
+        for _ in 0..self.count {
+            let mut item = Vec::with_capacity(self.properties.len());
+            for property in &self.properties {
+                item.push(match property.kind {
+                    PropertyKind::Scalar(ty) => vec![values.read(ty)?],
+                    PropertyKind::List(count, ty) => {
+                        let count = values.read(count)?;
+                        let count = to_usize(count).ok_or_else(|| {
+                            parse_error(format!("`{count}` is not a list length"))
+                        })?;
+                        (0..count)
+                            .map(|_| values.read(ty))
+                            .collect::<Result<_, _>>()?
+                    }
+                });
+            }
+            items.push(item);
+        }
+        Ok(items)
+    }
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+impl Header {
+    /// Splits off and parses the header, returning the body that follows it.
+    fn parse(bytes: &[u8]) -> Result<(Header, &[u8]), PlyError> {
+        // read line by line, as a binary body can hold any bytes, and
+        // `end_header` may also appear within a comment
+        let mut header = vec![];
+        let mut body = bytes;
+        loop {
+            if body.is_empty() {
+                return Err(parse_error("missing end_header"));
+            }
+            let (line, rest) = match body.iter().position(|&byte| byte == b'\n') {
+                Some(end) => (&body[..end], &body[end + 1..]),
+                None => (body, &[][..]),
+            };
+            body = rest;
+            // lines may end in \r\n
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line == b"end_header" {
+                break;
+            }
+            header.push(
+                std::str::from_utf8(line).map_err(|_| parse_error("header is not valid UTF-8"))?,
+            );
+        }
+        let mut lines = header.into_iter().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some("ply") {
+            return Err(parse_error("not a PLY file"));
+        }
+        let mut format = None;
+        let mut elements: Vec<Element> = vec![];
+        for (i, line) in lines {
+            let error = |message: String| parse_error(format!("line {}: {message}", i + 1));
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] | ["comment", ..] | ["obj_info", ..] => {}
+                ["format", name, _version] => {
+                    format = Some(match *name {
+                        "ascii" => Format::Ascii,
+                        "binary_little_endian" => Format::BinaryLittleEndian,
+                        "binary_big_endian" => Format::BinaryBigEndian,
+                        _ => return Err(error(format!("unknown format `{name}`"))),
+                    });
+                }
+                ["element", name, count] => elements.push(Element {
+                    name: name.to_string(),
+                    count: count
+                        .parse()
+                        .map_err(|_| error(format!("`{count}` is not an element count")))?,
+                    properties: vec![],
+                }),
+                ["property", rest @ ..] => {
+                    let element = elements
+                        .last_mut()
+                        .ok_or_else(|| error("property before any element".to_string()))?;
+                    let parse_type =
+                        |name: &str| Type::parse(name).map_err(|e| error(e.to_string()));
+                    let (kind, name) = match rest {
+                        ["list", count, item, name] => (
+                            PropertyKind::List(parse_type(count)?, parse_type(item)?),
+                            name,
+                        ),
+                        [ty, name] => (PropertyKind::Scalar(parse_type(ty)?), name),
+                        _ => return Err(error("malformed property".to_string())),
+                    };
+                    element.properties.push(Property {
+                        name: name.to_string(),
+                        kind,
+                    });
+                }
+                [keyword, ..] => return Err(error(format!("unexpected `{keyword}`"))),
+            }
+        }
+        let format = format.ok_or_else(|| parse_error("missing format"))?;
+        Ok((Header { format, elements }, body))
+    }
+}
+
+trait ValueReader {
+    fn read(&mut self, ty: Type) -> Result<f64, PlyError>;
+}
+
+struct AsciiReader<'a> {
+    words: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> AsciiReader<'a> {
+    fn new(body: &'a [u8]) -> Result<Self, PlyError> {
+        let text = std::str::from_utf8(body).map_err(|_| parse_error("body is not valid UTF-8"))?;
+        Ok(Self {
+            words: text.split_ascii_whitespace(),
+        })
+    }
+}
+
+impl ValueReader for AsciiReader<'_> {
+    fn read(&mut self, _ty: Type) -> Result<f64, PlyError> {
+        let word = self
+            .words
+            .next()
+            .ok_or_else(|| parse_error("file ends before all elements were read"))?;
+        word.parse()
+            .map_err(|_| parse_error(format!("`{word}` is not a number")))
+    }
+}
+
+struct BinaryReader<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl ValueReader for BinaryReader<'_> {
+    fn read(&mut self, ty: Type) -> Result<f64, PlyError> {
+        let size = ty.size();
+        if self.bytes.len() < size {
+            return Err(parse_error("file ends before all elements were read"));
+        }
+        let (value, rest) = self.bytes.split_at(size);
+        self.bytes = rest;
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(value);
+        if self.big_endian {
+            buffer[..size].reverse();
+        }
+        let b = buffer;
+        Ok(match ty {
+            Type::I8 => b[0] as i8 as f64,
+            Type::U8 => b[0] as f64,
+            Type::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Type::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Type::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Type::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Type::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Type::F64 => f64::from_le_bytes(b),
+        })
+    }
+}
+
+struct VertexData {
+    positions: Vec<Point>,
+    colors: Vec<Color>,
+    normals: Option<Vec<[f32; 3]>>,
+    texcoords: Option<Vec<[f32; 2]>>,
+}
+
+impl VertexData {
+    fn new(element: &Element, items: &[Vec<Vec<f64>>]) -> Result<Self, PlyError> {
+        let find = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| element.property(name))
+                .collect::<Option<Vec<usize>>>()
+        };
+        let value = |item: &Vec<Vec<f64>>, property: usize| {
+            item[property].first().copied().unwrap_or_default() as f32
+        };
+        let position = find(&["x", "y", "z"])
+            .ok_or_else(|| parse_error("vertices need x, y and z properties"))?;
+        let positions = items
+            .iter()
+            .map(|item| {
+                Point::new(
+                    value(item, position[0]),
+                    value(item, position[1]),
+                    value(item, position[2]),
+                    1.0,
+                )
+            })
+            .collect();
+        let colors = match find(&["red", "green", "blue"]) {
+            Some(rgb) => {
+                let alpha = element.property("alpha");
+                let is_float = |property: usize| matches!(element.properties[property].kind, PropertyKind::Scalar(ty) if ty.is_float());
+                items
+                    .iter()
+                    .map(|item| {
+                        let [r, g, b] = [rgb[0], rgb[1], rgb[2]].map(|p| value(item, p));
+                        let a = alpha.map(|p| value(item, p));
+                        let rgba = if is_float(rgb[0]) {
+                            Rgba::color_a(r, g, b, a.unwrap_or(1.0))
+                        } else {
+                            let byte = |v: f32| v.clamp(0.0, 255.0) as u8;
+                            Rgba::from_srgba8([byte(r), byte(g), byte(b), byte(a.unwrap_or(255.0))])
+                        };
+                        Color::from(&rgba)
+                    })
+                    .collect()
+            }
+            None => vec![Color::White; items.len()],
+        };
+        let normals = find(&["nx", "ny", "nz"]).map(|n| {
+            items
+                .iter()
+                .map(|item| [n[0], n[1], n[2]].map(|p| value(item, p)))
+                .collect()
+        });
+        let texcoords = find(&["s", "t"])
+            .or_else(|| find(&["u", "v"]))
+            .or_else(|| find(&["texture_u", "texture_v"]))
+            .map(|uv| {
+                items
+                    .iter()
+                    .map(|item| [value(item, uv[0]), 1.0 - value(item, uv[1])])
+                    .collect()
+            });
+        Ok(Self {
+            positions,
+            colors,
+            normals,
+            texcoords,
+        })
+    }
+}
+
+fn face_indices(element: &Element, items: Vec<Vec<Vec<f64>>>) -> Result<Vec<Vec<usize>>, PlyError> {
+    let property = element
+        .property("vertex_indices")
+        .or_else(|| element.property("vertex_index"))
+        .ok_or_else(|| parse_error("faces need a vertex_indices property"))?;
+    items
+        .into_iter()
+        .map(|mut item| {
+            std::mem::take(&mut item[property])
+                .into_iter()
+                .map(|index| {
+                    to_usize(index)
+                        .ok_or_else(|| parse_error(format!("`{index}` is not a vertex index")))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// One PLY vertex per distinct combination of position, color, normal and
+/// texture coordinate used by the geometry's corners, and the triangles as
+/// indices into them.
+struct Export {
+    vertices: Vec<ExportVertex>,
+    triangles: Vec<[u32; 3]>,
+    normals: bool,
+    texcoords: bool,
+}
+
+struct ExportVertex {
+    position: [f32; 3],
+    color: [u8; 4],
+    normal: [f32; 3],
+    texcoord: [f32; 2],
+}
+
+impl Export {
+    fn new(geometry: &Geometry) -> io::Result<Self> {
+        if geometry.geo_type != GeometryType::Triangle {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only triangles can be written to PLY",
+            ));
+        }
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let corners = geometry.vertices.len();
+        let normals = geometry.attribute(&Attribute::Normal);
+        let texcoords = geometry.attribute(&Attribute::TexCoord(0));
+        for (name, stream, components) in
+            [("normal", normals, 3), ("texture coordinate", texcoords, 2)]
+        {
+            let Some(stream) = stream else {
+                continue;
+            };
+            if stream.components() != components || stream.len() != corners {
+                return Err(invalid(format!(
+                    "expected {corners} {name}s of {components} components, found {} of {}",
+                    stream.len(),
+                    stream.components()
+                )));
+            }
+        }
+        let mut vertices = vec![];
+        let mut seen = HashMap::new();
+        let mut indices = Vec::with_capacity(geometry.vertices.len());
+        for (corner, vertex) in geometry.vertices.iter().enumerate() {
+            let location = *geometry.vertex_locations.get(vertex.index).ok_or_else(|| {
+                invalid(format!(
+                    "vertex {corner} uses location {}, but there are only {}",
+                    vertex.index,
+                    geometry.vertex_locations.len()
+                ))
+            })?;
+            let location = if location.w == 0.0 || location.w == 1.0 {
+                location
+            } else {
+                location / location.w
+            };
+            let mut export = ExportVertex {
+                position: [location.x, location.y, location.z],
+                color: Rgba::from(&vertex.color).to_srgba8(),
+                normal: [0.0; 3],
+                texcoord: [0.0; 2],
+            };
+            if let Some(normals) = normals {
+                export.normal.copy_from_slice(normals.get(corner));
+            }
+            if let Some(texcoords) = texcoords {
+                let uv = texcoords.get(corner);
+                export.texcoord = [uv[0], 1.0 - uv[1]];
+            }
+            let key = (
+                vertex.index,
+                export.color,
+                export.normal.map(f32::to_bits),
+                export.texcoord.map(f32::to_bits),
+            );
+            let index = *seen.entry(key).or_insert_with(|| {
+                vertices.push(export);
+                vertices.len() - 1
+            });
+            indices.push(index as u32);
+        }
+        Ok(Self {
+            vertices,
+            triangles: indices
+                .chunks_exact(3)
+                .map(|t| [t[0], t[1], t[2]])
+                .collect(),
+            normals: normals.is_some(),
+            texcoords: texcoords.is_some(),
+        })
+    }
+
+    fn write_header(&self, format: &str, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "ply")?;
+        writeln!(out, "format {format} 1.0")?;
+        writeln!(out, "element vertex {}", self.vertices.len())?;
+        for name in ["x", "y", "z"] {
+            writeln!(out, "property float {name}")?;
+        }
+        if self.normals {
+            for name in ["nx", "ny", "nz"] {
+                writeln!(out, "property float {name}")?;
+            }
+        }
+        if self.texcoords {
+            for name in ["s", "t"] {
+                writeln!(out, "property float {name}")?;
+            }
+        }
+        for name in ["red", "green", "blue", "alpha"] {
+            writeln!(out, "property uchar {name}")?;
+        }
+        writeln!(out, "element face {}", self.triangles.len())?;
+        writeln!(out, "property list uchar uint vertex_indices")?;
+        writeln!(out, "end_header")
+    }
+
+    fn floats(&self, vertex: &ExportVertex) -> Vec<f32> {
+        let mut floats = vertex.position.to_vec();
+        if self.normals {
+            floats.extend_from_slice(&vertex.normal);
+        }
+        if self.texcoords {
+            floats.extend_from_slice(&vertex.texcoord);
+        }
+        floats
+    }
+}
+
+/// Saves as little endian binary PLY. Geometry is written without its model
+/// matrix; use `Geometry::local_to_world` to bake it in first.
+pub fn save(geometry: &Geometry, path: impl AsRef<Path>) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_binary(geometry, &mut out)?;
+    out.flush()
+}
+
+pub fn write_ascii(geometry: &Geometry, out: &mut impl Write) -> io::Result<()> {
+    let export = Export::new(geometry)?;
+    export.write_header("ascii", out)?;
+    for vertex in &export.vertices {
+        for value in export.floats(vertex) {
+            write!(out, "{value} ")?;
+        }
+        let [r, g, b, a] = vertex.color;
+        writeln!(out, "{r} {g} {b} {a}")?;
+    }
+    for [a, b, c] in &export.triangles {
+        writeln!(out, "3 {a} {b} {c}")?;
+    }
+    Ok(())
+}
+
+pub fn write_binary(geometry: &Geometry, out: &mut impl Write) -> io::Result<()> {
+    let export = Export::new(geometry)?;
+    export.write_header("binary_little_endian", out)?;
+    for vertex in &export.vertices {
+        for value in export.floats(vertex) {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.write_all(&vertex.color)?;
+    }
+    for triangle in &export.triangles {
+        out.write_all(&[3])?;
+        for index in triangle {
+            out.write_all(&index.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{point, quad};
+
+    const COLORED_QUAD: &str = "ply
+format ascii 1.0
+comment four corners, one quad
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element material 1
+property list uchar float weights
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+2 0.5 0.5
+4 0 1 2 3
+";
+
+    #[test]
+    fn test_read_ascii() {
+        let geometry = read(COLORED_QUAD.as_bytes()).unwrap();
+        assert_eq!(geometry.vertex_locations.len(), 4);
+        assert_eq!(geometry.vertex_locations[2], point(1.0, 1.0, 0.0));
+        let indices: Vec<usize> = geometry.vertices.iter().map(|v| v.index).collect();
+        assert_eq!(indices, vec![0, 1, 2, 0, 2, 3]);
+        let green = Rgba::from(&geometry.vertices[1].color);
+        assert_eq!(green, Rgba::color(0.0, 1.0, 0.0));
+        assert!(geometry.attribute(&Attribute::Normal).is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let missing = COLORED_QUAD.replace("4 0 1 2 3", "4 0 1 2");
+        assert!(matches!(read(missing.as_bytes()), Err(PlyError::Parse(_))));
+        let out_of_range = COLORED_QUAD.replace("4 0 1 2 3", "3 0 1 7");
+        assert_eq!(
+            read(out_of_range.as_bytes()).unwrap_err().to_string(),
+            "face uses vertex 7, but there are only 4"
+        );
+        let bad_type = COLORED_QUAD.replace("property float z", "property half z");
+        assert_eq!(
+            read(bad_type.as_bytes()).unwrap_err().to_string(),
+            "line 7: unknown property type `half`"
+        );
+        let negative = COLORED_QUAD.replace("4 0 1 2 3", "3 0 -1 2");
+        assert_eq!(
+            read(negative.as_bytes()).unwrap_err().to_string(),
+            "`-1` is not a vertex index"
+        );
+        let fraction = COLORED_QUAD.replace("4 0 1 2 3", "3 0 1.5 2");
+        assert!(read(fraction.as_bytes()).is_err());
+        // a huge count in a tiny file runs out of body, not memory
+        let huge = "ply\nformat binary_little_endian 1.0\nelement vertex 4000000000\n\
+                    property float x\nend_header\n\0\0";
+        assert_eq!(
+            read(huge.as_bytes()).unwrap_err().to_string(),
+            "file ends before all elements were read"
+        );
+    }
+
+    #[test]
+    fn test_end_header_in_comment() {
+        let commented = COLORED_QUAD.replace(
+            "comment four corners, one quad",
+            "comment written before end_header, honest",
+        );
+        let geometry = read(commented.as_bytes()).unwrap();
+        assert_eq!(geometry.vertices.len(), 6);
+        let unterminated = COLORED_QUAD.replace("end_header\n", "");
+        assert_eq!(
+            read(unterminated.as_bytes()).unwrap_err().to_string(),
+            "missing end_header"
+        );
+    }
+
+    #[test]
+    fn test_write_rejects_mismatched_geometry() {
+        let mut s = quad();
+        s.vertices[4].index = 9;
+        let error = write_ascii(&s, &mut vec![]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let mut s = quad();
+        s.set_attribute(
+            Attribute::Normal,
+            AttributeStream::from_vec(3, vec![0.0; 9]),
+        );
+        let error = write_binary(&s, &mut vec![]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let mut s = quad();
+        s.set_attribute(
+            Attribute::TexCoord(0),
+            AttributeStream::from_vec(3, vec![0.0; 18]),
+        );
+        assert!(write_ascii(&s, &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut s = quad();
+        s.scale(nalgebra::matrix![2.0; 2.0; 1.0]);
+        let world = s.local_to_world(0.0, nalgebra::Matrix4::identity());
+        let mut ascii = vec![];
+        write_ascii(&world, &mut ascii).unwrap();
+        let mut binary = vec![];
+        write_binary(&world, &mut binary).unwrap();
+        for bytes in [ascii, binary] {
+            let read = read(&bytes).unwrap();
+            // corners with the same position and color share a PLY vertex
+            assert_eq!(read.vertex_locations.len(), 4);
+            assert_eq!(read.vertices.len(), 6);
+            for (original, copy) in world.vertices.iter().zip(&read.vertices) {
+                assert_eq!(
+                    world.vertex_locations[original.index],
+                    read.vertex_locations[copy.index]
+                );
+                assert_eq!(Rgba::from(&original.color), Rgba::from(&copy.color));
+            }
+            assert_eq!(
+                read.attribute(&Attribute::TexCoord(0)),
+                world.attribute(&Attribute::TexCoord(0))
+            );
+        }
+    }
+}
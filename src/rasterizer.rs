@@ -1,81 +1,273 @@
 use crate::color::Rgba;
-use crate::geometry::{GeoError, Geometry, GeometryType, Point};
+use crate::edge::{clip_mask, to_fixed, Edges, Kernel, BLOCK_SIZE};
+use crate::geometry::{GeometryType, Interpolation, ShadedGeometry, ShadedVertex};
 use crate::math::OrdFloat;
+use crate::shader::{FragmentInput, Varyings};
 use std::mem::swap;
+use std::ops::Range;
 
+/// A shaded fragment kept around for later, such as a translucent one that
+/// has to be blended in depth order.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ToDraw {
     pub x: i32,
     pub y: i32,
     pub color: Rgba,
     pub depth: OrdFloat,
+    /// Which of the pixel's samples it is for, when multisampling.
+    pub sample: usize,
 }
 
 impl ToDraw {
-    fn new(x: i32, y: i32, color: Rgba, depth: f32) -> Self {
+    pub fn new(x: i32, y: i32, color: Rgba, depth: f32) -> Self {
         Self {
             x,
             y,
             color,
             depth: OrdFloat(depth),
+            sample: 0,
         }
     }
 }
 
-pub fn rasterize_geometry<'a>(
-    geometry: &'a Geometry,
-    draw_buffer: &mut Vec<ToDraw>,
-) -> Result<(), GeoError<'a>> {
+/// Most samples a pixel can have.
+pub const MAX_SAMPLES: usize = 8;
+
+/// Multisample anti-aliasing: triangle coverage and depth are found for
+/// several samples per pixel while the fragment shader still runs once per
+/// pixel, and the samples are averaged into the final color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Msaa {
+    #[default]
+    Off,
+    X2,
+    X4,
+    X8,
+}
+
+impl Msaa {
+    pub fn samples(self) -> usize {
+        self.offsets().len()
+    }
+
+    /// Sample positions relative to the pixel center in 1/16 pixels, the
+    /// standard Direct3D patterns.
+    pub fn offsets(self) -> &'static [[i64; 2]] {
+        match self {
+            Msaa::Off => &[[0, 0]],
+            Msaa::X2 => &[[4, 4], [-4, -4]],
+            Msaa::X4 => &[[-2, -6], [6, -2], [-6, 2], [2, 6]],
+            Msaa::X8 => &[
+                [1, -3],
+                [-1, 3],
+                [5, 1],
+                [-3, -5],
+                [-5, 5],
+                [-7, -1],
+                [3, 7],
+                [7, -7],
+            ],
+        }
+    }
+}
+
+/// The samples of a pixel a fragment covers, with its depth at each.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Coverage {
+    /// Bit `i` is set when sample `i` is covered.
+    pub mask: u8,
+    pub depths: [f32; MAX_SAMPLES],
+}
+
+impl Coverage {
+    /// Every sample at the same depth, for lines and points.
+    pub fn full(msaa: Msaa, depth: f32) -> Self {
+        Self {
+            mask: (1u16 << msaa.samples()).wrapping_sub(1) as u8,
+            depths: [depth; MAX_SAMPLES],
+        }
+    }
+
+    /// Indices and depths of the covered samples.
+    pub fn samples(&self) -> impl Iterator<Item = (usize, f32)> + '_ {
+        (0..MAX_SAMPLES)
+            .filter(|sample| self.mask >> sample & 1 == 1)
+            .map(|sample| (sample, self.depths[sample]))
+    }
+}
+
+/// Receives fragments from the rasterizer as they are shaded.
+pub trait RenderTarget {
+    /// Samples per pixel the target keeps.
+    fn msaa(&self) -> Msaa {
+        Msaa::Off
+    }
+
+    /// The covered samples of a fragment at (x, y) that could still be seen.
+    /// Fragments with none left are not shaded at all.
+    fn depth_test(&self, _x: i32, _y: i32, coverage: &Coverage) -> u8 {
+        coverage.mask
+    }
+
+    /// Takes a fragment that was not discarded, covering the samples that
+    /// passed `depth_test`.
+    fn write(&mut self, x: i32, y: i32, coverage: &Coverage, color: Rgba);
+
+    /// Columns and rows the target covers. Triangles are only walked over
+    /// this area; anything else outside of it must fail `depth_test`.
+    fn bounds(&self) -> (Range<usize>, Range<usize>) {
+        (0..usize::MAX, 0..usize::MAX)
+    }
+}
+
+/// Collects every fragment, for tests and tools that look at them one by one.
+impl RenderTarget for Vec<ToDraw> {
+    fn write(&mut self, x: i32, y: i32, coverage: &Coverage, color: Rgba) {
+        self.push(ToDraw::new(x, y, color, coverage.depths[0]));
+    }
+}
+
+pub fn rasterize_geometry(geometry: &ShadedGeometry, target: &mut impl RenderTarget) {
+    for primitive in 0..primitive_count(geometry) {
+        rasterize_primitive(geometry, primitive, target);
+    }
+}
+
+/// Number of triangles, line segments or points in `geometry`.
+pub fn primitive_count(geometry: &ShadedGeometry) -> usize {
     match geometry.geo_type {
-        GeometryType::Line => {
-            let len = geometry.vertices.len();
-            for i in 0..len - 1 {
-                let v1 = &geometry.vertices[i];
-                let v2 = &geometry.vertices[i + 1];
-                draw_line(
-                    &geometry.vertex_locations[v1.index],
-                    &geometry.vertex_locations[v2.index],
-                    &(&v1.color).into(),
-                    &(&v2.color).into(),
-                    draw_buffer,
-                );
-            }
+        GeometryType::Line | GeometryType::Lines => geometry.vertices.len() / 2,
+        GeometryType::Point => geometry.vertices.len(),
+        GeometryType::Triangle => geometry.vertices.len() / 3,
+    }
+}
+
+fn primitive_vertices(geometry: &ShadedGeometry, primitive: usize) -> &[ShadedVertex] {
+    let size = match geometry.geo_type {
+        GeometryType::Line | GeometryType::Lines => 2,
+        GeometryType::Point => 1,
+        GeometryType::Triangle => 3,
+    };
+    &geometry.vertices[primitive * size..(primitive + 1) * size]
+}
+
+/// Pixels a primitive may cover, as inclusive `[x_min, y_min, x_max, y_max]`.
+pub fn primitive_bounds(geometry: &ShadedGeometry, primitive: usize) -> [i32; 4] {
+    let vertices = primitive_vertices(geometry, primitive);
+    let triangle = geometry.geo_type == GeometryType::Triangle;
+    let (mut min, mut max) = ([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]);
+    for v in vertices {
+        for axis in 0..2 {
+            let position = v.position[axis];
+            // lines and points only touch pixels up to their rounded ends
+            let (low, high) = if triangle {
+                (position.floor(), position.ceil())
+            } else {
+                (position.round(), position.round())
+            };
+            min[axis] = min[axis].min(low);
+            max[axis] = max[axis].max(high);
+        }
+    }
+    [min[0] as i32, min[1] as i32, max[0] as i32, max[1] as i32]
+}
+
+pub fn rasterize_primitive(
+    geometry: &ShadedGeometry,
+    primitive: usize,
+    target: &mut impl RenderTarget,
+) {
+    let vertices = primitive_vertices(geometry, primitive);
+    match geometry.geo_type {
+        GeometryType::Line | GeometryType::Lines => {
+            draw_line(&vertices[0], &vertices[1], geometry, target);
+        }
+        GeometryType::Point => {
+            let vertex = &vertices[0];
+            let zero = Varyings::zeros(vertex.varyings.len());
+            shade_fragment(
+                geometry,
+                vertex.position.x.round() as i32,
+                vertex.position.y.round() as i32,
+                vertex.position.z,
+                [vertex.varyings, zero, zero],
+                target,
+            );
         }
         GeometryType::Triangle => {
-            let len = geometry.vertices.len();
-            if len % 3 != 0 {
-                return Err(GeoError::NotDiv3(geometry));
-            }
-            let mut i = 0;
-            while i < len {
-                // move this inside Geometry struct?
-                let v1 = &geometry.vertices[i];
-                let v2 = &geometry.vertices[i + 1];
-                let v3 = &geometry.vertices[i + 2];
-                rasterize_triangle(
-                    &geometry.vertex_locations[v1.index],
-                    &geometry.vertex_locations[v2.index],
-                    &geometry.vertex_locations[v3.index],
-                    &(&v1.color).into(),
-                    &(&v2.color).into(),
-                    &(&v3.color).into(),
-                    draw_buffer,
-                );
-                i += 3;
-            }
+            rasterize_triangle(
+                [&vertices[0], &vertices[1], &vertices[2]],
+                geometry,
+                Kernel::detect(),
+                target,
+            );
         }
     }
-    Ok(())
+}
+
+/// Shades a fragment covering every sample of its pixel at `depth`.
+fn shade_fragment(
+    geometry: &ShadedGeometry,
+    x: i32,
+    y: i32,
+    depth: f32,
+    varyings: [Varyings; 3],
+    target: &mut impl RenderTarget,
+) {
+    let coverage = Coverage::full(target.msaa(), depth);
+    shade_samples(geometry, x, y, depth, coverage, varyings, target);
+}
+
+/// Runs the fragment shader once for a fragment with covered samples that
+/// pass the depth test and hands the result to `target` unless it was
+/// discarded. `depth` is the one at the pixel center the shader sees, and
+/// `varyings` are followed by their differences to the next pixel in x and y.
+fn shade_samples(
+    geometry: &ShadedGeometry,
+    x: i32,
+    y: i32,
+    depth: f32,
+    mut coverage: Coverage,
+    [varyings, ddx, ddy]: [Varyings; 3],
+    target: &mut impl RenderTarget,
+) {
+    coverage.mask = target.depth_test(x, y, &coverage);
+    if coverage.mask == 0 {
+        return;
+    }
+    let input = FragmentInput {
+        x,
+        y,
+        depth,
+        varyings,
+        ddx,
+        ddy,
+        layout: &geometry.layout,
+    };
+    if let Some(color) = geometry
+        .fragment_shader
+        .fragment(&input, &geometry.uniforms)
+    {
+        target.write(x, y, &coverage, color);
+    }
 }
 
 /// Implementation of Bresenham's line drawing algorithm.
-/// Takes two points and returns a ToDraw vector mapping the corresponding line
-/// to pixel values.
-/// to-do: color params do not need to be refs
-fn draw_line(v1: &Point, v2: &Point, v1c: &Rgba, v2c: &Rgba, draw_buffer: &mut Vec<ToDraw>) {
+/// Takes two points and shades the pixels along the line between them.
+/// Depth is interpolated linearly in screen space; varyings are perspective
+/// correct unless the geometry asks for linear interpolation.
+fn draw_line(
+    v1: &ShadedVertex,
+    v2: &ShadedVertex,
+    geometry: &ShadedGeometry,
+    target: &mut impl RenderTarget,
+) {
     // Prepare vars
-    let mut v1c = v1c;
-    let mut v2c = v2c;
+    let mut v1c = &v1.varyings;
+    let mut v2c = &v2.varyings;
+    let (v1, v2) = (&v1.position, &v2.position);
+    let mut w0 = v1.w;
+    let mut w1 = v2.w;
     let mut x0 = v1.x;
     let mut y0 = v1.y;
     let mut z0 = v1.z;
@@ -99,43 +291,49 @@ fn draw_line(v1: &Point, v2: &Point, v1c: &Rgba, v2c: &Rgba, draw_buffer: &mut V
         swap(&mut y0, &mut y1);
         swap(&mut v1c, &mut v2c);
         swap(&mut z0, &mut z1);
+        swap(&mut w0, &mut w1);
         x_diff *= -1.0;
-        y_diff *= -1.0;
     }
-    // Set up color and depth eq.
-    let rgba_diff = v2c - v1c;
+    // Set up varying and depth eq.
     let depth_diff = OrdFloat(z1 - z0);
-    let linear_interp = |x: i32, initial: OrdFloat, final_minus_initial: OrdFloat| {
-        (initial + final_minus_initial * OrdFloat((x as f32 - x0) / x_diff)).0
+    let screen_t = |x: i32| (x as f32 - x0) / x_diff;
+    // w holds 1 / w_clip, so blending by it undoes the perspective divide
+    let attribute_t = |x: i32| {
+        let t = screen_t(x);
+        match geometry.interpolation {
+            Interpolation::Linear => t,
+            Interpolation::Perspective => t * w1 / ((1.0 - t) * w0 + t * w1),
+        }
     };
-    // update floats to ints
-    let mut y_diff = y_diff as i32;
-    let x_diff = x_diff as i32;
-    let mut y = y0 as i32;
-    let y_incr = if y_diff > 0 {
-        1
+    let linear_interp = |t: f32, initial: OrdFloat, final_minus_initial: OrdFloat| {
+        (initial + final_minus_initial * OrdFloat(t)).0
+    };
+    // Step whole pixels between the rounded endpoints, so the line never
+    // leaves the rectangle they span; `primitive_bounds` relies on this.
+    let [x0, y0, x1, y1] = [x0, y0, x1, y1].map(|c| c.round() as i32);
+    let x_diff = x1 - x0;
+    let (y_diff, y_incr) = if y1 >= y0 {
+        (y1 - y0, 1)
     } else {
-        y_diff *= -1;
-        -1
+        (y0 - y1, -1)
     };
+    let mut y = y0;
     let mut d = x_diff - 2 * y_diff;
     let d_incr_gte_0 = -2 * y_diff;
     let d_incr_lt_0 = 2 * (x_diff - y_diff);
-    let x0 = x0.round() as i32;
-    let y0 = y0.round() as i32;
+    // lines are too thin to difference neighbouring pixels
+    let flat = |varyings: Varyings| {
+        let zero = Varyings::zeros(varyings.len());
+        [varyings, zero, zero]
+    };
     if xy_flipped {
-        draw_buffer.push(ToDraw::new(y0, x0, v1c.clone(), z0));
+        shade_fragment(geometry, y0, x0, z0, flat(*v1c), target);
     } else {
-        draw_buffer.push(ToDraw::new(x0, y0, v1c.clone(), z0));
+        shade_fragment(geometry, x0, y0, z0, flat(*v1c), target);
     }
-    for x in (x0 + 1)..=(x1.round() as i32) {
-        let color = Rgba::color_a(
-            linear_interp(x, v1c.r, rgba_diff.r),
-            linear_interp(x, v1c.g, rgba_diff.g),
-            linear_interp(x, v1c.b, rgba_diff.b),
-            linear_interp(x, v1c.a, rgba_diff.a),
-        );
-        let depth = linear_interp(x, OrdFloat(z0), depth_diff);
+    for x in (x0 + 1)..=x1 {
+        let varyings = flat(v1c.lerp(v2c, attribute_t(x)));
+        let depth = linear_interp(screen_t(x), OrdFloat(z0), depth_diff);
         if d >= 0 {
             d += d_incr_gte_0;
         } else {
@@ -143,84 +341,167 @@ fn draw_line(v1: &Point, v2: &Point, v1c: &Rgba, v2c: &Rgba, draw_buffer: &mut V
             d += d_incr_lt_0;
         }
         if xy_flipped {
-            draw_buffer.push(ToDraw::new(y, x, color, depth));
+            shade_fragment(geometry, y, x, depth, varyings, target);
         } else {
-            draw_buffer.push(ToDraw::new(x, y, color, depth));
+            shade_fragment(geometry, x, y, depth, varyings, target);
         }
     }
 }
 
+/// Vertices are snapped to 1/16 of a pixel and samples, the pixel centers
+/// unless multisampling, are covered when they are inside the triangle or on
+/// one of its top or left edges.
+/// Depth is interpolated linearly in screen space; varyings are perspective
+/// correct unless the geometry asks for linear interpolation.
 fn rasterize_triangle(
-    v1: &Point,
-    v2: &Point,
-    v3: &Point,
-    v1c: &Rgba,
-    v2c: &Rgba,
-    v3c: &Rgba,
-    draw_buffer: &mut Vec<ToDraw>,
+    vertices: [&ShadedVertex; 3],
+    geometry: &ShadedGeometry,
+    kernel: Kernel,
+    target: &mut impl RenderTarget,
 ) {
-    let x0 = v1[0].round();
-    let x1 = v2[0].round();
-    let x2 = v3[0].round();
-    let y0 = v1[1].round();
-    let y1 = v2[1].round();
-    let y2 = v3[1].round();
-    // I would assume the compiler would do these precomputes
-    // for me, but doing them myself seems to lead to gaining
-    // a handful of frames (~3). To-do: investigate further
-    let f12 = {
-        let y1y2 = y1 - y2;
-        let x2x1 = x2 - x1;
-        let x1y2x2y1 = x1 * y2 - x2 * y1;
-        move |x, y| y1y2 * x + x2x1 * y + x1y2x2y1
-    };
-    let f20 = {
-        let y2y0 = y2 - y0;
-        let x0x2 = x0 - x2;
-        let x2y0x0y2 = x2 * y0 - x0 * y2;
-        move |x, y| y2y0 * x + x0x2 * y + x2y0x0y2
+    let [v1, v2, v3] = vertices.map(|v| &v.position);
+    let varyings = vertices.map(|v| &v.varyings);
+    let corners = [v1, v2, v3].map(|v| [to_fixed(v.x), to_fixed(v.y)]);
+    let Some(edges) = Edges::new(corners, kernel) else {
+        return;
     };
-    let f01 = {
-        let y0y1 = y0 - y1;
-        let x1x0 = x1 - x0;
-        let x0y1x1y0 = x0 * y1 - x1 * y0;
-        move |x, y| y0y1 * x + x1x0 * y + x0y1x1y0
+    // clipping keeps vertices on screen, but snapping can still land a hair
+    // outside of it; never let the box start at a negative pixel
+    let (columns, rows) = target.bounds();
+    let [x_min, y_min, x_max, y_max] = edges.pixel_bounds();
+    let x_min = x_min.max(0).max(columns.start as i32);
+    let y_min = y_min.max(0).max(rows.start as i32);
+    let last = |range: Range<usize>| range.end.saturating_sub(1).min(i32::MAX as usize) as i32;
+    let x_max = x_max.min(last(columns));
+    let y_max = y_max.min(last(rows));
+    if x_min > x_max || y_min > y_max {
+        return;
+    }
+    let depth = |[a, b, l]: [f32; 3]| (a * v1.z) + (b * v2.z) + (l * v3.z);
+    let weights = |a: f32, b: f32, l: f32| match geometry.interpolation {
+        Interpolation::Linear => [a, b, l],
+        Interpolation::Perspective => {
+            // w holds 1 / w_clip for each vertex
+            let (pa, pb, pl) = (a * v1.w, b * v2.w, l * v3.w);
+            let sum = pa + pb + pl;
+            [pa / sum, pb / sum, pl / sum]
+        }
     };
-    let alpha_denom = f12(x0, y0);
-    let beta_denom = f20(x1, y1);
-    let lambda_denom = f01(x2, y2);
-    let alpha = |x, y| f12(x, y) / alpha_denom;
-    let beta = |x, y| f20(x, y) / beta_denom;
-    let lambda = |x, y| f01(x, y) / lambda_denom;
-    let x_min = x0.min(x1).min(x2) as usize;
-    let x_max = x0.max(x1).max(x2) as usize;
-    let y_min = y0.min(y1).min(y2) as usize;
-    let y_max = y0.max(y1).max(y2) as usize;
-    let within_bounds = |val| val >= 0.0 && val <= 1.0;
-    for y in (y_min..=y_max).map(|y| y as f32) {
-        for x in (x_min..=x_max).map(|x| x as f32) {
-            let a = alpha(x, y);
-            let b = beta(x, y);
-            let l = lambda(x, y);
-            if within_bounds(a) && within_bounds(b) && within_bounds(l) {
-                draw_buffer.push(ToDraw::new(
-                    x as i32,
-                    y as i32,
-                    &(&(a * v1c) + &(b * v2c)) + &(l * v3c),
-                    (a * v1.z) + (b * v2.z) + (l * v3.z),
-                ));
+    // Coverage is found for 8x8 blocks at a time, skipping blocks entirely
+    // outside of the triangle and testing none of the pixels of blocks
+    // entirely inside it. Pixels with any sample covered are then visited in
+    // 2x2 quads so the fragment shader can be given screen space derivatives
+    // of the varyings. Quad pixels outside of the triangle are still
+    // interpolated (extrapolated) for the differences but never shaded.
+    // Quads with no pixel covered are skipped before any barycentrics are
+    // computed, and ones that are entirely hidden before any varyings are
+    // interpolated.
+    let offsets = target.msaa().offsets();
+    let block = BLOCK_SIZE as i32;
+    let mut sample_masks = [0; MAX_SAMPLES];
+    for block_y in (y_min & -block..=y_max).step_by(BLOCK_SIZE) {
+        for block_x in (x_min & -block..=x_max).step_by(BLOCK_SIZE) {
+            let clip = clip_mask(
+                [x_min - block_x, x_max - block_x],
+                [y_min - block_y, y_max - block_y],
+            );
+            let mut mask = 0;
+            for (sample_mask, &offset) in sample_masks.iter_mut().zip(offsets) {
+                *sample_mask = clip & edges.block_mask(block_x, block_y, offset);
+                mask |= *sample_mask;
+            }
+            if mask == 0 {
+                continue;
+            }
+            for quad_y in (0..block).step_by(2) {
+                for quad_x in (0..block).step_by(2) {
+                    let first = quad_y * block + quad_x;
+                    if mask >> first & 0b11 == 0 && mask >> (first + block) & 0b11 == 0 {
+                        continue;
+                    }
+                    let mut barycentric = [[0.0; 3]; 4];
+                    let mut coverage = [Coverage::default(); 4];
+                    for (i, (x, y)) in quad_pixels(quad_x, quad_y).enumerate() {
+                        let bit = y * block + x;
+                        let (x, y) = (block_x + x, block_y + y);
+                        barycentric[i] = edges.barycentric(x, y, [0, 0]);
+                        if mask >> bit & 1 == 0 {
+                            continue;
+                        }
+                        for (sample, &offset) in offsets.iter().enumerate() {
+                            if sample_masks[sample] >> bit & 1 == 1 {
+                                let weights = match offset {
+                                    [0, 0] => barycentric[i],
+                                    _ => edges.barycentric(x, y, offset),
+                                };
+                                coverage[i].mask |= 1 << sample;
+                                coverage[i].depths[sample] = depth(weights);
+                            }
+                        }
+                        // hidden pixels still take part in the differences below
+                        coverage[i].mask = target.depth_test(x, y, &coverage[i]);
+                    }
+                    if coverage.iter().all(|coverage| coverage.mask == 0) {
+                        continue;
+                    }
+                    let quad_varyings =
+                        barycentric.map(|[a, b, l]| Varyings::blend(varyings, weights(a, b, l)));
+                    for (i, (x, y)) in quad_pixels(quad_x, quad_y).enumerate() {
+                        if coverage[i].mask == 0 {
+                            continue;
+                        }
+                        // index of the horizontal and vertical neighbour in the quad
+                        let (row, column) = (i & 2, i & 1);
+                        let ddx = &quad_varyings[row | 1] - &quad_varyings[row];
+                        let ddy = &quad_varyings[2 | column] - &quad_varyings[column];
+                        shade_samples(
+                            geometry,
+                            block_x + x,
+                            block_y + y,
+                            depth(barycentric[i]),
+                            coverage[i],
+                            [quad_varyings[i], ddx, ddy],
+                            target,
+                        );
+                    }
+                }
             }
         }
     }
 }
 
+/// Top left, top right, bottom left and bottom right pixel of a quad.
+fn quad_pixels(x: i32, y: i32) -> impl Iterator<Item = (i32, i32)> {
+    [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)].into_iter()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::color::Color;
-    use crate::geometry::point;
+    use crate::attribute::VaryingLayout;
+    use crate::color::{Color, Rgba};
+    use crate::geometry::{point, Point};
+    use crate::shader::{ColorShader, Uniforms};
 
     use super::*;
     use std::collections::BTreeSet;
+    use std::sync::Arc;
+
+    fn vertex(position: Point, color: &Rgba) -> ShadedVertex {
+        let mut varyings = Varyings::default();
+        varyings.push_rgba(color);
+        ShadedVertex { position, varyings }
+    }
+
+    fn shaded(interpolation: Interpolation) -> ShadedGeometry {
+        ShadedGeometry {
+            geo_type: GeometryType::Triangle,
+            vertices: vec![],
+            interpolation,
+            fragment_shader: Arc::new(ColorShader),
+            uniforms: Uniforms::default(),
+            layout: Arc::new(VaryingLayout::default()),
+        }
+    }
 
     #[test]
     fn test_point() {
@@ -229,8 +510,13 @@ mod tests {
         let c = Rgba::color(1.0, 0.0, 0.0);
         let target = vec![ToDraw::new(x, y, c.clone(), 0.0)];
         let mut calculated = vec![];
-        let vertex = point(x as f32, y as f32, 0.0);
-        draw_line(&vertex, &vertex, &c, &c, &mut calculated);
+        let location = point(x as f32, y as f32, 0.0);
+        draw_line(
+            &vertex(location, &c),
+            &vertex(location, &c),
+            &shaded(Interpolation::Perspective),
+            &mut calculated,
+        );
         assert_eq!(calculated, target);
     }
 
@@ -251,7 +537,12 @@ mod tests {
                 vertex2.x += x;
                 vertex2.y += y;
                 let mut line = vec![];
-                draw_line(&vertex1, &vertex2, &c, &c, &mut line);
+                draw_line(
+                    &vertex(vertex1, &c),
+                    &vertex(vertex2, &c),
+                    &shaded(Interpolation::Perspective),
+                    &mut line,
+                );
                 let target_point = ToDraw::new(
                     (vertex1.x + x) as i32,
                     (vertex1.y + y) as i32,
@@ -280,10 +571,9 @@ mod tests {
         ];
         let mut computed_line = vec![];
         draw_line(
-            &point(x0, y0, 0.0),
-            &point(x1, y1, 0.0),
-            &c,
-            &c,
+            &vertex(point(x0, y0, 0.0), &c),
+            &vertex(point(x1, y1, 0.0), &c),
+            &shaded(Interpolation::Perspective),
             &mut computed_line,
         );
         assert_eq!(
@@ -292,6 +582,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_line_stays_in_bounds() {
+        let c = Rgba::color(1.0, 0.0, 0.0);
+        let ends = [
+            ((1.3, 60.6), (198.6, 67.4)),
+            ((197.7, 130.2), (2.4, 126.5)),
+            ((63.5, 0.5), (66.4, 149.3)),
+        ];
+        for ((x0, y0), (x1, y1)) in ends {
+            let geometry = ShadedGeometry {
+                geo_type: GeometryType::Lines,
+                vertices: vec![
+                    vertex(point(x0, y0, 0.0), &c),
+                    vertex(point(x1, y1, 0.0), &c),
+                ],
+                ..shaded(Interpolation::Perspective)
+            };
+            let mut line = vec![];
+            rasterize_primitive(&geometry, 0, &mut line);
+            let [x_min, y_min, x_max, y_max] = primitive_bounds(&geometry, 0);
+            for pixel in &line {
+                assert!((x_min..=x_max).contains(&pixel.x));
+                assert!((y_min..=y_max).contains(&pixel.y));
+            }
+            for (x, y) in [(x0, y0), (x1, y1)] {
+                let end = (x.round() as i32, y.round() as i32);
+                assert!(line.iter().any(|pixel| (pixel.x, pixel.y) == end));
+            }
+        }
+    }
+
     #[test]
     fn test_triangle() {
         let color: Rgba = (&Color::Red).into();
@@ -300,29 +621,19 @@ mod tests {
         let v3 = point(1.0, 1.0, 0.0);
         let mut computed_triangle = vec![];
         rasterize_triangle(
-            &v1,
-            &v2,
-            &v3,
-            &color,
-            &color,
-            &color,
+            [
+                &vertex(v1, &color),
+                &vertex(v2, &color),
+                &vertex(v3, &color),
+            ],
+            &shaded(Interpolation::Perspective),
+            Kernel::detect(),
             &mut computed_triangle,
         );
-        let target_triangle = vec![
-            ToDraw::new(v1.x as i32, v1.y as i32, color.clone(), 0.0),
-            ToDraw::new(v2.x as i32, v2.y as i32, color.clone(), 0.0),
-            ToDraw::new(v3.x as i32, v3.y as i32, color.clone(), 0.0),
-            ToDraw::new(
-                (v1.x as i32 + v2.x as i32) / 2,
-                v1.y as i32,
-                color.clone(),
-                0.0,
-            ),
-        ];
-        assert_eq!(
-            BTreeSet::from_iter(target_triangle.into_iter()),
-            BTreeSet::from_iter(computed_triangle.into_iter())
-        );
+        // (0.5, 0.5) is on the left edge and drawn, (1.5, 0.5) is on the
+        // right edge and left to whatever is next to it
+        let target_triangle = vec![ToDraw::new(0, 0, color.clone(), 0.0)];
+        assert_eq!(computed_triangle, target_triangle);
     }
 
     #[test]
@@ -333,28 +644,110 @@ mod tests {
         let v3 = point(1.1, 0.9, 0.0);
         let mut computed_triangle = vec![];
         rasterize_triangle(
-            &v1,
-            &v2,
-            &v3,
-            &color,
-            &color,
-            &color,
+            [
+                &vertex(v1, &color),
+                &vertex(v2, &color),
+                &vertex(v3, &color),
+            ],
+            &shaded(Interpolation::Perspective),
+            Kernel::detect(),
             &mut computed_triangle,
         );
-        let target_triangle = vec![
-            ToDraw::new(v1.x.round() as i32, v1.y.round() as i32, color.clone(), 0.0),
-            ToDraw::new(v2.x.round() as i32, v2.y.round() as i32, color.clone(), 0.0),
-            ToDraw::new(v3.x.round() as i32, v3.y.round() as i32, color.clone(), 0.0),
-            ToDraw::new(
-                (v1.x.round() as i32 + v2.x.round() as i32) / 2,
-                v1.y.round() as i32,
-                color.clone(),
-                0.0,
-            ),
+        // only the center at (1.5, 0.5) is inside
+        let target_triangle = vec![ToDraw::new(1, 0, color.clone(), 0.0)];
+        assert_eq!(computed_triangle, target_triangle);
+    }
+
+    #[test]
+    fn test_shared_edges_cover_pixels_once() {
+        let color: Rgba = (&Color::Green).into();
+        // a fan around an off-center point, filling a rectangle whose top
+        // edge runs through pixel centers
+        let center = (20.3, 17.7);
+        let outline = [
+            (1.25, 2.5),
+            (21.0, 2.5),
+            (40.75, 2.5),
+            (40.75, 17.0),
+            (40.75, 33.1),
+            (13.6, 33.1),
+            (1.25, 33.1),
+            (1.25, 12.8),
         ];
+        let geometry = shaded(Interpolation::Perspective);
+        let mut scalar = vec![];
+        let mut computed: Vec<Vec<ToDraw>> = Kernel::available().iter().map(|_| vec![]).collect();
+        for i in 0..outline.len() {
+            let corners = [center, outline[i], outline[(i + 1) % outline.len()]];
+            let vertices = corners.map(|(x, y)| vertex(point(x, y, 0.0), &color));
+            let vertices = [&vertices[0], &vertices[1], &vertices[2]];
+            rasterize_triangle(vertices, &geometry, Kernel::Scalar, &mut scalar);
+            for (kernel, computed) in Kernel::available().into_iter().zip(&mut computed) {
+                rasterize_triangle(vertices, &geometry, kernel, computed);
+            }
+        }
+        for computed in computed {
+            assert_eq!(computed, scalar);
+        }
+        let mut covered: Vec<_> = scalar.iter().map(|f| (f.x, f.y)).collect();
+        covered.sort();
+        let expected: Vec<_> = (1..=40)
+            .flat_map(|x| (2..=32).map(move |y| (x, y)))
+            .collect();
+        assert_eq!(covered, expected);
+    }
+
+    #[test]
+    fn test_triangle_perspective_interpolation() {
+        let red: Rgba = (&Color::Red).into();
+        let blue: Rgba = (&Color::Blue).into();
+        // the top edge runs through the centers of the first row
+        let v1 = point(-0.5, 0.5, 0.0);
+        // three times as far from the camera as v1
+        let mut v2 = point(3.5, 0.5, 0.0);
+        v2.w = 1.0 / 3.0;
+        let v3 = point(-0.5, 4.5, 0.0);
+        let halfway = |interpolation| {
+            let mut computed = vec![];
+            rasterize_triangle(
+                [&vertex(v1, &red), &vertex(v2, &blue), &vertex(v3, &red)],
+                &shaded(interpolation),
+                Kernel::detect(),
+                &mut computed,
+            );
+            computed
+                .into_iter()
+                .find(|fragment| fragment.x == 1 && fragment.y == 0)
+                .unwrap()
+                .color
+        };
+        assert_eq!(
+            halfway(Interpolation::Linear),
+            Rgba::color_a(0.5, 0.0, 0.5, 1.0)
+        );
         assert_eq!(
-            BTreeSet::from_iter(target_triangle.into_iter()),
-            BTreeSet::from_iter(computed_triangle.into_iter())
+            halfway(Interpolation::Perspective),
+            Rgba::color_a(0.75, 0.0, 0.25, 1.0)
+        );
+    }
+
+    #[test]
+    fn test_line_perspective_interpolation() {
+        let red: Rgba = (&Color::Red).into();
+        let blue: Rgba = (&Color::Blue).into();
+        let v1 = point(0.0, 0.0, 0.0);
+        let mut v2 = point(4.0, 0.0, 1.0);
+        v2.w = 1.0 / 3.0;
+        let mut computed = vec![];
+        draw_line(
+            &vertex(v1, &red),
+            &vertex(v2, &blue),
+            &shaded(Interpolation::Perspective),
+            &mut computed,
         );
+        let halfway = computed.iter().find(|fragment| fragment.x == 2).unwrap();
+        assert_eq!(halfway.color, Rgba::color_a(0.75, 0.0, 0.25, 1.0));
+        // depth stays linear in screen space
+        assert_eq!(halfway.depth, OrdFloat(0.5));
     }
 }
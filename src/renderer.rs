@@ -0,0 +1,842 @@
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::color::{Color, Rgba};
+use crate::geometry::ShadedGeometry;
+use crate::math::OrdFloat;
+use crate::rasterizer::{
+    primitive_bounds, primitive_count, rasterize_geometry, rasterize_primitive, Coverage, Msaa,
+    RenderTarget, ToDraw,
+};
+use crate::world::{Camera, World};
+
+/// Width and height in pixels of the tiles the screen is split into when
+/// rendering on several threads. Even, so 2x2 quads never straddle tiles.
+pub const TILE_SIZE: usize = 64;
+
+/// Final color output of a frame, stored row by row starting at the top left.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgba>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize, clear_color: &Rgba) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![clear_color.clone(); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Rgba] {
+        &self.pixels
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<&Rgba> {
+        xy_to_1d(x as i32, y as i32, self.width as i32, self.height as i32)
+            .map(|index| &self.pixels[index])
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Rgba) {
+        if let Some(index) = xy_to_1d(x as i32, y as i32, self.width as i32, self.height as i32) {
+            self.pixels[index] = color;
+        }
+    }
+
+    pub fn clear(&mut self, color: &Rgba) {
+        for pixel in &mut self.pixels {
+            *pixel = color.clone();
+        }
+    }
+
+    /// Packs every pixel as 0RGB for display surfaces such as minifb.
+    /// `buffer` must hold at least width * height entries.
+    pub fn copy_to_u32(&self, buffer: &mut [u32]) {
+        for (out, pixel) in buffer.iter_mut().zip(&self.pixels) {
+            *out = u32::from(pixel);
+        }
+    }
+
+    pub fn to_u32(&self) -> Vec<u32> {
+        self.pixels.iter().map(u32::from).collect()
+    }
+}
+
+/// Owns the color and depth buffers along with the scratch space needed to
+/// turn a `World` into a `Framebuffer`. The buffers are reused across frames.
+pub struct Renderer {
+    framebuffer: Framebuffer,
+    msaa: Msaa,
+    /// Colors of every sample of each pixel in turn, resolved into the
+    /// framebuffer at the end of a frame. Empty unless multisampling.
+    samples: Vec<Rgba>,
+    /// One depth per sample.
+    depth_buffer: Vec<OrdFloat>,
+    /// Translucent fragments, blended after everything opaque is drawn.
+    transparent: Vec<ToDraw>,
+    clear_color: Rgba,
+    threads: usize,
+    /// Row by row, created on the first multithreaded frame.
+    tiles: Vec<Mutex<Tile>>,
+    /// Helper threads kept across frames, started on the first
+    /// multithreaded frame. The calling thread draws tiles alongside them.
+    workers: Vec<Worker>,
+}
+
+impl Renderer {
+    pub fn new(width: usize, height: usize) -> Self {
+        let clear_color = Rgba::from(&Color::Black);
+        Self {
+            framebuffer: Framebuffer::new(width, height, &clear_color),
+            msaa: Msaa::Off,
+            samples: vec![],
+            depth_buffer: vec![OrdFloat(-f32::INFINITY); width * height],
+            transparent: vec![],
+            clear_color,
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            tiles: vec![],
+            workers: vec![],
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Number of threads rasterizing a frame, defaulting to the available
+    /// parallelism. With more than one, the screen is split into tiles that
+    /// are drawn independently; the output is the same either way. The
+    /// helper threads are started once and kept until the renderer is dropped.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn msaa(&self) -> Msaa {
+        self.msaa
+    }
+
+    /// Anti-aliases triangle edges with `msaa` samples per pixel, at the cost
+    /// of a color and depth per sample.
+    pub fn set_msaa(&mut self, msaa: Msaa) {
+        let size = self.width() * self.height() * msaa.samples();
+        self.msaa = msaa;
+        self.depth_buffer = vec![OrdFloat(-f32::INFINITY); size];
+        self.samples = match msaa {
+            Msaa::Off => vec![],
+            _ => vec![self.clear_color.clone(); size],
+        };
+        // rebuilt with buffers of the new size on the next frame
+        self.tiles.clear();
+    }
+
+    pub fn width(&self) -> usize {
+        self.framebuffer.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.framebuffer.height
+    }
+
+    pub fn set_clear_color(&mut self, color: Color) {
+        self.clear_color = (&color).into();
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// For drawing on top of the last frame, e.g. text overlays.
+    pub fn framebuffer_mut(&mut self) -> &mut Framebuffer {
+        &mut self.framebuffer
+    }
+
+    pub fn render(&mut self, world: &World, camera: &Camera, time: f32) -> &Framebuffer {
+        self.clear();
+        let width = self.width();
+        let height = self.height();
+        let to_render = camera.world_view(world, width as f32, height as f32, time);
+        let objects: Vec<ShadedGeometry> = to_render
+            .into_iter()
+            .filter_map(|obj| match obj {
+                Ok(obj) => Some(obj),
+                Err(error) => {
+                    eprintln!("{error}");
+                    None
+                }
+            })
+            .collect();
+        if width == 0 || height == 0 {
+            return &self.framebuffer;
+        }
+        if self.threads > 1 {
+            self.render_tiles(objects);
+        } else {
+            let samples = match self.msaa {
+                Msaa::Off => &mut self.framebuffer.pixels,
+                _ => &mut self.samples,
+            };
+            let mut target = FrameTarget {
+                columns: 0..width,
+                rows: 0..height,
+                msaa: self.msaa,
+                samples,
+                depth_buffer: &mut self.depth_buffer,
+                transparent: &mut self.transparent,
+            };
+            for obj in &objects {
+                rasterize_geometry(obj, &mut target);
+            }
+            target.blend_transparent();
+            if self.msaa != Msaa::Off {
+                resolve(&self.samples, &mut self.framebuffer.pixels);
+            }
+        }
+        &self.framebuffer
+    }
+
+    /// Bins every primitive into the tiles its bounds overlap, then lets the
+    /// worker threads take tiles until none are left. Each tile draws its
+    /// primitives in submission order, so every pixel sees the same fragments
+    /// in the same order as when drawing the whole frame at once.
+    fn render_tiles(&mut self, objects: Vec<ShadedGeometry>) {
+        let (width, height) = (self.width(), self.height());
+        let columns = width.div_ceil(TILE_SIZE);
+        if self.tiles.is_empty() {
+            self.tiles = (0..height.div_ceil(TILE_SIZE))
+                .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                .map(|(column, row)| {
+                    let x = column * TILE_SIZE;
+                    let y = row * TILE_SIZE;
+                    Mutex::new(Tile::new(
+                        x..(x + TILE_SIZE).min(width),
+                        y..(y + TILE_SIZE).min(height),
+                        self.msaa,
+                    ))
+                })
+                .collect();
+        }
+        for tile in &mut self.tiles {
+            tile.get_mut().unwrap().bin.clear();
+        }
+        for (object, obj) in objects.iter().enumerate() {
+            for primitive in 0..primitive_count(obj) {
+                let [x_min, y_min, x_max, y_max] = primitive_bounds(obj, primitive);
+                if x_max < 0 || y_max < 0 || x_min >= width as i32 || y_min >= height as i32 {
+                    continue;
+                }
+                let first_column = x_min.max(0) as usize / TILE_SIZE;
+                let last_column = (x_max as usize).min(width - 1) / TILE_SIZE;
+                let first_row = y_min.max(0) as usize / TILE_SIZE;
+                let last_row = (y_max as usize).min(height - 1) / TILE_SIZE;
+                for row in first_row..=last_row {
+                    for column in first_column..=last_column {
+                        let tile = self.tiles[row * columns + column].get_mut().unwrap();
+                        tile.bin.push((object, primitive));
+                    }
+                }
+            }
+        }
+
+        let helpers = self.threads.min(self.tiles.len()) - 1;
+        while self.workers.len() < helpers {
+            self.workers.push(Worker::spawn());
+        }
+        let frame = Arc::new(Frame {
+            objects,
+            tiles: std::mem::take(&mut self.tiles),
+            clear_color: self.clear_color.clone(),
+            next: AtomicUsize::new(0),
+        });
+        let (done, finished) = mpsc::channel();
+        for worker in &self.workers[..helpers] {
+            let job = Job {
+                frame: frame.clone(),
+                done: done.clone(),
+            };
+            worker
+                .jobs
+                .send(job)
+                .expect("workers run until the renderer is dropped");
+        }
+        drop(done);
+        frame.work();
+        // wait until every job is dropped, which releases its frame first
+        let mut failure = None;
+        while let Ok(result) = finished.recv() {
+            failure = failure.or(result.err());
+        }
+        if let Some(payload) = failure {
+            // the tiles are left behind, poisoned, and rebuilt next frame
+            panic::resume_unwind(payload);
+        }
+        let frame = Arc::into_inner(frame).expect("workers hold no frame once finished");
+        self.tiles = frame.tiles;
+
+        for tile in &mut self.tiles {
+            let tile = tile.get_mut().unwrap();
+            let tile_width = tile.columns.len();
+            let row_samples = tile_width * self.msaa.samples();
+            for (row, samples) in tile.rows.clone().zip(tile.samples.chunks(row_samples)) {
+                let start = row * width + tile.columns.start;
+                resolve(
+                    samples,
+                    &mut self.framebuffer.pixels[start..start + tile_width],
+                );
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.framebuffer.clear(&self.clear_color);
+        self.samples.fill(self.clear_color.clone());
+        self.depth_buffer.fill(OrdFloat(-f32::INFINITY));
+        self.transparent.clear();
+    }
+}
+
+impl Drop for Renderer {
+    /// Hangs up on the workers, which then return, and waits for them.
+    fn drop(&mut self) {
+        for Worker { jobs, handle } in self.workers.drain(..) {
+            drop(jobs);
+            let _ = handle.join();
+        }
+    }
+}
+
+/// A persistent helper thread that draws tiles of each frame it is sent.
+struct Worker {
+    jobs: Sender<Job>,
+    handle: JoinHandle<()>,
+}
+
+impl Worker {
+    fn spawn() -> Self {
+        let (jobs, received) = mpsc::channel::<Job>();
+        let handle = thread::spawn(move || {
+            for job in received {
+                // a panicking shader is passed on to the renderer's thread,
+                // and the worker carries on with the next frame
+                let result = panic::catch_unwind(AssertUnwindSafe(|| job.frame.work()));
+                let _ = job.done.send(result);
+            }
+        });
+        Self { jobs, handle }
+    }
+}
+
+/// One frame's share of work for a worker. Fields drop in order, so the
+/// frame is released before `done` hangs up.
+struct Job {
+    frame: Arc<Frame>,
+    /// Whether drawing finished, or what it panicked with.
+    done: Sender<thread::Result<()>>,
+}
+
+/// Everything the threads drawing a frame share.
+struct Frame {
+    objects: Vec<ShadedGeometry>,
+    tiles: Vec<Mutex<Tile>>,
+    clear_color: Rgba,
+    /// Index of the next tile to be taken.
+    next: AtomicUsize,
+}
+
+impl Frame {
+    /// Takes tiles and draws them until none are left.
+    fn work(&self) {
+        loop {
+            let index = self.next.fetch_add(1, Ordering::Relaxed);
+            let Some(tile) = self.tiles.get(index) else {
+                break;
+            };
+            tile.lock()
+                .unwrap()
+                .render(&self.objects, &self.clear_color);
+        }
+    }
+}
+
+/// A rectangle of the frame with its own buffers, drawn by one thread.
+struct Tile {
+    columns: Range<usize>,
+    rows: Range<usize>,
+    msaa: Msaa,
+    samples: Vec<Rgba>,
+    depth_buffer: Vec<OrdFloat>,
+    transparent: Vec<ToDraw>,
+    /// Object and primitive indices overlapping the tile, in drawing order.
+    bin: Vec<(usize, usize)>,
+}
+
+impl Tile {
+    fn new(columns: Range<usize>, rows: Range<usize>, msaa: Msaa) -> Self {
+        let size = columns.len() * rows.len() * msaa.samples();
+        Self {
+            columns,
+            rows,
+            msaa,
+            samples: vec![Rgba::from(&Color::Black); size],
+            depth_buffer: vec![OrdFloat(-f32::INFINITY); size],
+            transparent: vec![],
+            bin: vec![],
+        }
+    }
+
+    fn render(&mut self, objects: &[ShadedGeometry], clear_color: &Rgba) {
+        self.samples.fill(clear_color.clone());
+        self.depth_buffer.fill(OrdFloat(-f32::INFINITY));
+        let mut target = FrameTarget {
+            columns: self.columns.clone(),
+            rows: self.rows.clone(),
+            msaa: self.msaa,
+            samples: &mut self.samples,
+            depth_buffer: &mut self.depth_buffer,
+            transparent: &mut self.transparent,
+        };
+        for &(object, primitive) in &self.bin {
+            rasterize_primitive(&objects[object], primitive, &mut target);
+        }
+        target.blend_transparent();
+    }
+}
+
+/// Depth tests fragments against the frame, or a tile of it, as they come
+/// out of the rasterizer, writing opaque ones straight into it and setting
+/// translucent ones aside to be blended in depth order. Every sample has its
+/// own color and depth.
+struct FrameTarget<'a> {
+    columns: Range<usize>,
+    rows: Range<usize>,
+    msaa: Msaa,
+    /// Covers only `columns` and `rows`, row by row, with the samples of
+    /// each pixel in turn.
+    samples: &'a mut [Rgba],
+    depth_buffer: &'a mut [OrdFloat],
+    transparent: &'a mut Vec<ToDraw>,
+}
+
+impl FrameTarget<'_> {
+    /// Index of the first sample of pixel (x, y).
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        xy_to_1d(
+            x - self.columns.start as i32,
+            y - self.rows.start as i32,
+            self.columns.len() as i32,
+            self.rows.len() as i32,
+        )
+        .map(|pixel| pixel * self.msaa.samples())
+    }
+
+    /// Layers the translucent fragments on top of the opaque ones, farthest
+    /// first, one sample at a time. Each blend writes its depth and later
+    /// fragments must be strictly nearer, so where translucent surfaces are
+    /// coplanar only the first fragment drawn is kept. The sort is stable,
+    /// which makes that the same fragment however the frame is split up.
+    fn blend_transparent(self) {
+        self.transparent.sort_by_key(|fragment| fragment.depth);
+        for fragment in std::mem::take(self.transparent) {
+            if let Some(index) = self.index(fragment.x, fragment.y) {
+                let index = index + fragment.sample;
+                if fragment.depth > self.depth_buffer[index] {
+                    self.samples[index].over_blend(fragment.color);
+                    self.depth_buffer[index] = fragment.depth;
+                }
+            }
+        }
+    }
+}
+
+impl RenderTarget for FrameTarget<'_> {
+    fn msaa(&self) -> Msaa {
+        self.msaa
+    }
+
+    fn depth_test(&self, x: i32, y: i32, coverage: &Coverage) -> u8 {
+        let Some(index) = self.index(x, y) else {
+            return 0;
+        };
+        coverage
+            .samples()
+            .filter(|&(sample, depth)| OrdFloat(depth) > self.depth_buffer[index + sample])
+            .fold(0, |mask, (sample, _)| mask | 1 << sample)
+    }
+
+    fn write(&mut self, x: i32, y: i32, coverage: &Coverage, color: Rgba) {
+        let Some(index) = self.index(x, y) else {
+            return;
+        };
+        for (sample, depth) in coverage.samples() {
+            if color.a == OrdFloat(1.0) {
+                self.samples[index + sample] = color.clone();
+                self.depth_buffer[index + sample] = OrdFloat(depth);
+            } else {
+                self.transparent.push(ToDraw {
+                    sample,
+                    ..ToDraw::new(x, y, color.clone(), depth)
+                });
+            }
+        }
+    }
+
+    fn bounds(&self) -> (Range<usize>, Range<usize>) {
+        (self.columns.clone(), self.rows.clone())
+    }
+}
+
+/// Averages the samples of each pixel into `pixels`.
+fn resolve(samples: &[Rgba], pixels: &mut [Rgba]) {
+    let count = samples.len() / pixels.len();
+    if count == 1 {
+        pixels.clone_from_slice(samples);
+        return;
+    }
+    for (pixel, samples) in pixels.iter_mut().zip(samples.chunks(count)) {
+        let mut sum = samples[0].clone();
+        for sample in &samples[1..] {
+            sum += sample.clone();
+        }
+        *pixel = &sum * (1.0 / count as f32);
+    }
+}
+
+/// Renders a single frame without any window or display, returning the color
+/// output. Prefer keeping a `Renderer` around when drawing several frames so
+/// the buffers are not reallocated each time.
+pub fn render_headless(
+    world: &World,
+    camera: &Camera,
+    width: usize,
+    height: usize,
+    time: f32,
+) -> Framebuffer {
+    let mut renderer = Renderer::new(width, height);
+    renderer.render(world, camera, time);
+    Framebuffer {
+        pixels: std::mem::take(&mut renderer.framebuffer.pixels),
+        ..renderer.framebuffer
+    }
+}
+
+pub fn xy_to_1d(x: i32, y: i32, width: i32, height: i32) -> Option<usize> {
+    if x >= width || x < 0 || y < 0 || y >= height {
+        None
+    } else {
+        Some((y * width + x) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{direction, line, point, quad, triangle, Geometry, GeometryType, Vertex};
+    use crate::shader::{FragmentInput, FragmentShader, Uniforms};
+    use nalgebra as na;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_xy_to_1d() {
+        let height = 500;
+        let width = 500;
+        assert_eq!(xy_to_1d(0, 0, width, height), Some(0));
+        assert_eq!(xy_to_1d(0, 1, width, height), Some(500));
+        assert_eq!(xy_to_1d(499, 499, width, height), Some(249999));
+        assert_eq!(xy_to_1d(500, 499, width, height), None);
+    }
+
+    #[test]
+    fn test_render_depth_and_blend() {
+        let mut world = World::default();
+        let mut back = triangle();
+        back.set_name(Some("Back".to_string()));
+        back.scale(na::matrix![20.0; 20.0; 0.0]);
+        back.translate(direction(50.0, 50.0, 0.0));
+        back.set_color(Color::Red);
+        let mut front = back.clone();
+        front.set_name(Some("Front".to_string()));
+        front.translate(direction(0.0, 0.0, 1.0));
+        front.set_color(Color::Custom(0.0, 0.0, 1.0, 0.5));
+        world.insert(front);
+        world.insert(back);
+        let camera = Camera::new(0.0, 0.0, 100.0, 100.0, 0.0);
+        let mut renderer = Renderer::new(100, 100);
+        let frame = renderer.render(&world, &camera, 0.0);
+        assert_eq!(frame.get(50, 50), Some(&Rgba::color_a(0.5, 0.0, 0.5, 1.5)));
+        assert_eq!(frame.get(0, 0), Some(&Rgba::from(&Color::Black)));
+    }
+
+    #[test]
+    fn test_translucent_square_blends_once() {
+        let mut world = World::default();
+        let mut glass = quad();
+        glass.scale(na::matrix![20.0; 20.0; 1.0]);
+        glass.translate(direction(50.0, 50.0, 0.0));
+        glass.set_color(Color::Custom(1.0, 1.0, 1.0, 0.5));
+        world.insert(glass);
+        let camera = Camera::new(0.0, 0.0, 100.0, 100.0, 0.0);
+        let frame = render_headless(&world, &camera, 100, 100, 0.0);
+        // including along the diagonal the two triangles share
+        let black = Rgba::from(&Color::Black);
+        let covered: Vec<_> = frame.pixels().iter().filter(|&p| p != &black).collect();
+        assert_eq!(covered.len(), 40 * 40);
+        assert!(covered.iter().all(|&p| p == covered[0]));
+    }
+
+    /// Counts the fragments it shades.
+    struct Counting(AtomicUsize);
+
+    impl FragmentShader for Counting {
+        fn fragment(&self, input: &FragmentInput, _uniforms: &Uniforms) -> Option<Rgba> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Some(input.varyings.rgba(0))
+        }
+    }
+
+    #[test]
+    fn test_hidden_fragments_are_not_shaded() {
+        let mut world = World::default();
+        let mut front = quad();
+        front.scale(na::matrix![20.0; 20.0; 1.0]);
+        front.translate(direction(50.0, 50.0, 1.0));
+        front.set_color(Color::Green);
+        let mut back = front.clone();
+        back.translate(direction(0.0, 0.0, -1.0));
+        let counting = Arc::new(Counting(AtomicUsize::new(0)));
+        back.set_fragment_shader(counting.clone());
+        world.insert(front.clone());
+        world.insert(back.clone());
+        let camera = Camera::new(0.0, 0.0, 100.0, 100.0, 0.0);
+        let mut renderer = Renderer::new(100, 100);
+        renderer.render(&world, &camera, 0.0);
+        assert_eq!(counting.0.load(Ordering::Relaxed), 0);
+        // drawn first, the back square is shaded and then covered
+        let mut world = World::default();
+        world.insert(back);
+        world.insert(front);
+        let frame = renderer.render(&world, &camera, 0.0);
+        assert!(counting.0.load(Ordering::Relaxed) > 0);
+        assert_eq!(frame.get(50, 50), Some(&Rgba::from(&Color::Green)));
+    }
+
+    #[test]
+    fn test_tiles_match_single_thread() {
+        let mut world = World::default();
+        for layer in 0..6 {
+            let offset = layer as f32 * 13.0;
+            let color = match layer % 3 {
+                0 => Color::Custom(0.2, 0.4, 0.8, 0.5),
+                1 => Color::Custom(0.9, 0.1, 0.1, 0.25),
+                _ => Color::Green,
+            };
+            let mut background = quad();
+            background.scale(na::matrix![60.0; 50.0; 1.0]);
+            // translucent layers share depths so their blending order matters
+            background.translate(direction(100.0 + offset, 75.0, (layer / 2) as f32));
+            background.set_color(color);
+            world.insert(background);
+            let mut front = triangle();
+            front.scale(na::matrix![70.0; 60.0; 1.0]);
+            front.translate(direction(90.0 - offset, 70.0, 3.0 - layer as f32));
+            front.set_color(color);
+            world.insert(front);
+        }
+        let mut diagonal = line();
+        diagonal.scale(na::matrix![90.0; 70.0; 1.0]);
+        diagonal.translate(direction(100.0, 75.0, 10.0));
+        diagonal.set_color(Color::White);
+        world.insert(diagonal);
+        let camera = Camera::new(0.0, 0.0, 200.0, 150.0, 0.0);
+        let mut renderer = Renderer::new(200, 150);
+        for msaa in [Msaa::Off, Msaa::X4] {
+            renderer.set_msaa(msaa);
+            renderer.set_threads(1);
+            let single = renderer.render(&world, &camera, 0.0).clone();
+            renderer.set_threads(4);
+            let tiled = renderer.render(&world, &camera, 0.0);
+            assert_eq!(single.pixels(), tiled.pixels());
+            assert_ne!(tiled.get(100, 75), Some(&Rgba::from(&Color::Black)));
+            // the workers from the last frame are reused, some of them idle
+            renderer.set_threads(2);
+            let tiled = renderer.render(&world, &camera, 0.0);
+            assert_eq!(single.pixels(), tiled.pixels());
+        }
+    }
+
+    #[test]
+    fn test_tiles_match_single_thread_for_shallow_lines() {
+        let mut lines = Geometry::new(GeometryType::Lines);
+        // long, nearly flat lines with fractional ends, crossing tile edges
+        // in both directions
+        let ends = [
+            ((1.3, 60.6), (198.6, 67.4)),
+            ((197.7, 130.2), (2.4, 126.5)),
+            ((63.5, 0.5), (66.4, 149.3)),
+            ((10.2, 63.5), (190.8, 64.5)),
+            ((-20.6, 127.6), (220.2, 129.4)),
+        ];
+        for (start, end) in ends {
+            for (x, y) in [start, end] {
+                lines
+                    .vertices
+                    .push(Vertex::new(lines.vertex_locations.len(), Color::White));
+                lines.vertex_locations.push(point(x, y, 1.0));
+            }
+        }
+        let mut world = World::default();
+        world.insert(lines);
+        let camera = Camera::new(0.0, 0.0, 200.0, 150.0, 0.0);
+        let mut renderer = Renderer::new(200, 150);
+        renderer.set_threads(1);
+        let single = renderer.render(&world, &camera, 0.0).clone();
+        renderer.set_threads(4);
+        let tiled = renderer.render(&world, &camera, 0.0);
+        assert_eq!(single.pixels(), tiled.pixels());
+        let white = Rgba::from(&Color::White);
+        assert!(
+            single
+                .pixels()
+                .iter()
+                .filter(|&pixel| pixel == &white)
+                .count()
+                > 700
+        );
+    }
+
+    #[test]
+    fn test_empty_frame() {
+        let mut world = World::default();
+        world.insert(triangle());
+        let camera = Camera::new(0.0, 0.0, 100.0, 100.0, 0.0);
+        for (width, height) in [(0, 0), (0, 10), (10, 0)] {
+            let mut renderer = Renderer::new(width, height);
+            renderer.set_threads(4);
+            renderer.set_msaa(Msaa::X4);
+            assert!(renderer.render(&world, &camera, 0.0).pixels().is_empty());
+            renderer.set_threads(1);
+            assert!(renderer.render(&world, &camera, 0.0).pixels().is_empty());
+        }
+    }
+
+    struct Panicking;
+
+    impl FragmentShader for Panicking {
+        fn fragment(&self, _input: &FragmentInput, _uniforms: &Uniforms) -> Option<Rgba> {
+            panic!("shader failed");
+        }
+    }
+
+    #[test]
+    fn test_shader_panics_reach_the_caller() {
+        let mut broken = quad();
+        broken.scale(na::matrix![200.0; 200.0; 1.0]);
+        broken.set_fragment_shader(Arc::new(Panicking));
+        let mut world = World::default();
+        world.insert(broken);
+        let camera = Camera::new(0.0, 0.0, 200.0, 150.0, 0.0);
+        let mut renderer = Renderer::new(200, 150);
+        renderer.set_threads(4);
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| {
+            renderer.render(&world, &camera, 0.0);
+        }))
+        .unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"shader failed"));
+        // the same renderer and its workers draw the next frame
+        let mut world = World::default();
+        let mut square = quad();
+        square.scale(na::matrix![200.0; 200.0; 1.0]);
+        square.set_color(Color::Green);
+        world.insert(square);
+        let frame = renderer.render(&world, &camera, 0.0);
+        assert_eq!(frame.get(199, 149), Some(&Rgba::from(&Color::Green)));
+    }
+
+    #[test]
+    fn test_msaa_smooths_edges() {
+        let mut world = World::default();
+        let mut t = triangle();
+        t.scale(na::matrix![30.0; 30.0; 1.0]);
+        t.translate(direction(50.0, 50.0, 0.0));
+        t.set_color(Color::White);
+        world.insert(t);
+        let camera = Camera::new(0.0, 0.0, 100.0, 100.0, 0.0);
+        let mut renderer = Renderer::new(100, 100);
+        let aliased = renderer.render(&world, &camera, 0.0).clone();
+        let (black, white) = (Rgba::from(&Color::Black), Rgba::from(&Color::White));
+        assert!(aliased.pixels().iter().all(|p| p == &black || p == &white));
+        for msaa in [Msaa::X2, Msaa::X4, Msaa::X8] {
+            renderer.set_msaa(msaa);
+            let smooth = renderer.render(&world, &camera, 0.0);
+            // the inside is untouched and edge pixels land in between
+            assert_eq!(smooth.get(50, 50), Some(&white));
+            assert_eq!(smooth.get(0, 0), Some(&black));
+            let partial: Vec<_> = smooth
+                .pixels()
+                .iter()
+                .filter(|&p| p != &black && p != &white)
+                .collect();
+            assert!(!partial.is_empty());
+            assert!(partial
+                .iter()
+                .all(|p| p.r > OrdFloat(0.0) && p.r < OrdFloat(1.0)));
+        }
+    }
+
+    #[test]
+    fn test_msaa_blends_translucent_samples_once() {
+        let mut world = World::default();
+        let mut glass = quad();
+        glass.scale(na::matrix![20.5; 20.5; 1.0]);
+        glass.translate(direction(50.0, 50.0, 0.0));
+        glass.set_color(Color::Custom(1.0, 1.0, 1.0, 0.5));
+        world.insert(glass);
+        let camera = Camera::new(0.0, 0.0, 100.0, 100.0, 0.0);
+        let mut renderer = Renderer::new(100, 100);
+        let aliased = renderer.render(&world, &camera, 0.0).clone();
+        renderer.set_msaa(Msaa::X4);
+        let smooth = renderer.render(&world, &camera, 0.0);
+        // fully covered pixels blend exactly as without multisampling, even
+        // along the diagonal the two triangles share
+        assert_eq!(smooth.get(50, 50), aliased.get(50, 50));
+        assert_eq!(smooth.get(40, 40), aliased.get(40, 40));
+        assert_eq!(smooth.get(60, 60), aliased.get(60, 60));
+        // and the half covered border is half as bright over the black
+        let inside = aliased.get(50, 50).unwrap();
+        let border = smooth.get(50, 29).unwrap();
+        assert_eq!(border.r, OrdFloat(inside.r.0 * 0.5));
+    }
+
+    #[test]
+    fn test_render_headless() {
+        let mut world = World::default();
+        let mut t = triangle();
+        t.set_name(Some("Headless".to_string()));
+        t.scale(na::matrix![10.0; 10.0; 0.0]);
+        t.translate(direction(16.0, 16.0, 0.0));
+        t.set_color(Color::Green);
+        world.insert(t);
+        let camera = Camera::new(0.0, 0.0, 32.0, 32.0, 0.0);
+        let frame = render_headless(&world, &camera, 32, 32, 0.0);
+        assert_eq!(frame.width(), 32);
+        assert_eq!(frame.height(), 32);
+        assert_eq!(frame.get(16, 16), Some(&Rgba::from(&Color::Green)));
+        assert_eq!(frame.get(31, 0), Some(&Rgba::from(&Color::Black)));
+        assert_eq!(frame.get(32, 0), None);
+    }
+}
@@ -0,0 +1,883 @@
+use crate::attribute::Attribute;
+use crate::color::Color;
+use crate::geometry::{
+    direction, line, point, quad, right_triangle, square, triangle, Geometry, GeometryType,
+    Interpolation, Point, Transform, Vertex,
+};
+use crate::math;
+use crate::text_file::{self, LineError};
+use crate::world::{Camera, NodeId, Projection, World};
+use crate::{gltf, obj, ply, stl};
+use nalgebra as na;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Everything a scene file describes.
+pub struct Scene {
+    pub world: World,
+    pub camera: Camera,
+    /// Clear color of the renderer.
+    pub background: Color,
+}
+
+/// Reads a scene file, see `parse`.
+pub fn load(path: impl AsRef<Path>) -> Result<Scene, LineError> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or(Path::new(""));
+    text_file::load(path, |source| parse_in(source, dir))
+}
+
+/// Builds a scene from its text description.
+///
+/// Each line holds one statement; `#` starts a comment. Angles are in
+/// degrees and colors are either a name (`black`, `red`, `green`, `blue`,
+/// `white`) or `r g b [a]`.
+///
+/// ```text
+/// background 0.1 0.1 0.1
+/// projection orthographic 1500 1000   # or: perspective <fov y> <aspect>
+/// clip -10000 10000                   # near and far planes
+/// camera_position 550 300 0
+/// camera_orientation 0 0 0            # yaw pitch roll
+///
+/// object Red triangle                 # the rest of the line is the name
+///   primitive triangle                # triangle, right_triangle, square, quad or line
+///   mesh models/tank.obj              # or load an OBJ, STL, PLY or glTF file
+///   scale 200 200 0
+///   translate 0 0 0                   # or `position` to set it outright
+///   rotate 0 0 180                    # around x, y then z
+///   color red                         # recolors every vertex so far
+/// end
+///
+/// object
+///   type lines                        # triangle, line, lines or point
+///   v 0 0 0                           # vertex location, with optional w
+///   v 1 0 0
+///   vertex 0 red                      # location index and color
+///   vertex 1 0 0 1 0.5
+/// end
+///
+/// object Arm
+///   primitive square
+///   position 100 0 0
+///   object Hand                       # moves, turns and scales with its parent
+///     primitive triangle
+///     position 0 50 0
+///   end
+/// end
+/// ```
+///
+/// `rotation_matrix` takes 9 numbers row by row instead of angles, and
+/// `interpolation linear` blends attributes in screen space. Mesh paths are
+/// relative to the scene file when it is read with `load`, and to the working
+/// directory otherwise. Without camera
+/// statements the camera is orthographic, viewing 800 x 600 units around the
+/// origin; the background defaults to black.
+pub fn parse(source: &str) -> Result<Scene, LineError> {
+    parse_in(source, Path::new(""))
+}
+
+fn parse_in(source: &str, dir: &Path) -> Result<Scene, LineError> {
+    let mut parser = Parser {
+        line: 0,
+        dir: dir.to_path_buf(),
+        world: World::default(),
+        open: Vec::new(),
+        background: Color::Black,
+        projection: Projection::Orthographic {
+            width: 800.0,
+            height: 600.0,
+        },
+        clip: None,
+        position: point(0.0, 0.0, 0.0),
+        orientation: [0.0; 3],
+    };
+    for (i, line) in source.lines().enumerate() {
+        parser.line = i + 1;
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let words: Vec<&str> = words.collect();
+        match parser.open.pop() {
+            Some(object) if keyword == "end" => parser.finish_object(object)?,
+            Some(object) if keyword == "object" => {
+                parser.open.push(object);
+                parser.open.push(OpenObject::new(line));
+            }
+            Some(mut object) => {
+                parser.object_statement(keyword, &words, &mut object.object)?;
+                parser.open.push(object);
+            }
+            None => parser.scene_statement(keyword, &words, line)?,
+        }
+    }
+    if !parser.open.is_empty() {
+        return Err(parser.error("object is missing its `end`".to_string()));
+    }
+    let [yaw, pitch, roll] = parser.orientation.map(f32::to_radians);
+    let mut camera = match parser.projection {
+        Projection::Orthographic { width, height } => Camera::orthographic(
+            parser.position,
+            width,
+            height,
+            -ORTHOGRAPHIC_DEPTH,
+            ORTHOGRAPHIC_DEPTH,
+        ),
+        Projection::Perspective { fov_y, aspect } => {
+            Camera::perspective(parser.position, fov_y, aspect, 1.0, 10000.0)
+        }
+    };
+    if let Some([near, far]) = parser.clip {
+        camera.set_clip_planes(near, far);
+    }
+    camera.set_orientation(yaw, pitch, roll);
+    Ok(Scene {
+        world: parser.world,
+        camera,
+        background: parser.background,
+    })
+}
+
+/// Matches the depth range of `Camera::new`.
+const ORTHOGRAPHIC_DEPTH: f32 = 10000.0;
+
+struct Parser {
+    line: usize,
+    /// Mesh paths are relative to it.
+    dir: PathBuf,
+    world: World,
+    /// The objects between `object` and `end`, innermost last.
+    open: Vec<OpenObject>,
+    background: Color,
+    projection: Projection,
+    clip: Option<[f32; 2]>,
+    position: Point,
+    orientation: [f32; 3],
+}
+
+/// An object being read, along with its finished children. Objects only go
+/// into the world once the outermost one ends.
+struct OpenObject {
+    object: Geometry,
+    children: Vec<OpenObject>,
+}
+
+impl OpenObject {
+    /// Starts the object declared on `line`; the rest of the line is its name.
+    fn new(line: &str) -> Self {
+        let name = line
+            .trim()
+            .strip_prefix("object")
+            .unwrap_or_default()
+            .trim();
+        let mut object = Geometry::new(GeometryType::Triangle);
+        object.set_name((!name.is_empty()).then(|| name.to_string()));
+        Self {
+            object,
+            children: Vec::new(),
+        }
+    }
+
+    /// Inserts the object, then its children below it.
+    fn insert(self, world: &mut World, parent: Option<NodeId>) {
+        let node = world.insert(self.object);
+        if let Some(parent) = parent {
+            world
+                .set_parent(node, Some(parent))
+                .expect("a new node cannot be an ancestor");
+        }
+        for child in self.children {
+            child.insert(world, Some(node));
+        }
+    }
+}
+
+impl Parser {
+    fn error(&self, message: String) -> LineError {
+        LineError::Parse {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn floats<const N: usize>(&self, words: &[&str]) -> Result<[f32; N], LineError> {
+        if words.len() != N {
+            return Err(self.error(format!("expected {N} numbers, found {}", words.len())));
+        }
+        let mut values = [0.0; N];
+        for (value, word) in values.iter_mut().zip(words) {
+            *value = word
+                .parse()
+                .map_err(|_| self.error(format!("`{word}` is not a number")))?;
+        }
+        Ok(values)
+    }
+
+    fn color(&self, words: &[&str]) -> Result<Color, LineError> {
+        match words {
+            ["black"] => Ok(Color::Black),
+            ["red"] => Ok(Color::Red),
+            ["green"] => Ok(Color::Green),
+            ["blue"] => Ok(Color::Blue),
+            ["white"] => Ok(Color::White),
+            [_, _, _] => {
+                let [r, g, b] = self.floats(words)?;
+                Ok(Color::Custom(r, g, b, 1.0))
+            }
+            [_, _, _, _] => {
+                let [r, g, b, a] = self.floats(words)?;
+                Ok(Color::Custom(r, g, b, a))
+            }
+            _ => Err(self.error("expected a color name or r g b [a]".to_string())),
+        }
+    }
+
+    fn scene_statement(
+        &mut self,
+        keyword: &str,
+        words: &[&str],
+        line: &str,
+    ) -> Result<(), LineError> {
+        match keyword {
+            "object" => self.open.push(OpenObject::new(line)),
+            "background" => self.background = self.color(words)?,
+            "projection" => {
+                self.projection = match words {
+                    ["orthographic", size @ ..] => {
+                        let [width, height] = self.floats(size)?;
+                        Projection::Orthographic { width, height }
+                    }
+                    ["perspective", values @ ..] => {
+                        let [fov_y, aspect] = self.floats(values)?;
+                        Projection::Perspective {
+                            fov_y: fov_y.to_radians(),
+                            aspect,
+                        }
+                    }
+                    _ => {
+                        return Err(self.error(
+                            "expected `orthographic <width> <height>` or \
+                             `perspective <fov y> <aspect>`"
+                                .to_string(),
+                        ))
+                    }
+                }
+            }
+            "clip" => self.clip = Some(self.floats(words)?),
+            "camera_position" => {
+                let [x, y, z] = self.floats(words)?;
+                self.position = point(x, y, z);
+            }
+            "camera_orientation" => self.orientation = self.floats(words)?,
+            "end" => return Err(self.error("`end` without an `object`".to_string())),
+            _ => return Err(self.error(format!("unknown statement `{keyword}`"))),
+        }
+        Ok(())
+    }
+
+    fn object_statement(
+        &self,
+        keyword: &str,
+        words: &[&str],
+        object: &mut Geometry,
+    ) -> Result<(), LineError> {
+        match keyword {
+            "primitive" => {
+                let primitive = match words {
+                    ["triangle"] => triangle(),
+                    ["right_triangle"] => right_triangle(),
+                    ["square"] => square(),
+                    ["quad"] => quad(),
+                    ["line"] => line(),
+                    _ => {
+                        return Err(self.error(
+                            "expected `triangle`, `right_triangle`, `square`, `quad` or `line`"
+                                .to_string(),
+                        ))
+                    }
+                };
+                set_shape(object, primitive);
+            }
+            "mesh" => {
+                if words.is_empty() {
+                    return Err(self.error("expected a mesh file".to_string()));
+                }
+                // paths may contain spaces
+                let path = self.dir.join(words.join(" "));
+                let mesh = load_mesh(&path).map_err(|message| {
+                    self.error(format!("cannot load `{}`: {message}", path.display()))
+                })?;
+                set_shape(object, mesh);
+            }
+            "type" => {
+                object.geo_type = match words {
+                    ["triangle"] => GeometryType::Triangle,
+                    ["line"] => GeometryType::Line,
+                    ["lines"] => GeometryType::Lines,
+                    ["point"] => GeometryType::Point,
+                    _ => {
+                        return Err(self
+                            .error("expected `triangle`, `line`, `lines` or `point`".to_string()))
+                    }
+                }
+            }
+            "v" => {
+                let location = match words.len() {
+                    3 => {
+                        let [x, y, z] = self.floats(words)?;
+                        point(x, y, z)
+                    }
+                    _ => na::Vector4::from(self.floats::<4>(words)?),
+                };
+                object.vertex_locations.push(location);
+            }
+            "vertex" => {
+                let Some((index, color)) = words.split_first() else {
+                    return Err(self.error("expected a location index".to_string()));
+                };
+                let index = index
+                    .parse()
+                    .map_err(|_| self.error(format!("`{index}` is not a location index")))?;
+                let color = match color {
+                    [] => Color::White,
+                    color => self.color(color)?,
+                };
+                object.vertices.push(Vertex::new(index, color));
+            }
+            "color" => object.set_color(self.color(words)?),
+            "position" => {
+                let [x, y, z] = self.floats(words)?;
+                object.set_position(point(x, y, z));
+            }
+            "translate" => {
+                let [x, y, z] = self.floats(words)?;
+                object.translate(direction(x, y, z));
+            }
+            "rotate" => {
+                let [x, y, z] = self.floats(words)?.map(f32::to_radians);
+                object.rotation(x, y, z);
+            }
+            "rotation_matrix" => {
+                let values = self.floats::<9>(words)?;
+                let mut rotation = Transform::identity();
+                rotation
+                    .fixed_view_mut::<3, 3>(0, 0)
+                    .copy_from(&na::Matrix3::from_row_slice(&values));
+                object.set_rotation_matrix(rotation);
+            }
+            "scale" => object.scale(na::Vector3::from(self.floats::<3>(words)?)),
+            "interpolation" => {
+                let interpolation = match words {
+                    ["perspective"] => Interpolation::Perspective,
+                    ["linear"] => Interpolation::Linear,
+                    _ => return Err(self.error("expected `perspective` or `linear`".to_string())),
+                };
+                object.set_interpolation(interpolation);
+            }
+            _ => return Err(self.error(format!("unknown object statement `{keyword}`"))),
+        }
+        Ok(())
+    }
+
+    fn finish_object(&mut self, open: OpenObject) -> Result<(), LineError> {
+        let object = &open.object;
+        let locations = object.vertex_locations.len();
+        if let Some(vertex) = object.vertices.iter().find(|v| v.index >= locations) {
+            return Err(self.error(format!(
+                "location index {} is out of range, the object has {locations}",
+                vertex.index
+            )));
+        }
+        let per_primitive = match object.geo_type {
+            GeometryType::Triangle => 3,
+            GeometryType::Lines => 2,
+            GeometryType::Line | GeometryType::Point => 1,
+        };
+        if !object.vertices.len().is_multiple_of(per_primitive) {
+            return Err(self.error(format!(
+                "{:?} objects need a multiple of {per_primitive} vertices, found {}",
+                object.geo_type,
+                object.vertices.len()
+            )));
+        }
+        let vertices = object.vertices.len();
+        if let Some((attribute, stream)) = object
+            .attributes()
+            .iter()
+            .find(|(_, stream)| stream.len() != vertices)
+        {
+            return Err(self.error(format!(
+                "{attribute:?} has {} values for {vertices} vertices",
+                stream.len()
+            )));
+        }
+        match self.open.last_mut() {
+            Some(parent) => parent.children.push(open),
+            None => open.insert(&mut self.world, None),
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the vertices and attribute streams of `object` with those of
+/// `shape`, keeping its name, transform and everything else.
+fn set_shape(object: &mut Geometry, shape: Geometry) {
+    let stale: Vec<Attribute> = object
+        .attributes()
+        .iter()
+        .map(|(attribute, _)| attribute.clone())
+        .collect();
+    for attribute in &stale {
+        object.remove_attribute(attribute);
+    }
+    object.geo_type = shape.geo_type.clone();
+    object.vertices = shape.vertices.clone();
+    object.vertex_locations = shape.vertex_locations.clone();
+    for (attribute, stream) in shape.attributes().iter() {
+        object.set_attribute(attribute.clone(), stream.clone());
+    }
+}
+
+/// The triangles of a mesh file, picked by its extension. Files holding
+/// several meshes, like OBJ groups or the nodes of a glTF scene, are merged
+/// into one with the node transforms baked in, keeping the attributes all of
+/// them have. Line and point parts are left out, as are glTF materials and
+/// textures.
+fn load_mesh(path: &Path) -> Result<Geometry, String> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let parts = match extension.as_deref() {
+        Some("obj") => obj::load(path).map_err(|error| error.to_string())?,
+        Some("stl") => vec![stl::load(path).map_err(|error| error.to_string())?],
+        Some("ply") => vec![ply::load(path).map_err(|error| error.to_string())?],
+        Some("gltf" | "glb") => gltf::load(path)
+            .map_err(|error| error.to_string())?
+            .world
+            .iter()
+            .map(|(_, part)| part.local_to_world(0.0, Transform::identity()))
+            .collect(),
+        _ => return Err("expected an .obj, .stl, .ply, .gltf or .glb file".to_string()),
+    };
+    let mut parts = parts
+        .into_iter()
+        .filter(|part| part.geo_type == GeometryType::Triangle);
+    let mut mesh = parts.next().ok_or("the file has no triangles")?;
+    for part in parts {
+        let offset = mesh.vertex_locations.len();
+        let corners = mesh.vertices.len();
+        mesh.vertex_locations
+            .extend_from_slice(&part.vertex_locations);
+        mesh.vertices
+            .extend(part.vertices.iter().map(|vertex| Vertex {
+                index: vertex.index + offset,
+                color: vertex.color,
+            }));
+        let attributes: Vec<Attribute> = mesh
+            .attributes()
+            .iter()
+            .map(|(attribute, _)| attribute.clone())
+            .collect();
+        for attribute in attributes {
+            let Some(stream) = part.attribute(&attribute).cloned() else {
+                mesh.remove_attribute(&attribute);
+                continue;
+            };
+            let merged = mesh.attribute_mut(&attribute).unwrap();
+            if merged.components() != stream.components() || merged.len() != corners {
+                mesh.remove_attribute(&attribute);
+                continue;
+            }
+            for value in stream.iter() {
+                merged.push(value);
+            }
+        }
+    }
+    Ok(mesh)
+}
+
+/// Saves a scene file, see `write`.
+pub fn save(scene: &Scene, path: impl AsRef<Path>) -> io::Result<()> {
+    text_file::save(path, |out| write(scene, out))
+}
+
+/// Writes a scene in the format read by `parse`. Every object is written out
+/// as explicit locations and vertices along with its transform, with children
+/// nested inside their parents, so objects read from a `mesh` file are saved
+/// inline rather than as a reference to it. Attribute streams such as texture
+/// coordinates and normals are dropped, as are shaders and animations.
+pub fn write(scene: &Scene, out: &mut impl Write) -> io::Result<()> {
+    let camera = &scene.camera;
+    writeln!(out, "background {}", color_words(&scene.background))?;
+    match camera.projection() {
+        Projection::Orthographic { width, height } => {
+            writeln!(out, "projection orthographic {width} {height}")?
+        }
+        Projection::Perspective { fov_y, aspect } => writeln!(
+            out,
+            "projection perspective {} {aspect}",
+            fov_y.to_degrees()
+        )?,
+    }
+    writeln!(out, "clip {} {}", camera.near(), camera.far())?;
+    let p = camera.position();
+    writeln!(out, "camera_position {} {} {}", p.x, p.y, p.z)?;
+    let (yaw, pitch, roll) = camera.orientation();
+    writeln!(
+        out,
+        "camera_orientation {} {} {}",
+        yaw.to_degrees(),
+        pitch.to_degrees(),
+        roll.to_degrees()
+    )?;
+    for root in scene.world.roots() {
+        writeln!(out)?;
+        write_object(&scene.world, root, "", out)?;
+    }
+    Ok(())
+}
+
+fn write_object(world: &World, node: NodeId, outer: &str, out: &mut impl Write) -> io::Result<()> {
+    let Some(object) = world.get(node) else {
+        return Ok(());
+    };
+    match object.name() {
+        // a newline in the name would end the statement early
+        Some(name) => writeln!(
+            out,
+            "{outer}object {}",
+            name.replace(['\n', '\r', '#'], " ")
+        )?,
+        None => writeln!(out, "{outer}object")?,
+    }
+    let indent = format!("{outer}  ");
+    let geo_type = match object.geo_type {
+        GeometryType::Triangle => "triangle",
+        GeometryType::Line => "line",
+        GeometryType::Lines => "lines",
+        GeometryType::Point => "point",
+    };
+    writeln!(out, "{indent}type {geo_type}")?;
+    for v in &object.vertex_locations {
+        if v.w == 1.0 {
+            writeln!(out, "{indent}v {} {} {}", v.x, v.y, v.z)?;
+        } else {
+            writeln!(out, "{indent}v {} {} {} {}", v.x, v.y, v.z, v.w)?;
+        }
+    }
+    for vertex in &object.vertices {
+        writeln!(
+            out,
+            "{indent}vertex {} {}",
+            vertex.index,
+            color_words(&vertex.color)
+        )?;
+    }
+    let scale = object.scale_matrix();
+    writeln!(
+        out,
+        "{indent}scale {} {} {}",
+        scale[(0, 0)],
+        scale[(1, 1)],
+        scale[(2, 2)]
+    )?;
+    let rotation = object.rotation_matrix();
+    match math::euler_angles(&rotation) {
+        Some(angles) => {
+            let [x, y, z] = angles.map(f32::to_degrees);
+            writeln!(out, "{indent}rotate {x} {y} {z}")?;
+        }
+        None => {
+            write!(out, "{indent}rotation_matrix")?;
+            for row in 0..3 {
+                for column in 0..3 {
+                    write!(out, " {}", rotation[(row, column)])?;
+                }
+            }
+            writeln!(out)?;
+        }
+    }
+    let translation = object.translation_matrix();
+    let t = translation.column(3);
+    writeln!(out, "{indent}position {} {} {}", t.x, t.y, t.z)?;
+    if object.interpolation() == Interpolation::Linear {
+        writeln!(out, "{indent}interpolation linear")?;
+    }
+    for &child in world.children(node) {
+        write_object(world, child, &indent, out)?;
+    }
+    writeln!(out, "{outer}end")
+}
+
+fn color_words(color: &Color) -> String {
+    match color {
+        Color::Black => "black".to_string(),
+        Color::Red => "red".to_string(),
+        Color::Green => "green".to_string(),
+        Color::Blue => "blue".to_string(),
+        Color::White => "white".to_string(),
+        Color::Custom(r, g, b, 1.0) => format!("{r} {g} {b}"),
+        Color::Custom(r, g, b, a) => format!("{r} {g} {b} {a}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::f32_equals;
+
+    const SCENE: &str = "
+# two objects
+background 0.1 0.2 0.3
+projection perspective 60 1.5
+clip 1 5000
+camera_position 550 300 800
+camera_orientation 90 0 0
+
+object Red  triangle
+  primitive triangle
+  scale 200 200 0
+  translate 10 0 0
+  translate 0 20 0
+  rotate 0 0 180
+  color red
+end
+
+object
+  type lines
+  v 0 0 0
+  v 1 0 0 2
+  vertex 0 red
+  vertex 1 0 0 1 0.5 # translucent blue
+  interpolation linear
+end
+";
+
+    fn assert_close(left: &Transform, right: &Transform) {
+        assert!((left - right).amax() < 1e-4, "{left} != {right}");
+    }
+
+    #[test]
+    fn test_parse() {
+        let scene = parse(SCENE).unwrap();
+        assert!(matches!(
+            scene.background,
+            Color::Custom(0.1, 0.2, 0.3, 1.0)
+        ));
+        let camera = &scene.camera;
+        assert_eq!(camera.position(), point(550.0, 300.0, 800.0));
+        assert!(f32_equals(
+            camera.orientation().0,
+            std::f32::consts::FRAC_PI_2
+        ));
+        assert_eq!((camera.near(), camera.far()), (1.0, 5000.0));
+        assert!(matches!(
+            camera.projection(),
+            Projection::Perspective { aspect: 1.5, .. }
+        ));
+
+        let objects: Vec<&Geometry> = scene.world.iter().map(|(_, obj)| obj).collect();
+        assert_eq!(objects.len(), 2);
+        let red = &objects[0];
+        assert_eq!(red.name(), Some("Red  triangle"));
+        assert_eq!(red.vertices.len(), 3);
+        assert!(red.vertices.iter().all(|v| matches!(v.color, Color::Red)));
+        let mut expected = triangle();
+        expected.scale(na::Vector3::new(200.0, 200.0, 0.0));
+        expected.translate(direction(10.0, 20.0, 0.0));
+        expected.rotation(0.0, 0.0, std::f32::consts::PI);
+        assert_close(&red.model_matrix(), &expected.model_matrix());
+
+        let lines = &objects[1];
+        assert_eq!(lines.name(), None);
+        assert_eq!(lines.geo_type, GeometryType::Lines);
+        assert_eq!(lines.vertex_locations[1], Point::new(1.0, 0.0, 0.0, 2.0));
+        assert!(matches!(
+            lines.vertices[1].color,
+            Color::Custom(0.0, 0.0, 1.0, 0.5)
+        ));
+        assert_eq!(lines.interpolation(), Interpolation::Linear);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut scene = parse(SCENE).unwrap();
+        // a mirrored object cannot be written as angles
+        let mut mirrored = square();
+        mirrored.set_rotation_matrix(math::scale_matrix(na::Vector3::new(1.0, -1.0, 1.0)));
+        scene.world.insert(mirrored);
+
+        let mut out = vec![];
+        write(&scene, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("rotation_matrix 1 0 0 0 -1 0 0 0 1"));
+        let read = parse(&text).unwrap();
+
+        assert_eq!(read.camera.position(), scene.camera.position());
+        assert_eq!(read.camera.projection(), scene.camera.projection());
+        assert_eq!(read.world.len(), 3);
+        for ((_, read), (_, written)) in read.world.iter().zip(scene.world.iter()) {
+            assert_eq!(read.name(), written.name());
+            assert_eq!(read.geo_type, written.geo_type);
+            assert_eq!(read.vertex_locations, written.vertex_locations);
+            let indices = |geometry: &Geometry| -> Vec<usize> {
+                geometry.vertices.iter().map(|v| v.index).collect()
+            };
+            assert_eq!(indices(read), indices(written));
+            assert_close(&read.model_matrix(), &written.model_matrix());
+        }
+    }
+
+    #[test]
+    fn test_nested_objects() {
+        let source = "
+object Arm
+  primitive square
+  position 100 0 0
+  object Hand
+    primitive triangle
+    position 0 50 0
+    object Finger
+      primitive line
+    end
+  end
+  scale 2 2 2
+end
+object Other
+  primitive triangle
+end
+";
+        let scene = parse(source).unwrap();
+        let world = &scene.world;
+        let arm = world.find_by_name("Arm").unwrap();
+        let hand = world.find_by_name("Hand").unwrap();
+        let finger = world.find_by_name("Finger").unwrap();
+        assert_eq!(world.parent(hand), Some(arm));
+        assert_eq!(world.parent(finger), Some(hand));
+        assert_eq!(world.roots().count(), 2);
+        // statements after a child still apply to the parent
+        let origin = world.world_matrix(hand, 0.0) * point(0.0, 0.0, 0.0);
+        assert_eq!(origin, point(100.0, 100.0, 0.0));
+
+        let mut out = vec![];
+        write(&scene, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("\n    object Finger\n      type line\n"));
+        let read = parse(&text).unwrap();
+        let finger = read.world.find_by_name("Finger").unwrap();
+        let hand = read.world.find_by_name("Hand").unwrap();
+        assert_eq!(read.world.parent(finger), Some(hand));
+        assert_close(
+            &read.world.world_matrix(finger, 0.0),
+            &world.world_matrix(world.find_by_name("Finger").unwrap(), 0.0),
+        );
+    }
+
+    #[test]
+    fn test_demo_scene() {
+        let scene = parse(include_str!("../scenes/demo.scene")).unwrap();
+        assert_eq!(scene.world.len(), 8);
+        assert_eq!(scene.camera.position(), point(550.0, 300.0, 0.0));
+    }
+
+    fn error_line(source: &str) -> usize {
+        match parse(source) {
+            Err(LineError::Parse { line, .. }) => line,
+            Err(other) => panic!("expected a parse error, got {other}"),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(error_line("background purple"), 1);
+        assert_eq!(error_line("\nprojection fisheye 1"), 2);
+        assert_eq!(error_line("object\n  primitive cube\nend"), 2);
+        assert_eq!(error_line("object\n  scale 1 x 1\nend"), 2);
+        assert_eq!(error_line("object\n  v 0 0 0\n  vertex 1\nend"), 4);
+        assert_eq!(error_line("object\n  v 0 0 0\n  vertex 0\nend"), 4);
+        assert_eq!(error_line("object\n  object\n  end\n"), 3);
+        assert_eq!(error_line("end"), 1);
+        assert_eq!(error_line("object\n  primitive square\n"), 2);
+        let message = parse("camera_position 1 2").err().unwrap().to_string();
+        assert_eq!(message, "line 1: expected 3 numbers, found 2");
+        assert_eq!(error_line("object\n  mesh\nend"), 2);
+        assert_eq!(error_line("object\n  mesh model.txt\nend"), 2);
+        assert_eq!(error_line("object\n  mesh /no/such/model.obj\nend"), 2);
+        // the quad's texture coordinates no longer match its vertices
+        let extended = "object\n  primitive quad\n  vertex 0\n  vertex 1\n  vertex 2\nend";
+        let message = parse(extended).err().unwrap().to_string();
+        assert_eq!(message, "line 6: TexCoord(0) has 6 values for 9 vertices");
+    }
+
+    #[test]
+    fn test_shape_replaces_attributes() {
+        let scene = parse("object\n  primitive quad\n  primitive triangle\nend").unwrap();
+        let (_, object) = scene.world.iter().next().unwrap();
+        assert_eq!(object.vertices.len(), 3);
+        assert!(object.attributes().iter().next().is_none());
+    }
+
+    #[test]
+    fn test_mesh_files() {
+        let dir = std::env::temp_dir().join(format!("scene-mesh-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // two groups, merged into one object
+        let model = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\ng a\nf 1 2 3\ng b\nf 2 4 3\n";
+        std::fs::write(dir.join("model.obj"), model).unwrap();
+        let source = "object Model\n  mesh model.obj\n  translate 5 0 0\nend\n";
+        std::fs::write(dir.join("model.scene"), source).unwrap();
+        let scene = load(dir.join("model.scene")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (_, model) = scene.world.iter().next().unwrap();
+        assert_eq!(model.name(), Some("Model"));
+        assert_eq!(model.vertex_locations.len(), 6);
+        let indices: Vec<usize> = model.vertices.iter().map(|v| v.index).collect();
+        assert_eq!(indices, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(model.vertex_locations[4], point(1.0, 1.0, 0.0));
+        assert_eq!(
+            model.model_matrix() * point(0.0, 0.0, 0.0),
+            point(5.0, 0.0, 0.0)
+        );
+        // without the file next to it, the relative path is not found
+        assert!(parse(source).is_err());
+    }
+
+    #[test]
+    fn test_mesh_skips_points_and_lines() {
+        let dir = std::env::temp_dir().join(format!("scene-gltf-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // one triangle, and the same three positions again as points
+        let model = r#"{
+            "asset": {"version": "2.0"},
+            "nodes": [{"mesh": 0}],
+            "meshes": [{"primitives": [
+                {"attributes": {"POSITION": 0}, "indices": 1},
+                {"attributes": {"POSITION": 0}, "mode": 0}
+            ]}],
+            "buffers": [{
+                "byteLength": 44,
+                "uri": "data:application/octet-stream;base64,AACAvwAAgL8AAAAAAACAPwAAgL8AAAAAAAAAAAAAgD8AAAAAAAABAAIAAAA="
+            }],
+            "bufferViews": [
+                {"buffer": 0, "byteLength": 36},
+                {"buffer": 0, "byteOffset": 36, "byteLength": 6}
+            ],
+            "accessors": [
+                {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"},
+                {"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}
+            ]
+        }"#;
+        std::fs::write(dir.join("model.gltf"), model).unwrap();
+        std::fs::write(dir.join("model.scene"), "object\n  mesh model.gltf\nend\n").unwrap();
+        let scene = load(dir.join("model.scene"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let scene = scene.unwrap();
+        let (_, model) = scene.world.iter().next().unwrap();
+        assert_eq!(model.geo_type, GeometryType::Triangle);
+        assert_eq!(model.vertices.len(), 3);
+    }
+}
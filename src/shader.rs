@@ -0,0 +1,350 @@
+use crate::attribute::{Attribute, Attributes, VaryingLayout};
+use crate::color::Rgba;
+use crate::geometry::{Point, Transform};
+use nalgebra as na;
+use std::ops::{Index, Sub};
+
+/// Most floats a vertex shader can hand to the fragment shader.
+pub const MAX_VARYINGS: usize = 32;
+
+/// Values written by the vertex shader and interpolated across a primitive
+/// before being handed to the fragment shader. What each slot means is up to
+/// the shader pair; `ColorShader` stores the vertex color in the first four.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Varyings {
+    values: [f32; MAX_VARYINGS],
+    len: usize,
+}
+
+impl Default for Varyings {
+    fn default() -> Self {
+        Self {
+            values: [0.0; MAX_VARYINGS],
+            len: 0,
+        }
+    }
+}
+
+impl Varyings {
+    pub fn from_slice(values: &[f32]) -> Self {
+        let mut varyings = Self::default();
+        varyings.extend_from_slice(values);
+        varyings
+    }
+
+    /// `len` varyings that are all zero.
+    pub fn zeros(len: usize) -> Self {
+        assert!(len <= MAX_VARYINGS, "too many varyings");
+        Self {
+            len,
+            ..Self::default()
+        }
+    }
+
+    /// Panics when more than `MAX_VARYINGS` values are pushed.
+    pub fn push(&mut self, value: f32) {
+        assert!(self.len < MAX_VARYINGS, "too many varyings");
+        self.values[self.len] = value;
+        self.len += 1;
+    }
+
+    pub fn extend_from_slice(&mut self, values: &[f32]) {
+        for value in values {
+            self.push(*value);
+        }
+    }
+
+    pub fn push_rgba(&mut self, color: &Rgba) {
+        self.extend_from_slice(&[color.r.0, color.g.0, color.b.0, color.a.0]);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        &self.values[..self.len]
+    }
+
+    /// Four consecutive values starting at `offset` read as a color.
+    pub fn rgba(&self, offset: usize) -> Rgba {
+        let v = &self.as_slice()[offset..offset + 4];
+        Rgba::color_a(v[0], v[1], v[2], v[3])
+    }
+
+    pub fn lerp(&self, other: &Varyings, t: f32) -> Varyings {
+        let mut out = *self;
+        for i in 0..self.len {
+            out.values[i] = self.values[i] + (other.values[i] - self.values[i]) * t;
+        }
+        out
+    }
+
+    /// Weighted sum of three sets of varyings, used to blend across triangles.
+    pub fn blend(varyings: [&Varyings; 3], weights: [f32; 3]) -> Varyings {
+        let mut out = *varyings[0];
+        for i in 0..out.len {
+            out.values[i] = (weights[0] * varyings[0].values[i]
+                + weights[1] * varyings[1].values[i])
+                + weights[2] * varyings[2].values[i];
+        }
+        out
+    }
+}
+
+impl Sub for &Varyings {
+    type Output = Varyings;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        let mut out = *self;
+        for i in 0..out.len {
+            out.values[i] -= rhs.values[i];
+        }
+        out
+    }
+}
+
+impl Index<usize> for Varyings {
+    type Output = f32;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.as_slice()[index]
+    }
+}
+
+/// Per object values the renderer provides to both shader stages. Values that
+/// belong to a particular effect (light directions, tints, ...) live on the
+/// shader itself.
+#[derive(Debug, Clone)]
+pub struct Uniforms {
+    pub model: Transform,
+    pub view: Transform,
+    pub projection: Transform,
+    /// projection * view * model
+    pub model_view_projection: Transform,
+    pub camera_position: Point,
+    pub time: f32,
+}
+
+impl Default for Uniforms {
+    fn default() -> Self {
+        Self {
+            model: Transform::identity(),
+            view: Transform::identity(),
+            projection: Transform::identity(),
+            model_view_projection: Transform::identity(),
+            camera_position: na::Vector4::new(0.0, 0.0, 0.0, 1.0),
+            time: 0.0,
+        }
+    }
+}
+
+pub struct VertexInput<'a> {
+    /// Local space position.
+    pub position: Point,
+    pub color: Rgba,
+    /// Position of this vertex in `Geometry::vertices`.
+    pub vertex: usize,
+    pub attributes: &'a Attributes,
+}
+
+impl VertexInput<'_> {
+    /// This vertex's value of an attribute stream.
+    pub fn attribute(&self, attribute: &Attribute) -> Option<&[f32]> {
+        self.attributes
+            .get(attribute)
+            .map(|stream| stream.get(self.vertex))
+    }
+
+    /// The vertex color followed by every attribute, laid out as described
+    /// by `VaryingLayout::pass_through`.
+    pub fn pass_through(&self) -> Varyings {
+        let mut varyings = Varyings::default();
+        varyings.push_rgba(&self.color);
+        for (_, stream) in self.attributes.iter() {
+            varyings.extend_from_slice(stream.get(self.vertex));
+        }
+        varyings
+    }
+}
+
+pub struct VertexOutput {
+    /// Clip space position.
+    pub position: Point,
+    pub varyings: Varyings,
+}
+
+pub struct FragmentInput<'a> {
+    pub x: i32,
+    pub y: i32,
+    /// Greater is closer to the camera.
+    pub depth: f32,
+    pub varyings: Varyings,
+    /// Change of the varyings towards the next pixel to the right, taken
+    /// within the 2x2 quad the fragment belongs to. Zero for lines.
+    pub ddx: Varyings,
+    /// Change of the varyings towards the next pixel below.
+    pub ddy: Varyings,
+    pub layout: &'a VaryingLayout,
+}
+
+impl FragmentInput<'_> {
+    /// Interpolated value of an attribute. Only meaningful when the vertex
+    /// shader wrote its varyings with `VertexInput::pass_through`.
+    pub fn attribute(&self, attribute: &Attribute) -> Option<&[f32]> {
+        let (offset, components) = self.layout.find(attribute)?;
+        self.varyings.as_slice().get(offset..offset + components)
+    }
+
+    /// Screen space derivatives of an attribute in x and y.
+    pub fn attribute_derivatives(&self, attribute: &Attribute) -> Option<(&[f32], &[f32])> {
+        let (offset, components) = self.layout.find(attribute)?;
+        let range = offset..offset + components;
+        Some((
+            self.ddx.as_slice().get(range.clone())?,
+            self.ddy.as_slice().get(range)?,
+        ))
+    }
+}
+
+pub trait VertexShader: Send + Sync {
+    fn vertex(&self, input: &VertexInput, uniforms: &Uniforms) -> VertexOutput;
+}
+
+/// Returning `None` discards the fragment.
+pub trait FragmentShader: Send + Sync {
+    fn fragment(&self, input: &FragmentInput, uniforms: &Uniforms) -> Option<Rgba>;
+}
+
+/// Default shader pair: transforms by the model view projection matrix and
+/// passes the vertex color and every attribute through. The fragment output
+/// is the interpolated vertex color.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ColorShader;
+
+impl VertexShader for ColorShader {
+    fn vertex(&self, input: &VertexInput, uniforms: &Uniforms) -> VertexOutput {
+        VertexOutput {
+            position: uniforms.model_view_projection * input.position,
+            varyings: input.pass_through(),
+        }
+    }
+}
+
+impl FragmentShader for ColorShader {
+    fn fragment(&self, input: &FragmentInput, _uniforms: &Uniforms) -> Option<Rgba> {
+        Some(input.varyings.rgba(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::AttributeStream;
+    use crate::color::Color;
+    use crate::geometry::{direction, triangle, GeoError};
+    use crate::renderer::render_headless;
+    use crate::world::{Camera, World};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_varyings() {
+        let a = Varyings::from_slice(&[0.0, 2.0]);
+        let b = Varyings::from_slice(&[1.0, 4.0]);
+        assert_eq!(a.lerp(&b, 0.5).as_slice(), &[0.5, 3.0]);
+        let c = Varyings::from_slice(&[2.0, 0.0]);
+        let blended = Varyings::blend([&a, &b, &c], [0.5, 0.25, 0.25]);
+        assert_eq!(blended.as_slice(), &[0.75, 2.0]);
+        assert_eq!(blended[1], 2.0);
+        assert_eq!(blended.len(), 2);
+    }
+
+    /// Pushes vertices along x and draws a checkerboard, dropping dark squares.
+    struct Checker {
+        offset: f32,
+    }
+
+    impl VertexShader for Checker {
+        fn vertex(&self, input: &VertexInput, uniforms: &Uniforms) -> VertexOutput {
+            let position = uniforms.model * input.position + direction(self.offset, 0.0, 0.0);
+            VertexOutput {
+                position: uniforms.projection * uniforms.view * position,
+                varyings: Varyings::from_slice(&[position.x, position.y]),
+            }
+        }
+    }
+
+    impl FragmentShader for Checker {
+        fn fragment(&self, input: &FragmentInput, _uniforms: &Uniforms) -> Option<Rgba> {
+            let cell = (input.varyings[0] / 4.0).floor() + (input.varyings[1] / 4.0).floor();
+            if cell.rem_euclid(2.0) == 0.0 {
+                Some(Rgba::from(&Color::White))
+            } else {
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_shaders() {
+        let mut world = World::default();
+        let mut t = triangle();
+        t.scale(nalgebra::matrix![30.0; 30.0; 0.0]);
+        t.translate(direction(32.0, 32.0, 0.0));
+        let shader = Arc::new(Checker { offset: 10.0 });
+        t.set_vertex_shader(shader.clone());
+        t.set_fragment_shader(shader);
+        world.insert(t);
+        let camera = Camera::new(0.0, 0.0, 64.0, 64.0, 0.0);
+        let frame = render_headless(&world, &camera, 64, 64, 0.0);
+        let white = Rgba::from(&Color::White);
+        let black = Rgba::from(&Color::Black);
+        // the triangle was moved right by the vertex shader
+        assert_eq!(frame.get(42, 58), Some(&white));
+        // inside the triangle, but on a discarded square
+        assert_eq!(frame.get(38, 50), Some(&black));
+        assert_eq!(frame.get(4, 61), Some(&black));
+    }
+
+    /// Shows a custom attribute as the red channel.
+    struct ShowHeat;
+
+    impl FragmentShader for ShowHeat {
+        fn fragment(&self, input: &FragmentInput, _uniforms: &Uniforms) -> Option<Rgba> {
+            let heat = input.attribute(&Attribute::Custom("heat".to_string()))?;
+            Some(Rgba::color_a(heat[0], 0.0, 0.0, 1.0))
+        }
+    }
+
+    #[test]
+    fn test_custom_attribute() {
+        let mut world = World::default();
+        let mut t = triangle();
+        t.scale(nalgebra::matrix![30.0; 30.0; 0.0]);
+        t.translate(direction(32.0, 32.0, 0.0));
+        let heat = Attribute::Custom("heat".to_string());
+        t.set_attribute(
+            heat.clone(),
+            AttributeStream::from_vec(1, vec![0.0, 1.0, 0.0]),
+        );
+        t.set_fragment_shader(Arc::new(ShowHeat));
+        world.insert(t.clone());
+        let camera = Camera::new(0.0, 0.0, 64.0, 64.0, 0.0);
+        let frame = render_headless(&world, &camera, 64, 64, 0.0);
+        // heat is 1 at the second vertex and fades towards the others
+        let near = frame.get(32, 58).unwrap().r.0;
+        let far = frame.get(10, 4).unwrap().r.0;
+        assert!(near > 0.8 && far < 0.2, "{near} {far}");
+
+        // a stream with the wrong length is reported instead of drawn
+        t.set_attribute(heat, AttributeStream::from_vec(1, vec![0.0]));
+        assert!(matches!(
+            t.shade(0.0, Uniforms::default()),
+            Err(GeoError::AttributeLength(_, _))
+        ));
+    }
+}
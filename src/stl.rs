@@ -0,0 +1,339 @@
+use crate::attribute::{Attribute, AttributeStream};
+use crate::color::Color;
+use crate::geometry::{point, Geometry, GeometryType, Point, Vertex};
+use nalgebra as na;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum StlError {
+    Io(io::Error),
+    /// Malformed file, with the line for ASCII files.
+    Parse(String),
+}
+
+impl fmt::Display for StlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StlError::Io(error) => write!(f, "could not read STL file: {error}"),
+            StlError::Parse(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for StlError {}
+
+impl From<io::Error> for StlError {
+    fn from(error: io::Error) -> Self {
+        StlError::Io(error)
+    }
+}
+
+/// Reads an ASCII or binary STL file, see `read`.
+pub fn load(path: impl AsRef<Path>) -> Result<Geometry, StlError> {
+    read(&fs::read(path)?)
+}
+
+/// Builds a white triangle geometry from either STL flavor.
+///
+/// STL repeats the corners of every facet, so identical positions are
+/// welded into one entry of `vertex_locations`. Facet normals become the
+/// `Normal` attribute of their three corners; missing (zero) normals are
+/// computed from the winding. ASCII files keep their solid's name.
+pub fn read(bytes: &[u8]) -> Result<Geometry, StlError> {
+    // binary files may also start with "solid", so trust the size first
+    let binary_size = bytes
+        .get(80..84)
+        .map(|count| 84 + 50 * u32::from_le_bytes(count.try_into().unwrap()) as usize);
+    if binary_size != Some(bytes.len()) && bytes.trim_ascii_start().starts_with(b"solid") {
+        let source = std::str::from_utf8(bytes)
+            .map_err(|_| StlError::Parse("ASCII STL is not valid UTF-8".to_string()))?;
+        read_ascii(source)
+    } else {
+        read_binary(bytes)
+    }
+}
+
+/// Collects facets, welding identical positions.
+#[derive(Default)]
+struct Builder {
+    geometry: Option<Geometry>,
+    welded: HashMap<[u32; 3], usize>,
+    normals: Vec<f32>,
+}
+
+impl Builder {
+    fn facet(&mut self, normal: [f32; 3], corners: [[f32; 3]; 3]) {
+        let geometry = self
+            .geometry
+            .get_or_insert_with(|| Geometry::new(GeometryType::Triangle));
+        for corner in corners {
+            // -0.0 and 0.0 are the same position
+            let key = corner.map(|value| (value + 0.0).to_bits());
+            let index = *self.welded.entry(key).or_insert_with(|| {
+                geometry
+                    .vertex_locations
+                    .push(point(corner[0], corner[1], corner[2]));
+                geometry.vertex_locations.len() - 1
+            });
+            geometry.vertices.push(Vertex::new(index, Color::White));
+        }
+        let normal = match na::Vector3::from(normal).try_normalize(0.0) {
+            Some(normal) => normal,
+            None => facet_normal(corners.map(|c| point(c[0], c[1], c[2]))),
+        };
+        for _ in 0..3 {
+            self.normals.extend_from_slice(normal.as_slice());
+        }
+    }
+
+    fn finish(self) -> Geometry {
+        let mut geometry = self
+            .geometry
+            .unwrap_or_else(|| Geometry::new(GeometryType::Triangle));
+        geometry.set_attribute(
+            Attribute::Normal,
+            AttributeStream::from_vec(3, self.normals),
+        );
+        geometry
+    }
+}
+
+fn read_ascii(source: &str) -> Result<Geometry, StlError> {
+    let mut builder = Builder::default();
+    let mut name = None;
+    let mut normal = [0.0; 3];
+    let mut corners = vec![];
+    for (i, line) in source.lines().enumerate() {
+        let error = |message: String| StlError::Parse(format!("line {}: {message}", i + 1));
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let floats = |words: &[&str]| -> Result<[f32; 3], StlError> {
+            match words {
+                [x, y, z] => {
+                    let parse = |word: &str| {
+                        word.parse::<f32>()
+                            .map_err(|_| error(format!("`{word}` is not a number")))
+                    };
+                    Ok([parse(x)?, parse(y)?, parse(z)?])
+                }
+                _ => Err(error("expected three numbers".to_string())),
+            }
+        };
+        match words.as_slice() {
+            [] => {}
+            ["solid", rest @ ..] => {
+                name = (!rest.is_empty()).then(|| rest.join(" "));
+            }
+            ["facet", "normal", rest @ ..] => {
+                normal = floats(rest)?;
+                corners.clear();
+            }
+            ["vertex", rest @ ..] => corners.push(floats(rest)?),
+            ["endfacet"] => {
+                let [a, b, c] = corners[..] else {
+                    return Err(error(format!(
+                        "facets need 3 vertices, found {}",
+                        corners.len()
+                    )));
+                };
+                builder.facet(normal, [a, b, c]);
+            }
+            ["outer", "loop"] | ["endloop"] | ["endsolid", ..] => {}
+            [keyword, ..] => return Err(error(format!("unexpected `{keyword}`"))),
+        }
+    }
+    let mut geometry = builder.finish();
+    geometry.set_name(name);
+    Ok(geometry)
+}
+
+fn read_binary(bytes: &[u8]) -> Result<Geometry, StlError> {
+    let Some(count) = bytes.get(80..84) else {
+        return Err(StlError::Parse(
+            "binary STL is missing its header".to_string(),
+        ));
+    };
+    let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+    let facets = &bytes[84..];
+    if facets.len() < count * 50 {
+        return Err(StlError::Parse(format!(
+            "binary STL declares {count} facets but only has room for {}",
+            facets.len() / 50
+        )));
+    }
+    let mut builder = Builder::default();
+    for facet in facets.chunks_exact(50).take(count) {
+        let float = |i: usize| f32::from_le_bytes(facet[i * 4..i * 4 + 4].try_into().unwrap());
+        let vector = |i: usize| [float(i), float(i + 1), float(i + 2)];
+        builder.facet(vector(0), [vector(3), vector(6), vector(9)]);
+    }
+    Ok(builder.finish())
+}
+
+/// Unit normal of a counter clockwise triangle.
+fn facet_normal(corners: [Point; 3]) -> na::Vector3<f32> {
+    let [a, b, c] = corners.map(|p| p.xyz());
+    (b - a)
+        .cross(&(c - a))
+        .try_normalize(0.0)
+        .unwrap_or_default()
+}
+
+/// Positions of every triangle, divided by w. Geometry is written without
+/// its model matrix; use `Geometry::local_to_world` to bake it in first.
+fn triangles(geometry: &Geometry) -> io::Result<impl Iterator<Item = [Point; 3]> + '_> {
+    if geometry.geo_type != GeometryType::Triangle {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "STL can only hold triangles",
+        ));
+    }
+    let location = |vertex: &Vertex| {
+        let location = geometry.vertex_locations[vertex.index];
+        if location.w == 0.0 || location.w == 1.0 {
+            location
+        } else {
+            location / location.w
+        }
+    };
+    Ok(geometry
+        .vertices
+        .chunks_exact(3)
+        .map(move |triangle| [&triangle[0], &triangle[1], &triangle[2]].map(location)))
+}
+
+/// Saves as binary STL.
+pub fn save(geometry: &Geometry, path: impl AsRef<Path>) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_binary(geometry, &mut out)?;
+    out.flush()
+}
+
+pub fn write_ascii(geometry: &Geometry, out: &mut impl Write) -> io::Result<()> {
+    let name = geometry.name().unwrap_or("geometry");
+    writeln!(out, "solid {name}")?;
+    for corners in triangles(geometry)? {
+        let n = facet_normal(corners);
+        writeln!(out, "facet normal {:e} {:e} {:e}", n.x, n.y, n.z)?;
+        writeln!(out, "  outer loop")?;
+        for p in corners {
+            writeln!(out, "    vertex {:e} {:e} {:e}", p.x, p.y, p.z)?;
+        }
+        writeln!(out, "  endloop")?;
+        writeln!(out, "endfacet")?;
+    }
+    writeln!(out, "endsolid {name}")
+}
+
+pub fn write_binary(geometry: &Geometry, out: &mut impl Write) -> io::Result<()> {
+    let mut header = [0u8; 80];
+    let name = geometry.name().unwrap_or_default().as_bytes();
+    // a header starting with "solid" would look like an ASCII file
+    let name = name.strip_prefix(b"solid").unwrap_or(name);
+    let len = name.len().min(80);
+    header[..len].copy_from_slice(&name[..len]);
+    out.write_all(&header)?;
+    let count = geometry.vertices.len() / 3;
+    let count = u32::try_from(count)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many triangles for STL"))?;
+    out.write_all(&count.to_le_bytes())?;
+    for corners in triangles(geometry)? {
+        let n = facet_normal(corners);
+        for value in [n.x, n.y, n.z] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        for p in corners {
+            for value in [p.x, p.y, p.z] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        // attribute byte count
+        out.write_all(&[0, 0])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{direction, quad};
+
+    const TETRA_FACE: &str = "solid part
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 0
+    vertex 1 0 0
+    vertex 0 1 0
+  endloop
+endfacet
+facet normal 0 0 0
+  outer loop
+    vertex 1 0 0
+    vertex 1 1 0
+    vertex -0 1 0
+  endloop
+endfacet
+endsolid part
+";
+
+    #[test]
+    fn test_read_ascii() {
+        let geometry = read(TETRA_FACE.as_bytes()).unwrap();
+        assert_eq!(geometry.name(), Some("part"));
+        // six corners share four positions
+        assert_eq!(geometry.vertices.len(), 6);
+        assert_eq!(geometry.vertex_locations.len(), 4);
+        let indices: Vec<usize> = geometry.vertices.iter().map(|v| v.index).collect();
+        assert_eq!(indices, vec![0, 1, 2, 1, 3, 2]);
+        // the zero normal was computed from the winding
+        let normals = geometry.attribute(&Attribute::Normal).unwrap();
+        assert_eq!(normals.get(5), &[0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_ascii_error_line() {
+        let broken = TETRA_FACE.replace("vertex 1 1 0", "vertex 1 one 0");
+        let error = read(broken.as_bytes()).unwrap_err().to_string();
+        assert_eq!(error, "line 12: `one` is not a number");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut s = quad();
+        s.set_name(Some("panel".to_string()));
+        s.translate(direction(2.0, 0.0, 0.0));
+        let world = s.local_to_world(0.0, nalgebra::Matrix4::identity());
+
+        let mut binary = vec![];
+        write_binary(&world, &mut binary).unwrap();
+        assert_eq!(binary.len(), 84 + 2 * 50);
+        let mut ascii = vec![];
+        write_ascii(&world, &mut ascii).unwrap();
+        for bytes in [binary, ascii] {
+            let read = read(&bytes).unwrap();
+            assert_eq!(read.vertex_locations.len(), 4);
+            let positions: Vec<Point> = read
+                .vertices
+                .iter()
+                .map(|v| read.vertex_locations[v.index])
+                .collect();
+            let expected: Vec<Point> = world
+                .vertices
+                .iter()
+                .map(|v| world.vertex_locations[v.index])
+                .collect();
+            assert_eq!(positions, expected);
+            assert_eq!(positions[0], point(1.0, -1.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn test_truncated_binary() {
+        let mut bytes = vec![0; 84];
+        bytes[80] = 2;
+        assert!(matches!(read(&bytes), Err(StlError::Parse(_))));
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Error from the line based text formats: OBJ meshes, scene files and
+/// animation clips.
+#[derive(Debug)]
+pub enum LineError {
+    Io(io::Error),
+    /// Malformed statement; lines count from 1.
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LineError::Io(error) => write!(f, "could not read file: {error}"),
+            LineError::Parse { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for LineError {}
+
+impl From<io::Error> for LineError {
+    fn from(error: io::Error) -> Self {
+        LineError::Io(error)
+    }
+}
+
+/// Reads the file at `path` as text and hands it to `parse`.
+pub fn load<T>(
+    path: impl AsRef<Path>,
+    parse: impl FnOnce(&str) -> Result<T, LineError>,
+) -> Result<T, LineError> {
+    parse(&fs::read_to_string(path)?)
+}
+
+/// Creates the file at `path` and lets `write` fill it through a buffer.
+pub fn save(
+    path: impl AsRef<Path>,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write(&mut out)?;
+    out.flush()
+}
@@ -0,0 +1,610 @@
+use crate::attribute::Attribute;
+use crate::color::Rgba;
+use crate::shader::{
+    ColorShader, FragmentInput, FragmentShader, Uniforms, VertexInput, VertexOutput, VertexShader,
+};
+use std::sync::Arc;
+
+/// How texture coordinates outside of 0..1 are brought back into the texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Wrap {
+    #[default]
+    Repeat,
+    /// Uses the edge texels.
+    Clamp,
+    /// Repeats, flipping every other copy.
+    Mirror,
+}
+
+impl Wrap {
+    /// Maps any texel index into `0..size`.
+    fn apply(self, index: i64, size: usize) -> usize {
+        let size = size as i64;
+        let wrapped = match self {
+            Wrap::Repeat => index.rem_euclid(size),
+            Wrap::Clamp => index.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let index = index.rem_euclid(2 * size);
+                if index < size {
+                    index
+                } else {
+                    2 * size - 1 - index
+                }
+            }
+        };
+        wrapped as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    /// The texel the coordinate falls in.
+    Nearest,
+    /// Weighted average of the four closest texel centers.
+    #[default]
+    Bilinear,
+}
+
+/// How the mip level is chosen when sampling with derivatives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MipFilter {
+    /// Always reads the full resolution level.
+    None,
+    /// Reads the closest level.
+    Nearest,
+    /// Blends the two closest levels, trilinear filtering when combined with
+    /// `Filter::Bilinear`.
+    #[default]
+    Linear,
+}
+
+/// Describes how a texture is read, independent of its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sampler {
+    pub filter: Filter,
+    pub mip_filter: MipFilter,
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap,
+    /// Most samples taken along the longer axis of the pixel's footprint.
+    /// 1 disables anisotropic filtering.
+    pub max_anisotropy: u32,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::new(Filter::default(), Wrap::default())
+    }
+}
+
+impl Sampler {
+    pub fn new(filter: Filter, wrap: Wrap) -> Self {
+        Self {
+            filter,
+            mip_filter: MipFilter::default(),
+            wrap_u: wrap,
+            wrap_v: wrap,
+            max_anisotropy: 1,
+        }
+    }
+
+    /// Bilinear filtering within and linear blending between mip levels.
+    pub fn trilinear(wrap: Wrap) -> Self {
+        Self::new(Filter::Bilinear, wrap)
+    }
+
+    /// Trilinear filtering with up to `max_anisotropy` samples per pixel.
+    pub fn anisotropic(wrap: Wrap, max_anisotropy: u32) -> Self {
+        Self {
+            max_anisotropy: max_anisotropy.max(1),
+            ..Self::trilinear(wrap)
+        }
+    }
+}
+
+/// One mip level: linear `Rgba` texels stored row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    texels: Vec<Rgba>,
+}
+
+impl Image {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn texels(&self) -> &[Rgba] {
+        &self.texels
+    }
+
+    pub fn texel(&self, x: usize, y: usize) -> &Rgba {
+        &self.texels[y * self.width + x]
+    }
+
+    /// Box filters 2x2 blocks into a level of half the size. Odd sizes repeat
+    /// the last row or column. Texels are linear, so averaging them is
+    /// correct even for textures that came from sRGB data.
+    fn downsample(&self) -> Image {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (x0, y0) = (2 * x, 2 * y);
+                let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+                let sum = &(self.texel(x0, y0) + self.texel(x1, y0))
+                    + &(self.texel(x0, y1) + self.texel(x1, y1));
+                texels.push(&sum * 0.25);
+            }
+        }
+        Image {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    fn wrapped_texel(&self, x: i64, y: i64, sampler: &Sampler) -> &Rgba {
+        let x = sampler.wrap_u.apply(x, self.width);
+        let y = sampler.wrap_v.apply(y, self.height);
+        self.texel(x, y)
+    }
+
+    fn sample(&self, u: f32, v: f32, sampler: &Sampler) -> Rgba {
+        let x = u * self.width as f32;
+        let y = v * self.height as f32;
+        match sampler.filter {
+            Filter::Nearest => self
+                .wrapped_texel(x.floor() as i64, y.floor() as i64, sampler)
+                .clone(),
+            Filter::Bilinear => {
+                // texel centers sit at half coordinates
+                let x = x - 0.5;
+                let y = y - 0.5;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let top = lerp(
+                    self.wrapped_texel(x0, y0, sampler),
+                    self.wrapped_texel(x0 + 1, y0, sampler),
+                    tx,
+                );
+                let bottom = lerp(
+                    self.wrapped_texel(x0, y0 + 1, sampler),
+                    self.wrapped_texel(x0 + 1, y0 + 1, sampler),
+                    tx,
+                );
+                lerp(&top, &bottom, ty)
+            }
+        }
+    }
+}
+
+/// Image of linear `Rgba` texels with its mip chain. Texture coordinate
+/// (0, 0) is the top left corner of the first texel and (1, 1) the bottom
+/// right corner of the last.
+///
+/// Every constructor builds the full chain down to 1x1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Texture {
+    /// Level 0 is the full resolution image.
+    levels: Vec<Image>,
+}
+
+impl Texture {
+    pub fn new(width: usize, height: usize, texels: Vec<Rgba>) -> Self {
+        assert!(width > 0 && height > 0, "textures cannot be empty");
+        assert_eq!(
+            texels.len(),
+            width * height,
+            "texel count does not match size"
+        );
+        let mut texture = Self {
+            levels: vec![Image {
+                width,
+                height,
+                texels,
+            }],
+        };
+        texture.generate_mipmaps();
+        texture
+    }
+
+    /// Builds a texture by calling `texel(x, y)` for every texel.
+    pub fn from_fn(
+        width: usize,
+        height: usize,
+        mut texel: impl FnMut(usize, usize) -> Rgba,
+    ) -> Self {
+        let texels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| texel(x, y))
+            .collect();
+        Self::new(width, height, texels)
+    }
+
+    /// Decodes image data with sRGB encoded color and linear alpha, four
+    /// bytes per texel.
+    pub fn from_srgba8(width: usize, height: usize, bytes: &[u8]) -> Self {
+        assert_eq!(
+            bytes.len(),
+            width * height * 4,
+            "byte count does not match size"
+        );
+        let texels = bytes
+            .chunks_exact(4)
+            .map(|texel| Rgba::from_srgba8([texel[0], texel[1], texel[2], texel[3]]))
+            .collect();
+        Self::new(width, height, texels)
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    pub fn texels(&self) -> &[Rgba] {
+        &self.levels[0].texels
+    }
+
+    pub fn texel(&self, x: usize, y: usize) -> &Rgba {
+        self.levels[0].texel(x, y)
+    }
+
+    /// Changes a full resolution texel and drops the now outdated mip levels;
+    /// call `generate_mipmaps` once done editing.
+    pub fn set_texel(&mut self, x: usize, y: usize, color: Rgba) {
+        self.levels.truncate(1);
+        let width = self.width();
+        self.levels[0].texels[y * width + x] = color;
+    }
+
+    /// Rebuilds every level below the full resolution one.
+    pub fn generate_mipmaps(&mut self) {
+        self.levels.truncate(1);
+        loop {
+            let last = &self.levels[self.levels.len() - 1];
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let next = last.downsample();
+            self.levels.push(next);
+        }
+    }
+
+    pub fn levels(&self) -> &[Image] {
+        &self.levels
+    }
+
+    /// Reads the full resolution level.
+    pub fn sample(&self, u: f32, v: f32, sampler: &Sampler) -> Rgba {
+        self.levels[0].sample(u, v, sampler)
+    }
+
+    /// Reads at a level of detail, where 0 is full resolution and every step
+    /// up halves it.
+    pub fn sample_lod(&self, u: f32, v: f32, lod: f32, sampler: &Sampler) -> Rgba {
+        let last = (self.levels.len() - 1) as f32;
+        let lod = lod.clamp(0.0, last);
+        match sampler.mip_filter {
+            MipFilter::None => self.sample(u, v, sampler),
+            MipFilter::Nearest => self.levels[lod.round() as usize].sample(u, v, sampler),
+            MipFilter::Linear => {
+                let lower = lod.floor();
+                let t = lod - lower;
+                let near = self.levels[lower as usize].sample(u, v, sampler);
+                if t == 0.0 {
+                    return near;
+                }
+                let far = self.levels[lower as usize + 1].sample(u, v, sampler);
+                lerp(&near, &far, t)
+            }
+        }
+    }
+
+    /// Reads with the level of detail picked from how far the coordinates
+    /// move between neighbouring pixels. `ddx` and `ddy` are the changes of
+    /// (u, v) one pixel to the right and one pixel down.
+    ///
+    /// With anisotropic filtering the level follows the shorter axis of the
+    /// pixel's footprint and several samples are averaged along the longer
+    /// one, keeping surfaces seen at grazing angles sharp.
+    pub fn sample_grad(
+        &self,
+        u: f32,
+        v: f32,
+        ddx: [f32; 2],
+        ddy: [f32; 2],
+        sampler: &Sampler,
+    ) -> Rgba {
+        let (width, height) = (self.width() as f32, self.height() as f32);
+        let texels = |d: [f32; 2]| (d[0] * width).hypot(d[1] * height);
+        let (length_x, length_y) = (texels(ddx), texels(ddy));
+        let (major, minor, axis) = if length_x >= length_y {
+            (length_x, length_y, ddx)
+        } else {
+            (length_y, length_x, ddy)
+        };
+        let samples = if sampler.max_anisotropy > 1 && minor > 0.0 {
+            ((major / minor).ceil() as u32).clamp(1, sampler.max_anisotropy)
+        } else {
+            1
+        };
+        let lod = (major / samples as f32).max(f32::MIN_POSITIVE).log2();
+        if samples == 1 {
+            return self.sample_lod(u, v, lod, sampler);
+        }
+        let mut sum = Rgba::color_a(0.0, 0.0, 0.0, 0.0);
+        for i in 0..samples {
+            // spread evenly over the footprint, centered on (u, v)
+            let offset = (i as f32 + 0.5) / samples as f32 - 0.5;
+            sum += self.sample_lod(u + axis[0] * offset, v + axis[1] * offset, lod, sampler);
+        }
+        &sum * (1.0 / samples as f32)
+    }
+}
+
+fn lerp(a: &Rgba, b: &Rgba, t: f32) -> Rgba {
+    &(a * (1.0 - t)) + &(b * t)
+}
+
+/// Shader pair that colors fragments by sampling a texture at the
+/// interpolated `TexCoord(texcoord)` attribute, picking mip levels from its
+/// screen space derivatives. With `modulate` the sample is multiplied by the
+/// vertex color. Geometry without texture coordinates falls back to its
+/// vertex colors.
+#[derive(Debug, Clone)]
+pub struct TextureShader {
+    pub texture: Arc<Texture>,
+    pub sampler: Sampler,
+    /// Texture coordinate set to sample with, 0 unless changed.
+    pub texcoord: usize,
+    pub modulate: bool,
+}
+
+impl TextureShader {
+    pub fn new(texture: Arc<Texture>, sampler: Sampler) -> Self {
+        Self {
+            texture,
+            sampler,
+            texcoord: 0,
+            modulate: false,
+        }
+    }
+}
+
+impl VertexShader for TextureShader {
+    fn vertex(&self, input: &VertexInput, uniforms: &Uniforms) -> VertexOutput {
+        ColorShader.vertex(input, uniforms)
+    }
+}
+
+impl FragmentShader for TextureShader {
+    fn fragment(&self, input: &FragmentInput, uniforms: &Uniforms) -> Option<Rgba> {
+        let texcoord = Attribute::TexCoord(self.texcoord);
+        let (Some(uv), Some((ddx, ddy))) = (
+            input.attribute(&texcoord),
+            input.attribute_derivatives(&texcoord),
+        ) else {
+            return ColorShader.fragment(input, uniforms);
+        };
+        let texel = self.texture.sample_grad(
+            uv[0],
+            uv[1],
+            [ddx[0], ddx[1]],
+            [ddy[0], ddy[1]],
+            &self.sampler,
+        );
+        if self.modulate {
+            Some(&texel * &input.varyings.rgba(0))
+        } else {
+            Some(texel)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::geometry::{direction, quad};
+    use crate::math::f32_equals;
+    use crate::renderer::render_headless;
+    use crate::world::{Camera, World};
+
+    /// 2x1 texture, black on the left and white on the right.
+    fn black_white() -> Texture {
+        Texture::new(
+            2,
+            1,
+            vec![Rgba::from(&Color::Black), Rgba::from(&Color::White)],
+        )
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(Wrap::Repeat.apply(-1, 4), 3);
+        assert_eq!(Wrap::Repeat.apply(5, 4), 1);
+        assert_eq!(Wrap::Clamp.apply(-3, 4), 0);
+        assert_eq!(Wrap::Clamp.apply(9, 4), 3);
+        assert_eq!(Wrap::Mirror.apply(-1, 4), 0);
+        assert_eq!(Wrap::Mirror.apply(4, 4), 3);
+        assert_eq!(Wrap::Mirror.apply(7, 4), 0);
+        assert_eq!(Wrap::Mirror.apply(8, 4), 0);
+    }
+
+    #[test]
+    fn test_nearest() {
+        let texture = black_white();
+        let sampler = Sampler::new(Filter::Nearest, Wrap::Repeat);
+        assert_eq!(texture.sample(0.2, 0.5, &sampler).r.0, 0.0);
+        assert_eq!(texture.sample(0.7, 0.5, &sampler).r.0, 1.0);
+        // the next copy to the right starts black again
+        assert_eq!(texture.sample(1.2, 0.5, &sampler).r.0, 0.0);
+        let clamp = Sampler::new(Filter::Nearest, Wrap::Clamp);
+        assert_eq!(texture.sample(1.2, 0.5, &clamp).r.0, 1.0);
+        let mirror = Sampler::new(Filter::Nearest, Wrap::Mirror);
+        assert_eq!(texture.sample(1.2, 0.5, &mirror).r.0, 1.0);
+    }
+
+    #[test]
+    fn test_bilinear() {
+        let texture = black_white();
+        let clamp = Sampler::new(Filter::Bilinear, Wrap::Clamp);
+        // halfway between the two texel centers
+        assert_eq!(texture.sample(0.5, 0.5, &clamp).r.0, 0.5);
+        assert_eq!(texture.sample(0.375, 0.5, &clamp).r.0, 0.25);
+        // past the last center only the edge texel contributes
+        assert_eq!(texture.sample(0.9, 0.5, &clamp).r.0, 1.0);
+        // with repeat the right edge blends into the left texel of the next copy
+        let repeat = Sampler::new(Filter::Bilinear, Wrap::Repeat);
+        assert_eq!(texture.sample(1.0, 0.5, &repeat).r.0, 0.5);
+    }
+
+    #[test]
+    fn test_mip_chain() {
+        let texture = Texture::from_fn(4, 2, |_, _| Rgba::from(&Color::Red));
+        let sizes: Vec<(usize, usize)> = texture
+            .levels()
+            .iter()
+            .map(|level| (level.width(), level.height()))
+            .collect();
+        assert_eq!(sizes, vec![(4, 2), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn test_srgb_downsample() {
+        let texture = Texture::from_srgba8(2, 1, &[0, 0, 0, 255, 255, 255, 255, 255]);
+        // averaged as linear light, not as encoded bytes (which would be 128)
+        let gray = texture.levels()[1].texel(0, 0);
+        assert_eq!(gray.to_srgba8(), [188, 188, 188, 255]);
+    }
+
+    /// 2x2 checkerboard whose first mip level is middle gray.
+    fn checker() -> Texture {
+        Texture::from_fn(2, 2, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgba::from(&Color::White)
+            } else {
+                Rgba::from(&Color::Black)
+            }
+        })
+    }
+
+    #[test]
+    fn test_lod() {
+        let texture = checker();
+        let sampler = Sampler::trilinear(Wrap::Repeat);
+        // one pixel covers a quarter of a texel: full resolution
+        let magnified = texture.sample_grad(0.25, 0.25, [0.125, 0.0], [0.0, 0.125], &sampler);
+        assert_eq!(magnified.r.0, 1.0);
+        // one pixel covers two texels: the 1x1 level
+        let minified = texture.sample_grad(0.25, 0.25, [1.0, 0.0], [0.0, 1.0], &sampler);
+        assert_eq!(minified.r.0, 0.5);
+        // halfway between the levels
+        let between = texture.sample_lod(0.25, 0.25, 0.5, &sampler);
+        assert_eq!(between.r.0, 0.75);
+        let nearest = Sampler {
+            mip_filter: MipFilter::Nearest,
+            ..sampler
+        };
+        assert_eq!(texture.sample_lod(0.25, 0.25, 0.4, &nearest).r.0, 1.0);
+        let no_mips = Sampler {
+            mip_filter: MipFilter::None,
+            ..sampler
+        };
+        assert_eq!(texture.sample_lod(0.25, 0.25, 1.0, &no_mips).r.0, 1.0);
+    }
+
+    #[test]
+    fn test_anisotropic() {
+        // white top row, black bottom row
+        let stripes = Texture::from_fn(2, 2, |_, y| {
+            if y == 0 {
+                Rgba::from(&Color::White)
+            } else {
+                Rgba::from(&Color::Black)
+            }
+        });
+        // a footprint stretched along the stripes
+        let (ddx, ddy) = ([1.0, 0.0], [0.0, 0.25]);
+        let isotropic = Sampler::trilinear(Wrap::Clamp);
+        assert_eq!(
+            stripes.sample_grad(0.5, 0.25, ddx, ddy, &isotropic).r.0,
+            0.5
+        );
+        let anisotropic = Sampler::anisotropic(Wrap::Clamp, 4);
+        assert_eq!(
+            stripes.sample_grad(0.5, 0.25, ddx, ddy, &anisotropic).r.0,
+            1.0
+        );
+    }
+
+    /// Shows the texture coordinate derivatives, scaled by the frame size.
+    struct ShowDerivatives;
+
+    impl FragmentShader for ShowDerivatives {
+        fn fragment(&self, input: &FragmentInput, _uniforms: &Uniforms) -> Option<Rgba> {
+            let (ddx, ddy) = input.attribute_derivatives(&Attribute::TexCoord(0))?;
+            Some(Rgba::color(ddx[0] * 64.0, ddy[1] * 64.0, ddx[1] + ddy[0]))
+        }
+    }
+
+    #[test]
+    fn test_derivatives() {
+        let mut s = quad();
+        s.scale(nalgebra::matrix![32.0; 32.0; 0.0]);
+        s.translate(direction(32.0, 32.0, 0.0));
+        s.set_fragment_shader(Arc::new(ShowDerivatives));
+        let mut world = World::default();
+        world.insert(s);
+        let camera = Camera::new(0.0, 0.0, 64.0, 64.0, 0.0);
+        let frame = render_headless(&world, &camera, 64, 64, 0.0);
+        // the square spans the frame, so u and v change by 1/64 per pixel
+        for (x, y) in [(0, 0), (17, 40), (63, 63)] {
+            let color = frame.get(x, y).unwrap();
+            assert!(f32_equals(color.r.0, 1.0), "{color:?}");
+            assert!(f32_equals(color.g.0, 1.0), "{color:?}");
+            assert!(f32_equals(color.b.0, 0.0), "{color:?}");
+        }
+    }
+
+    #[test]
+    fn test_textured_square() {
+        let checker = Texture::from_fn(4, 4, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgba::from(&Color::White)
+            } else {
+                Rgba::from(&Color::Red)
+            }
+        });
+        let mut s = quad();
+        s.scale(nalgebra::matrix![32.0; 32.0; 0.0]);
+        s.translate(direction(32.0, 32.0, 0.0));
+        let shader = Arc::new(TextureShader::new(
+            Arc::new(checker),
+            Sampler::new(Filter::Nearest, Wrap::Repeat),
+        ));
+        s.set_vertex_shader(shader.clone());
+        s.set_fragment_shader(shader);
+        let mut world = World::default();
+        world.insert(s);
+        let camera = Camera::new(0.0, 0.0, 64.0, 64.0, 0.0);
+        let frame = render_headless(&world, &camera, 64, 64, 0.0);
+        // each texel covers a 16x16 block of the frame, top left is white
+        let white = Rgba::from(&Color::White);
+        let red = Rgba::from(&Color::Red);
+        assert_eq!(frame.get(8, 8), Some(&white));
+        assert_eq!(frame.get(24, 8), Some(&red));
+        assert_eq!(frame.get(8, 40), Some(&white));
+        assert_eq!(frame.get(56, 40), Some(&red));
+    }
+}
@@ -1,164 +1,859 @@
-use crate::geometry::{direction, point, Geometry, GeometryType, Point};
-use crate::math::{self, translation_matrix, z_rotation_matrix};
+use crate::clip::clip_geometry;
+use crate::geometry::{
+    direction, point, Direction, GeoError, Geometry, Point, ShadedGeometry, Transform,
+};
+use crate::math::{self, translation_matrix, Quaternion};
+use crate::shader::Uniforms;
+use nalgebra as na;
+use std::fmt;
 
+/// Objects arranged in a hierarchy: each object's model matrix is relative
+/// to its parent, so moving a parent moves all of its descendants.
+///
+/// Objects are stored in slots that are reused after removal. A `NodeId`
+/// carries the generation of its slot, so a handle to a removed object stays
+/// invalid even once the slot holds something else.
 #[derive(Default)]
 pub struct World {
-    objects: Vec<Geometry>,
+    slots: Vec<Slot>,
+    /// Empty slots, reused by `insert`.
+    free: Vec<usize>,
+    len: usize,
 }
 
+/// Refers to an object inserted into a `World`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+struct Slot {
+    /// Bumped every time the slot is emptied.
+    generation: u32,
+    node: Option<Node>,
+}
+
+struct Node {
+    object: Geometry,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorldError {
+    /// The node does not belong to this world.
+    UnknownNode(NodeId),
+    /// Making `parent` the parent of `node` would make a node its own
+    /// ancestor.
+    Cycle { node: NodeId, parent: NodeId },
+}
+
+impl fmt::Display for WorldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorldError::UnknownNode(node) => write!(f, "{node:?} is not part of this world"),
+            WorldError::Cycle { node, parent } => {
+                write!(
+                    f,
+                    "{parent:?} cannot become the parent of its ancestor {node:?}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for WorldError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Parallel projection of a `width` x `height` region of view space.
+    Orthographic { width: f32, height: f32 },
+    /// `fov_y` is the full vertical field of view in radians and `aspect` is
+    /// width / height.
+    Perspective { fov_y: f32, aspect: f32 },
+}
+
+/// View space has x pointing right, y pointing down (matching the screen) and
+/// the camera looking down -z. Yaw, pitch and roll are the rotations applied
+/// to the world around the view y, x and z axes, in that order.
+#[derive(Debug, Clone)]
 pub struct Camera {
-    x: f32,
-    y: f32,
-    height: f32,
-    width: f32,
-    angle: f32,
+    position: Point,
+    /// Camera to world rotation: takes the view axes to world space.
+    rotation: Quaternion,
+    projection: Projection,
+    near: f32,
+    far: f32,
 }
 
 impl World {
-    pub fn insert(&mut self, obj: Geometry) {
-        self.objects.push(obj);
+    /// Adds an object at the root of the hierarchy.
+    pub fn insert(&mut self, obj: Geometry) -> NodeId {
+        let node = Node {
+            object: obj,
+            parent: None,
+            children: Vec::new(),
+        };
+        self.len += 1;
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.node = Some(node);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: Some(node),
+                });
+                NodeId {
+                    index: self.slots.len() - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    /// Adds an object whose model matrix is relative to `parent`.
+    pub fn insert_child(&mut self, parent: NodeId, obj: Geometry) -> Result<NodeId, WorldError> {
+        self.check(parent)?;
+        let child = self.insert(obj);
+        self.set_parent(child, Some(parent))?;
+        Ok(child)
+    }
+
+    /// Takes an object out of the world. Its children are handed to its
+    /// parent, or become roots, keeping their own model matrices. Returns
+    /// `None` if the object was already removed.
+    pub fn remove(&mut self, node: NodeId) -> Option<Geometry> {
+        self.node(node)?;
+        let parent = self.parent(node);
+        self.set_parent(node, None).ok()?;
+        for child in self.children(node).to_vec() {
+            self.set_parent(child, parent).ok()?;
+        }
+        let slot = &mut self.slots[node.index];
+        slot.generation = slot.generation.wrapping_add(1);
+        let removed = slot.node.take()?;
+        self.free.push(node.index);
+        self.len -= 1;
+        Some(removed.object)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, node: NodeId) -> bool {
+        self.node(node).is_some()
+    }
+
+    pub fn get(&self, node: NodeId) -> Option<&Geometry> {
+        Some(&self.node(node)?.object)
+    }
+
+    pub fn get_mut(&mut self, node: NodeId) -> Option<&mut Geometry> {
+        Some(&mut self.node_mut(node)?.object)
+    }
+
+    /// Every object with its handle, in slot order. That is insertion order
+    /// until objects are removed, after which new objects fill the gaps.
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &Geometry)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let node = slot.node.as_ref()?;
+            let id = NodeId {
+                index,
+                generation: slot.generation,
+            };
+            Some((id, &node.object))
+        })
+    }
+
+    /// Like `iter`, allowing the objects to be changed in place.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (NodeId, &mut Geometry)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let id = NodeId {
+                    index,
+                    generation: slot.generation,
+                };
+                Some((id, &mut slot.node.as_mut()?.object))
+            })
+    }
+
+    /// The first object, in `iter` order, with the given name.
+    pub fn find_by_name(&self, name: &str) -> Option<NodeId> {
+        self.iter()
+            .find(|(_, obj)| obj.name() == Some(name))
+            .map(|(id, _)| id)
+    }
+
+    fn node(&self, id: NodeId) -> Option<&Node> {
+        let slot = self.slots.get(id.index)?;
+        match slot.generation == id.generation {
+            true => slot.node.as_ref(),
+            false => None,
+        }
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        let slot = self.slots.get_mut(id.index)?;
+        match slot.generation == id.generation {
+            true => slot.node.as_mut(),
+            false => None,
+        }
+    }
+
+    fn check(&self, node: NodeId) -> Result<(), WorldError> {
+        match self.contains(node) {
+            true => Ok(()),
+            false => Err(WorldError::UnknownNode(node)),
+        }
+    }
+
+    pub fn parent(&self, node: NodeId) -> Option<NodeId> {
+        self.node(node)?.parent
+    }
+
+    pub fn children(&self, node: NodeId) -> &[NodeId] {
+        self.node(node).map_or(&[], |node| node.children.as_slice())
+    }
+
+    /// Nodes without a parent, in `iter` order.
+    pub fn roots(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.iter()
+            .map(|(id, _)| id)
+            .filter(|&id| self.parent(id).is_none())
+    }
+
+    /// Moves `node`, along with its descendants, under `parent`, or to the
+    /// root with `None`. Its model matrix is kept, so it becomes relative to
+    /// the new parent.
+    pub fn set_parent(&mut self, node: NodeId, parent: Option<NodeId>) -> Result<(), WorldError> {
+        self.check(node)?;
+        if let Some(parent) = parent {
+            self.check(parent)?;
+            let mut ancestor = Some(parent);
+            while let Some(current) = ancestor {
+                if current == node {
+                    return Err(WorldError::Cycle { node, parent });
+                }
+                ancestor = self.parent(current);
+            }
+        }
+        if let Some(old) = self.parent(node) {
+            if let Some(old) = self.node_mut(old) {
+                old.children.retain(|&child| child != node);
+            }
+        }
+        if let Some(new) = parent.and_then(|parent| self.node_mut(parent)) {
+            new.children.push(node);
+        }
+        if let Some(node) = self.node_mut(node) {
+            node.parent = parent;
+        }
+        Ok(())
+    }
+
+    /// Visits `node` and its descendants depth first, parents before their
+    /// children, passing each node's depth below `node`.
+    pub fn traverse(&self, node: NodeId, mut visit: impl FnMut(NodeId, &Geometry, usize)) {
+        let mut stack = vec![(node, 0)];
+        while let Some((current, depth)) = stack.pop() {
+            let Some(current_node) = self.node(current) else {
+                continue;
+            };
+            visit(current, &current_node.object, depth);
+            // reversed so that children are visited in order
+            for &child in current_node.children.iter().rev() {
+                stack.push((child, depth + 1));
+            }
+        }
+    }
+
+    /// Local to world matrix of `node` at `time`: the animated model
+    /// matrices of its ancestors and itself, outermost first.
+    pub fn world_matrix(&self, node: NodeId, time: f32) -> Transform {
+        let mut matrix = Transform::identity();
+        let mut current = Some(node);
+        while let Some(node) = current {
+            let Some(obj) = self.get(node) else {
+                break;
+            };
+            matrix = obj.animated_model_matrix(time) * matrix;
+            current = self.parent(node);
+        }
+        matrix
+    }
+
+    /// `Geometry::local_to_world` for an object in the hierarchy, composing
+    /// its model matrix with those of its ancestors.
+    pub fn local_to_world(
+        &self,
+        node: NodeId,
+        time: f32,
+        camera_transform: Transform,
+    ) -> Option<Geometry> {
+        let parent = self
+            .parent(node)
+            .map_or_else(Transform::identity, |parent| {
+                self.world_matrix(parent, time)
+            });
+        Some(
+            self.get(node)?
+                .local_to_world(time, camera_transform * parent),
+        )
+    }
+
+    /// The world matrix of every object's parent at `time`, indexed by
+    /// slot, computing each ancestor's animation only once.
+    fn parent_matrices(&self, time: f32) -> Vec<Transform> {
+        let mut matrices = vec![Transform::identity(); self.slots.len()];
+        let mut stack: Vec<(NodeId, Transform)> = self
+            .roots()
+            .map(|root| (root, Transform::identity()))
+            .collect();
+        while let Some((node, parent)) = stack.pop() {
+            let Some(current) = self.node(node) else {
+                continue;
+            };
+            matrices[node.index] = parent;
+            let children = &current.children;
+            if !children.is_empty() {
+                let world = parent * current.object.animated_model_matrix(time);
+                stack.extend(children.iter().map(|&child| (child, world)));
+            }
+        }
+        matrices
     }
 }
 
 impl Camera {
+    /// 2D camera viewing the `width` x `height` rectangle whose top left corner
+    /// is at (x, y), rotated by `angle` around its center.
     pub fn new(x: f32, y: f32, width: f32, height: f32, angle: f32) -> Self {
-        // to-do: rethink this
-        //assert!(width >= 1.0 && height >= 1.0);
-        Self {
-            x,
-            y,
-            height,
+        let mut camera = Self::orthographic(
+            point(x + width / 2.0, y + height / 2.0, 0.0),
             width,
-            angle,
+            height,
+            -ORTHOGRAPHIC_DEPTH,
+            ORTHOGRAPHIC_DEPTH,
+        );
+        camera.set_orientation(0.0, 0.0, angle);
+        camera
+    }
+
+    pub fn orthographic(position: Point, width: f32, height: f32, near: f32, far: f32) -> Self {
+        Self {
+            position,
+            rotation: Quaternion::identity(),
+            projection: Projection::Orthographic { width, height },
+            near,
+            far,
+        }
+    }
+
+    /// `near` and `far` are distances along the view direction and must
+    /// satisfy 0 < near < far.
+    pub fn perspective(position: Point, fov_y: f32, aspect: f32, near: f32, far: f32) -> Self {
+        Self {
+            position,
+            rotation: Quaternion::identity(),
+            projection: Projection::Perspective { fov_y, aspect },
+            near,
+            far,
         }
     }
 
     pub fn position(&self) -> Point {
-        point(self.x, self.y, 0.0)
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: Point) {
+        self.position = position;
     }
 
     pub fn reposition(&mut self, x: f32, y: f32) {
-        self.x = x;
-        self.y = y;
+        self.position.x = x;
+        self.position.y = y;
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    pub fn near(&self) -> f32 {
+        self.near
+    }
+
+    pub fn far(&self) -> f32 {
+        self.far
+    }
+
+    pub fn set_clip_planes(&mut self, near: f32, far: f32) {
+        self.near = near;
+        self.far = far;
+    }
+
+    /// Yaw, pitch and roll of the camera. Looking straight up or down, only
+    /// yaw + roll is known and it is all given as yaw.
+    pub fn orientation(&self) -> (f32, f32, f32) {
+        // the view rotation is Rz(roll) * Rx(pitch) * Ry(yaw)
+        let view = self.rotation.inverse().to_rotation_matrix();
+        let view = view.matrix();
+        let pitch = view[(2, 1)].clamp(-1.0, 1.0).asin();
+        let (yaw, roll) = if pitch.cos() > 1e-6 {
+            (
+                (-view[(2, 0)]).atan2(view[(2, 2)]),
+                (-view[(0, 1)]).atan2(view[(1, 1)]),
+            )
+        } else {
+            (view[(0, 2)].atan2(view[(0, 0)]), 0.0)
+        };
+        (yaw, pitch, roll)
+    }
+
+    pub fn set_orientation(&mut self, yaw: f32, pitch: f32, roll: f32) {
+        let view = Quaternion::from_axis_angle(&na::Vector3::z_axis(), roll)
+            * Quaternion::from_axis_angle(&na::Vector3::x_axis(), pitch)
+            * Quaternion::from_axis_angle(&na::Vector3::y_axis(), yaw);
+        self.rotation = view.inverse();
+    }
+
+    /// Turns the camera from where it currently looks: yaw around the world
+    /// y axis, pitch around the camera's own x axis and roll around its view
+    /// direction. Without roll this matches adding to the angles of
+    /// `set_orientation`.
+    pub fn rotate(&mut self, yaw: f32, pitch: f32, roll: f32) {
+        let yaw = Quaternion::from_axis_angle(&na::Vector3::y_axis(), -yaw);
+        let local = Quaternion::from_axis_angle(&na::Vector3::x_axis(), -pitch)
+            * Quaternion::from_axis_angle(&na::Vector3::z_axis(), -roll);
+        self.rotation = yaw * self.rotation * local;
+        // keeps rounding errors from building up over many small turns
+        self.rotation.renormalize_fast();
+    }
+
+    /// Camera to world rotation, taking the view axes to world space.
+    pub fn rotation(&self) -> Quaternion {
+        self.rotation
+    }
+
+    pub fn set_rotation(&mut self, rotation: Quaternion) {
+        self.rotation = rotation;
+    }
+
+    /// Turns the camera towards `target`, keeping `up` pointing up on
+    /// screen as far as possible. Does nothing if `target` is the camera's
+    /// position or lies straight along `up`.
+    pub fn look_at(&mut self, target: Point, up: Direction) {
+        if let Some(rotation) = math::look_at_quaternion(target - self.position, up) {
+            self.rotation = rotation;
+        }
+    }
+
+    /// Places the camera with a camera to world matrix: the translation
+    /// becomes the position and the rotation, whose columns are the view
+    /// space axes in world space, the orientation. Scale is ignored.
+    pub fn set_transform(&mut self, camera_to_world: Transform) {
+        self.position = camera_to_world.column(3).into();
+        let mut rotation = camera_to_world.fixed_view::<3, 3>(0, 0).into_owned();
+        for mut column in rotation.column_iter_mut() {
+            column.normalize_mut();
+        }
+        self.rotation = Quaternion::from_matrix(&rotation);
     }
 
+    /// Rolls the camera around its view direction.
     pub fn add_rotation(&mut self, rotation: f32) {
-        self.angle += rotation;
+        self.rotate(0.0, 0.0, rotation);
     }
 
+    /// Moves the camera within its own view plane.
     pub fn translate(&mut self, x: f32, y: f32) {
-        let rotation_matrix = math::z_rotation_matrix(-self.angle);
-        let change = rotation_matrix * direction(x, y, 0.0);
-        self.x += change.x;
-        self.y += change.y;
+        self.translate_local(direction(x, y, 0.0));
+    }
+
+    /// Moves the camera along its own axes; -z moves forward.
+    pub fn translate_local(&mut self, dir: Direction) {
+        self.position += self.view_rotation().transpose() * dir;
+    }
+
+    fn view_rotation(&self) -> Transform {
+        self.rotation.inverse().to_homogeneous()
+    }
+
+    /// World space to view space.
+    pub fn view_matrix(&self) -> Transform {
+        let to_origin = direction(-self.position.x, -self.position.y, -self.position.z);
+        self.view_rotation() * translation_matrix(to_origin)
+    }
+
+    /// View space to clip space. Depth is reversed: after the homogeneous
+    /// divide the near plane maps to z = 1 and the far plane to z = 0, so a
+    /// greater depth is closer to the camera.
+    pub fn projection_matrix(&self) -> Transform {
+        let (n, f) = (self.near, self.far);
+        match self.projection {
+            Projection::Orthographic { width, height } => {
+                na::matrix![2.0 / width, 0.0, 0.0, 0.0;
+                            0.0, 2.0 / height, 0.0, 0.0;
+                            0.0, 0.0, 1.0 / (f - n), f / (f - n);
+                            0.0, 0.0, 0.0, 1.0]
+            }
+            Projection::Perspective { fov_y, aspect } => {
+                let focal = 1.0 / (fov_y / 2.0).tan();
+                na::matrix![focal / aspect, 0.0, 0.0, 0.0;
+                            0.0, focal, 0.0, 0.0;
+                            0.0, 0.0, n / (f - n), n * f / (f - n);
+                            0.0, 0.0, -1.0, 0.0]
+            }
+        }
+    }
+
+    pub fn view_projection(&self) -> Transform {
+        self.projection_matrix() * self.view_matrix()
     }
 
-    pub fn world_view(
+    /// Camera values shared by every object's shaders.
+    pub fn uniforms(&self) -> Uniforms {
+        Uniforms {
+            view: self.view_matrix(),
+            projection: self.projection_matrix(),
+            camera_position: self.position,
+            ..Uniforms::default()
+        }
+    }
+
+    /// Shades, culls and clips every object in the world, returning what is
+    /// in view in screen coordinates. Objects that cannot be drawn come back
+    /// as errors so the caller can report them without dropping the rest.
+    pub fn world_view<'a>(
         &self,
-        world: &World,
+        world: &'a World,
         target_width: f32,
         target_height: f32,
         time: f32,
-    ) -> Vec<Geometry> {
-        let direction_to_center = direction(
-            -(self.x + (self.width / 2.0)),
-            -(self.y + (self.height / 2.0)),
-            0.0,
-        );
-        let cam_center_translation = translation_matrix(direction_to_center);
-        let rotation = z_rotation_matrix(self.angle);
-        let undo_cam_center_translation = translation_matrix(-direction_to_center);
-        let translation_to_center = translation_matrix(direction(-self.x, -self.y, 0.0));
-        let final_transform =
-            translation_to_center * undo_cam_center_translation * rotation * cam_center_translation;
-        let mut in_view: Vec<Geometry> = world
-            .objects
+    ) -> Vec<Result<ShadedGeometry, GeoError<'a>>> {
+        let uniforms = self.uniforms();
+        let parents = world.parent_matrices(time);
+        world
             .iter()
-            .map(|x| x.local_to_world(time, final_transform))
-            .filter(|x| self.obj_view(x))
-            .collect();
-        for obj in &mut in_view {
-            // to-do: update in 3d
-            obj.camera_to_screen(self.width, self.height, target_width, target_height);
-        }
-        in_view
+            .map(|(id, x)| {
+                x.shade(
+                    time,
+                    Uniforms {
+                        model: parents[id.index],
+                        ..uniforms.clone()
+                    },
+                )
+            })
+            .filter(|x| !x.as_ref().is_ok_and(outside_frustum))
+            .filter_map(|x| match x {
+                Ok(shaded) => clip_geometry(shaded).map(Ok),
+                Err(error) => Some(Err(error)),
+            })
+            .map(|x| {
+                x.map(|mut shaded| {
+                    shaded.clip_to_screen(target_width, target_height);
+                    shaded
+                })
+            })
+            .collect()
     }
+}
 
-    fn obj_view(&self, obj: &Geometry) -> bool {
-        fn intercept(slope: f32, y_int: f32, y: f32, bound_min: f32, bound_max: f32) -> bool {
-            let x_input = (y - y_int) / slope;
-            x_input >= bound_min && x_input < bound_max
-        }
-        if self.vertex_in_bounds(obj) {
-            return true;
+/// Depth range of the 2D camera made by `Camera::new`, centered on z = 0.
+const ORTHOGRAPHIC_DEPTH: f32 = 10000.0;
+
+/// True when every vertex of a clip space object is on the outer side of the
+/// same frustum plane, so none of it can be visible.
+fn outside_frustum(obj: &ShadedGeometry) -> bool {
+    let planes: [fn(&Point) -> bool; 6] = [
+        |v| v.x < -v.w,
+        |v| v.x > v.w,
+        |v| v.y < -v.w,
+        |v| v.y > v.w,
+        |v| v.z < 0.0,
+        |v| v.z > v.w,
+    ];
+    planes
+        .iter()
+        .any(|outside| obj.vertices.iter().all(|v| outside(&v.position)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::triangle;
+    use crate::math::f32_equals;
+    use std::f32::consts::PI;
+
+    fn assert_point_eq(left: Point, right: Point) {
+        for i in 0..4 {
+            assert!(f32_equals(left[i], right[i]), "{left:?} != {right:?}");
         }
-        let vertex_count = obj.vertices.len();
-        for i in 0..vertex_count {
-            if i == vertex_count && obj.geo_type == GeometryType::Line {
-                break;
-            }
-            let j = if i == vertex_count - 1 { 0 } else { i + 1 };
-            let vertex1 = obj.vertex_locations[obj.vertices[i].index];
-            let vertex2 = obj.vertex_locations[obj.vertices[j].index];
-            let slope = (vertex2.y - vertex1.y) / (vertex2.x - vertex1.x);
-            // line is horizontal
-            if slope == 0.0 {
-                let y_int = vertex1.y;
-                if (self.y..=self.y + self.height).contains(&y_int) {
-                    return true;
-                }
-            // line is vertical
-            } else if slope.is_infinite() {
-                let x_int = vertex1.x;
-                if (self.x..=self.x + self.width).contains(&x_int) {
-                    return true;
-                }
-            // other types of lines
-            } else {
-                let y_int = vertex1.y - slope * vertex1.x;
-                let slope_flipped = 1.0 / slope;
-                let y_int_flipped = vertex1.x - slope_flipped * vertex1.y;
-                if intercept(slope, y_int, self.y, self.x, self.x + self.width)
-                    || intercept(
-                        slope,
-                        y_int,
-                        self.y + self.height,
-                        self.x,
-                        self.x + self.width,
-                    )
-                    || intercept(
-                        slope_flipped,
-                        y_int_flipped,
-                        self.x,
-                        self.y,
-                        self.y + self.height,
-                    )
-                    || intercept(
-                        slope_flipped,
-                        y_int_flipped,
-                        self.x + self.width,
-                        self.y,
-                        self.y + self.height,
-                    )
-                {
-                    return true;
-                }
+    }
+
+    #[test]
+    fn test_legacy_camera_maps_rectangle() {
+        let camera = Camera::new(-100.0, 50.0, 200.0, 100.0, 0.0);
+        let vp = camera.view_projection();
+        let top_left = vp * point(-100.0, 50.0, 0.0);
+        let bottom_right = vp * point(100.0, 150.0, 0.0);
+        assert!(f32_equals(top_left.x, -1.0) && f32_equals(top_left.y, -1.0));
+        assert!(f32_equals(bottom_right.x, 1.0) && f32_equals(bottom_right.y, 1.0));
+        // closer objects have a larger depth
+        assert!((vp * point(0.0, 0.0, 1.0)).z > (vp * point(0.0, 0.0, 0.0)).z);
+    }
+
+    #[test]
+    fn test_perspective_depth_range() {
+        let camera = Camera::perspective(point(0.0, 0.0, 0.0), PI / 2.0, 2.0, 1.0, 100.0);
+        let vp = camera.view_projection();
+        let near = vp * point(0.0, 0.0, -1.0);
+        let far = vp * point(0.0, 0.0, -100.0);
+        assert!(f32_equals(near.z / near.w, 1.0));
+        assert!(f32_equals(far.z / far.w, 0.0));
+        // 90 degree field of view: the frustum edge at distance d is d away
+        let edge = vp * point(4.0, 2.0, -2.0);
+        assert!(f32_equals(edge.x / edge.w, 1.0) && f32_equals(edge.y / edge.w, 1.0));
+    }
+
+    #[test]
+    fn test_camera_orientation() {
+        let mut camera = Camera::perspective(point(0.0, 0.0, 10.0), PI / 2.0, 1.0, 1.0, 100.0);
+        // a quarter turn of yaw looks down the x axis instead of -z
+        camera.set_orientation(PI / 2.0, 0.0, 0.0);
+        let view = camera.view_matrix();
+        assert_point_eq(view * point(5.0, 0.0, 10.0), point(0.0, 0.0, -5.0));
+        camera.translate_local(direction(0.0, 0.0, -5.0));
+        assert_point_eq(camera.position(), point(5.0, 0.0, 10.0));
+    }
+
+    #[test]
+    fn test_camera_quaternion_rotation() {
+        let mut camera = Camera::perspective(point(0.0, 0.0, 0.0), PI / 2.0, 1.0, 1.0, 100.0);
+        camera.look_at(point(10.0, 0.0, 0.0), direction(0.0, -1.0, 0.0));
+        assert_point_eq(
+            camera.view_matrix() * point(10.0, 0.0, 0.0),
+            point(0.0, 0.0, -10.0),
+        );
+        let (yaw, pitch, roll) = camera.orientation();
+        assert!(f32_equals(yaw, PI / 2.0) && f32_equals(pitch, 0.0) && f32_equals(roll, 0.0));
+
+        // pitching past straight up keeps turning instead of locking
+        camera.set_orientation(0.0, 0.0, 0.0);
+        camera.rotate(0.0, PI / 3.0, 0.0);
+        camera.rotate(0.0, PI / 3.0, 0.0);
+        let view = camera.view_matrix();
+        let ahead = view * point(0.0, -10.0, 5.0);
+        assert!(ahead.z < -9.0);
+        // with a roll, pitch turns around the camera's own x axis
+        camera.set_orientation(0.0, 0.0, PI / 2.0);
+        camera.rotate(0.0, PI / 2.0, 0.0);
+        let forward = camera.rotation() * na::Vector3::new(0.0, 0.0, -1.0);
+        assert!(
+            (forward - na::Vector3::new(-1.0, 0.0, 0.0)).amax() < 1e-5,
+            "{forward}"
+        );
+    }
+
+    #[test]
+    fn test_camera_set_transform() {
+        let mut camera = Camera::perspective(point(1.0, 2.0, 3.0), PI / 2.0, 1.0, 1.0, 100.0);
+        for (yaw, pitch, roll) in [(0.3, -0.4, 1.2), (-2.0, 0.1, -0.5), (0.7, PI / 2.0, 0.0)] {
+            camera.set_orientation(yaw, pitch, roll);
+            let camera_to_world = camera.view_matrix().try_inverse().unwrap();
+            let mut copy = Camera::perspective(point(0.0, 0.0, 0.0), PI / 2.0, 1.0, 1.0, 100.0);
+            copy.set_transform(camera_to_world);
+            assert_point_eq(copy.position(), camera.position());
+            let (expected, actual) = (camera.view_matrix(), copy.view_matrix());
+            for (e, a) in expected.iter().zip(actual.iter()) {
+                assert!((e - a).abs() < 1e-4, "{expected} != {actual}");
             }
         }
-        false
     }
 
-    fn vertex_in_bounds(&self, obj: &Geometry) -> bool {
-        let in_bounds = |x, y| {
-            let x_range = 0.0..self.width;
-            let y_range = 0.0..self.height;
-            x_range.contains(&x) && y_range.contains(&y)
-        };
-        for vec in &obj.vertex_locations {
-            if in_bounds(vec.x, vec.y) {
-                return true;
-            }
+    #[test]
+    fn test_world_view_culls_outside_frustum() {
+        let mut world = World::default();
+        let mut visible = triangle();
+        visible.translate(direction(0.0, 0.0, -10.0));
+        let mut behind = triangle();
+        behind.translate(direction(0.0, 0.0, 10.0));
+        world.insert(visible);
+        world.insert(behind);
+        let camera = Camera::perspective(point(0.0, 0.0, 0.0), PI / 2.0, 1.0, 1.0, 100.0);
+        let in_view = camera.world_view(&world, 100.0, 100.0, 0.0);
+        assert_eq!(in_view.len(), 1);
+        // (-1, -1, -10) is a tenth of the way from the center to the top left
+        let screen = in_view[0].as_ref().unwrap().vertices[0].position;
+        assert!(f32_equals(screen.x, 45.0) && f32_equals(screen.y, 45.0));
+        assert!(f32_equals(screen.w, 0.1));
+    }
+
+    #[test]
+    fn test_world_view_clips_camera_plane() {
+        let mut world = World::default();
+        let mut crossing = triangle();
+        crossing.scale(na::matrix![20.0; 20.0; 20.0]);
+        crossing.rotation(PI / 2.0, 0.0, 0.0);
+        world.insert(crossing);
+        let camera = Camera::perspective(point(0.0, 0.0, 0.0), PI / 2.0, 1.0, 1.0, 100.0);
+        let in_view = camera.world_view(&world, 100.0, 100.0, 0.0);
+        assert_eq!(in_view.len(), 1);
+        for v in in_view[0]
+            .as_ref()
+            .unwrap()
+            .vertices
+            .iter()
+            .map(|v| v.position)
+        {
+            assert!(v.w > 0.0);
+            assert!((-0.001..=100.001).contains(&v.x) && (-0.001..=100.001).contains(&v.y));
+            assert!((0.0..=1.0).contains(&v.z));
         }
-        false
+    }
+
+    #[test]
+    fn test_hierarchy_composes_transforms() {
+        let mut world = World::default();
+        let mut hull = triangle();
+        hull.set_name(Some("hull".to_string()));
+        hull.translate(direction(100.0, 0.0, 0.0));
+        hull.rotation(0.0, 0.0, PI / 2.0);
+        let hull = world.insert(hull);
+        let mut turret = triangle();
+        turret.set_name(Some("turret".to_string()));
+        turret.translate(direction(10.0, 0.0, 0.0));
+        turret.scale(na::matrix![2.0; 2.0; 2.0]);
+        let turret = world.insert_child(hull, turret).unwrap();
+        assert_eq!(world.find_by_name("turret"), Some(turret));
+        assert_eq!(world.parent(turret), Some(hull));
+        assert_eq!(world.children(hull), &[turret]);
+        // the turret's offset is rotated by the hull before moving with it
+        let origin = world.world_matrix(turret, 0.0) * point(1.0, 0.0, 0.0);
+        assert_point_eq(origin, point(100.0, 12.0, 0.0));
+        let moved = world
+            .local_to_world(turret, 0.0, Transform::identity())
+            .unwrap();
+        assert_point_eq(
+            moved.vertex_locations[0],
+            world.world_matrix(turret, 0.0) * triangle().vertex_locations[0],
+        );
+
+        world
+            .get_mut(hull)
+            .unwrap()
+            .translate(direction(0.0, 50.0, 0.0));
+        let origin = world.world_matrix(turret, 0.0) * point(1.0, 0.0, 0.0);
+        assert_point_eq(origin, point(100.0, 62.0, 0.0));
+
+        world.set_parent(turret, None).unwrap();
+        assert_eq!(world.roots().collect::<Vec<_>>(), vec![hull, turret]);
+        assert!(world.children(hull).is_empty());
+        let origin = world.world_matrix(turret, 0.0) * point(1.0, 0.0, 0.0);
+        assert_point_eq(origin, point(12.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_hierarchy_rejects_cycles() {
+        let mut world = World::default();
+        let a = world.insert(triangle());
+        let b = world.insert_child(a, triangle()).unwrap();
+        let c = world.insert_child(b, triangle()).unwrap();
+        assert_eq!(
+            world.set_parent(a, Some(c)),
+            Err(WorldError::Cycle { node: a, parent: c })
+        );
+        assert_eq!(
+            world.set_parent(a, Some(a)),
+            Err(WorldError::Cycle { node: a, parent: a })
+        );
+        assert_eq!(world.parent(a), None);
+        let mut visited = Vec::new();
+        world.traverse(a, |node, _, depth| visited.push((node, depth)));
+        assert_eq!(visited, vec![(a, 0), (b, 1), (c, 2)]);
+    }
+
+    #[test]
+    fn test_world_view_applies_parent_transform() {
+        let mut world = World::default();
+        let mut parent = triangle();
+        parent.translate(direction(500.0, 0.0, 0.0));
+        let parent = world.insert(parent);
+        let mut child = triangle();
+        child.scale(na::matrix![10.0; 10.0; 1.0]);
+        world.insert_child(parent, child).unwrap();
+        // the parent sits off screen, taking its child with it
+        let camera = Camera::new(0.0, 0.0, 100.0, 100.0, 0.0);
+        assert!(camera.world_view(&world, 100.0, 100.0, 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_handles() {
+        let mut world = World::default();
+        let names = ["a", "b", "c"].map(|name| {
+            let mut obj = triangle();
+            obj.set_name(Some(name.to_string()));
+            world.insert(obj)
+        });
+        let [a, b, c] = names;
+        assert_eq!(world.len(), 3);
+        assert_eq!(world.find_by_name("b"), Some(b));
+        assert_eq!(world.find_by_name("d"), None);
+        world
+            .get_mut(b)
+            .unwrap()
+            .translate(direction(1.0, 0.0, 0.0));
+        assert_point_eq(
+            world.get(b).unwrap().model_matrix() * point(0.0, 0.0, 0.0),
+            point(1.0, 0.0, 0.0),
+        );
+
+        let removed = world.remove(b).unwrap();
+        assert_eq!(removed.name(), Some("b"));
+        assert_eq!(world.len(), 2);
+        assert!(world.get(b).is_none() && world.remove(b).is_none());
+        assert_eq!(world.find_by_name("b"), None);
+        // the slot is reused, but the old handle does not see the new object
+        let d = world.insert(triangle());
+        assert_ne!(d, b);
+        assert!(world.get(b).is_none() && world.get(d).is_some());
+        assert_eq!(world.set_parent(b, None), Err(WorldError::UnknownNode(b)));
+        let ids: Vec<NodeId> = world.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![a, d, c]);
+        for (_, obj) in world.iter_mut() {
+            obj.set_name(None);
+        }
+        assert_eq!(world.find_by_name("a"), None);
+    }
+
+    #[test]
+    fn test_remove_keeps_children() {
+        let mut world = World::default();
+        let a = world.insert(triangle());
+        let b = world.insert_child(a, triangle()).unwrap();
+        let c = world.insert_child(b, triangle()).unwrap();
+        let d = world.insert_child(b, triangle()).unwrap();
+        world.remove(b).unwrap();
+        assert_eq!(world.children(a), &[c, d]);
+        assert_eq!(world.parent(c), Some(a));
+        world.remove(a).unwrap();
+        assert_eq!(world.roots().collect::<Vec<_>>(), vec![c, d]);
     }
 }